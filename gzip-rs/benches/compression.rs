@@ -0,0 +1,99 @@
+//! Throughput over the vendored corpus in `benches/corpus`: compression at
+//! every level, each half of the compressor on its own (the LZ77 parse and
+//! the Huffman coding of that parse), and inflate. Criterion reports MiB/s
+//! of uncompressed data; compression ratios are printed once up front.
+//!
+//!     cargo bench --bench compression
+//!     cargo bench --bench compression -- 'deflate/text'
+
+use std::fs;
+use std::hint::black_box;
+use std::path::Path;
+use std::time::Duration;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use gzip::entropy::{EntropyCoder, HuffmanCoder};
+use gzip::lz77::lz77_compress_with;
+use gzip::{deflate_with_level, inflate, CompressionLevel};
+
+const CORPUS: [&str; 4] = ["text.txt", "binary.bin", "repetitive.log", "compressed.gz"];
+
+fn corpus() -> Vec<(&'static str, Vec<u8>)> {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("benches/corpus");
+    CORPUS
+        .iter()
+        .map(|&name| {
+            let data = fs::read(dir.join(name)).unwrap_or_else(|err| panic!("{}: {}", name, err));
+            (name, data)
+        })
+        .collect()
+}
+
+fn levels() -> impl Iterator<Item = CompressionLevel> {
+    (0..=9).map(|level| CompressionLevel::new(level).unwrap())
+}
+
+/// Print compressed size as a percentage of the input for every file and
+/// level, so a ratio regression shows up next to the timings
+fn report_ratios(_: &mut Criterion) {
+    println!("\n{:<16}{}", "size %", levels().map(|level| format!("{:>7}", level.level())).collect::<String>());
+    for (name, data) in corpus() {
+        let sizes: String = levels()
+            .map(|level| format!("{:>7.2}", 100.0 * deflate_with_level(&data, level).len() as f64 / data.len() as f64))
+            .collect();
+        println!("{:<16}{}", name, sizes);
+    }
+    println!();
+}
+
+fn bench_deflate(c: &mut Criterion) {
+    for (name, data) in corpus() {
+        let mut group = c.benchmark_group(format!("deflate/{}", name));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        for level in levels() {
+            group.bench_with_input(BenchmarkId::from_parameter(level.level()), &data, |b, data| {
+                b.iter(|| deflate_with_level(black_box(data), level))
+            });
+        }
+        group.finish();
+    }
+}
+
+/// The two stages separately, at the default level, to tell which one a
+/// regression is in
+fn bench_stages(c: &mut Criterion) {
+    let config = CompressionLevel::DEFAULT.match_config();
+    for (name, data) in corpus() {
+        let mut group = c.benchmark_group(format!("stages/{}", name));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        group.bench_function("lz77", |b| b.iter(|| lz77_compress_with(black_box(&data), config)));
+
+        let tokens = lz77_compress_with(&data, config);
+        group.bench_function("huffman", |b| b.iter(|| HuffmanCoder.encode(black_box(&tokens))));
+        group.finish();
+    }
+}
+
+fn bench_inflate(c: &mut Criterion) {
+    for (name, data) in corpus() {
+        let mut group = c.benchmark_group(format!("inflate/{}", name));
+        group.throughput(Throughput::Bytes(data.len() as u64));
+        for level in [CompressionLevel::NONE, CompressionLevel::FASTEST, CompressionLevel::DEFAULT, CompressionLevel::BEST] {
+            let compressed = deflate_with_level(&data, level);
+            group.bench_with_input(BenchmarkId::from_parameter(level.level()), &compressed, |b, compressed| {
+                b.iter(|| inflate(black_box(compressed)).unwrap())
+            });
+        }
+        group.finish();
+    }
+}
+
+criterion_group! {
+    name = benches;
+    // Level 9 on the binary input is slow enough that the default 100
+    // samples take minutes; 20 is plenty to spot a regression
+    config = Criterion::default().sample_size(20).measurement_time(Duration::from_secs(3));
+    targets = report_ratios, bench_deflate, bench_stages, bench_inflate
+}
+criterion_main!(benches);
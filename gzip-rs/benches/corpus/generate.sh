@@ -0,0 +1,40 @@
+#!/bin/sh
+# Regenerate the benchmark corpus. Four kinds of input that stress the
+# compressor differently:
+#
+#   text.txt        English prose (licence texts, which permit verbatim copies)
+#   binary.bin      fixed-size records of integers, floats and flags
+#   repetitive.log  log lines from a handful of templates
+#   compressed.gz   gzip -9 output, which should come out no larger
+set -e
+cd "$(dirname "$0")"
+
+cat /usr/share/common-licenses/GPL-3 /usr/share/common-licenses/Apache-2.0 \
+    /usr/share/common-licenses/MPL-2.0 /usr/share/common-licenses/GFDL-1.3 > text.txt
+
+python3 - <<'PY'
+import math, random, struct
+rng = random.Random(20)
+
+records = []
+for i in range(4000):
+    value = 1000 * math.sin(i / 50) + rng.gauss(0, 3)
+    flags = rng.choice([0, 0, 0, 1, 4, 5])
+    name = rng.choice([b"sensor-a", b"sensor-b", b"pump-001", b"valve-17"])
+    records.append(struct.pack("<IdHxx8s", 1_600_000_000 + 15 * i, value, flags, name))
+open("binary.bin", "wb").write(b"".join(records))
+
+templates = [
+    "{t} INFO  request id={id} method=GET path=/api/v1/items status=200 ms={ms}\n",
+    "{t} INFO  request id={id} method=POST path=/api/v1/orders status=201 ms={ms}\n",
+    "{t} WARN  cache miss key=item:{id} backend=primary\n",
+    "{t} DEBUG pool connections=32 idle={idle} waiting=0\n",
+]
+lines = []
+for i in range(2500):
+    t = "2024-03-01T12:%02d:%02d.%03dZ" % (i // 3600 % 60, i // 60 % 60, i % 1000)
+    lines.append(rng.choice(templates).format(t=t, id=100000 + i, ms=rng.randrange(1, 40), idle=rng.randrange(32)))
+open("repetitive.log", "w").write("".join(lines))
+PY
+
+cat text.txt binary.bin | gzip -9 -n > compressed.gz
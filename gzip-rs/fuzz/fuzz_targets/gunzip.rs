@@ -0,0 +1,24 @@
+//! gzip: the one-shot and streaming decoders agree on every input,
+//! including where they stop at a limit
+
+#![no_main]
+
+use std::io::Read;
+
+use gzip::{gunzip_with_limits, GzDecoder, GzipError, Limits};
+use libfuzzer_sys::fuzz_target;
+
+const LIMITS: Limits = Limits { max_output: Some(1 << 24), max_ratio: None, max_members: Some(64) };
+
+fuzz_target!(|data: &[u8]| {
+    let one_shot = gunzip_with_limits(data, &LIMITS);
+
+    let mut streamed = Vec::new();
+    let streaming = GzDecoder::with_limits(data, LIMITS).read_to_end(&mut streamed).map_err(GzipError::from);
+
+    match (one_shot, streaming) {
+        (Ok(output), Ok(_)) => assert_eq!(output, streamed),
+        (Err(_), Err(_)) => {}
+        (one_shot, streaming) => panic!("decoders disagree: {:?} vs {:?}", one_shot.err(), streaming.err()),
+    }
+});
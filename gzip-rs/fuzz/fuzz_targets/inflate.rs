@@ -0,0 +1,21 @@
+//! Raw DEFLATE: any input either decodes or fails cleanly, and whatever
+//! decodes survives being compressed again
+
+#![no_main]
+
+use gzip::analyze::analyze_deflate;
+use gzip::{deflate, inflate};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let result = inflate(data);
+    // The analyzer walks the same bits and must agree on what they hold
+    let analyzed = analyze_deflate(data, false);
+    assert_eq!(result.is_ok(), analyzed.is_ok());
+
+    if let Ok(output) = result {
+        let blocks = analyzed.unwrap();
+        assert_eq!(blocks.iter().map(|block| block.output_len).sum::<usize>(), output.len());
+        assert_eq!(inflate(&deflate(&output)).unwrap(), output);
+    }
+});
@@ -0,0 +1,29 @@
+//! Compression: every level, the streaming encoder and the entropy
+//! coders reproduce their input exactly
+
+#![no_main]
+
+use std::io::Write;
+
+use gzip::entropy::{EntropyCoder, HuffmanCoder, Lz77Codec, RangeCoder};
+use gzip::{gunzip, gzip_with_level, CompressionLevel, GzEncoder};
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|input: &[u8]| {
+    let Some((&control, data)) = input.split_first() else {
+        return;
+    };
+    let level = CompressionLevel::new(control % 10).unwrap();
+    assert_eq!(gunzip(&gzip_with_level(data, level)).unwrap(), data);
+
+    // Split the input where the control byte says, flushing in between
+    let mut encoder = GzEncoder::new(Vec::new(), level);
+    let (head, tail) = data.split_at((control as usize * 37) % (data.len() + 1));
+    encoder.write_all(head).unwrap();
+    encoder.flush().unwrap();
+    encoder.write_all(tail).unwrap();
+    assert_eq!(gunzip(&encoder.finish().unwrap()).unwrap(), data);
+
+    let tokens = Lz77Codec::new(HuffmanCoder).parse(data);
+    assert_eq!(RangeCoder.decode(&RangeCoder.encode(&tokens)).unwrap(), tokens);
+});
@@ -0,0 +1,27 @@
+//! zlib: one-shot and streaming decoding agree, with and without a
+//! preset dictionary taken from the front of the input
+
+#![no_main]
+
+use std::io::Read;
+
+use gzip::zlib::zlib_decompress_with_dictionary;
+use gzip::{zlib_decompress_with_limits, Limits, ZlibDecoder};
+use libfuzzer_sys::fuzz_target;
+
+const LIMITS: Limits = Limits { max_output: Some(1 << 24), max_ratio: None, max_members: None };
+
+fuzz_target!(|data: &[u8]| {
+    let one_shot = zlib_decompress_with_limits(data, &LIMITS);
+    let mut streamed = Vec::new();
+    let streaming = ZlibDecoder::with_limits(data, LIMITS).read_to_end(&mut streamed);
+    assert_eq!(one_shot.is_ok(), streaming.is_ok());
+    if let Ok(output) = one_shot {
+        assert_eq!(output, streamed);
+    }
+
+    if let Some((&len, rest)) = data.split_first() {
+        let (dictionary, stream) = rest.split_at((len as usize).min(rest.len()));
+        let _ = zlib_decompress_with_dictionary(stream, dictionary);
+    }
+});
@@ -0,0 +1,71 @@
+/// Largest prime smaller than 65536 (RFC 1950, section 8.2)
+const MODULUS: u32 = 65521;
+
+/// Bytes that can be summed before the 32-bit sums risk overflowing, so the
+/// modulo only has to be taken once per run (the NMAX of zlib)
+const NMAX: usize = 5552;
+
+/// Running Adler-32 over data fed in pieces
+#[derive(Debug, Clone, Copy)]
+pub struct Adler32 {
+    a: u32,
+    b: u32,
+}
+
+impl Default for Adler32 {
+    fn default() -> Self {
+        Adler32 { a: 1, b: 0 }
+    }
+}
+
+impl Adler32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        for run in data.chunks(NMAX) {
+            for &byte in run {
+                self.a += byte as u32;
+                self.b += self.a;
+            }
+            self.a %= MODULUS;
+            self.b %= MODULUS;
+        }
+    }
+
+    pub fn value(&self) -> u32 {
+        (self.b << 16) | self.a
+    }
+}
+
+/// Adler-32 of a complete buffer
+pub fn adler32(data: &[u8]) -> u32 {
+    let mut adler = Adler32::new();
+    adler.update(data);
+    adler.value()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(adler32(b""), 1);
+        assert_eq!(adler32(b"Wikipedia"), 0x11e6_0398);
+        assert_eq!(adler32(b"123456789"), 0x091e_01de);
+        // Long runs of 0xff are the worst case for the deferred modulo
+        assert_eq!(adler32(&[0xff; 100_000]), 0x149a_302c);
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let data: Vec<u8> = (0..20_000u32).map(|i| (i * 7) as u8).collect();
+        let mut adler = Adler32::new();
+        for piece in data.chunks(777) {
+            adler.update(piece);
+        }
+        assert_eq!(adler.value(), adler32(&data));
+    }
+}
@@ -0,0 +1,503 @@
+//! Inspection of existing DEFLATE streams, for tuning the compressor.
+//!
+//! `analyze` walks a gzip file (or a raw DEFLATE stream) the way the
+//! decoder does, but instead of producing output it records what each
+//! block is made of: its type and code tables, how many literals and
+//! matches it holds, which match lengths and distances occur, and exactly
+//! how many bits each of those categories costs.
+
+use std::io::Read;
+
+use crate::bits::BitReader;
+use crate::deflate::{
+    fixed_distance_lengths, fixed_litlen_lengths, DISTANCE_BASE, DISTANCE_EXTRA, END_OF_BLOCK, LENGTH_BASE,
+    LENGTH_EXTRA, NUM_DISTANCE_SYMBOLS,
+};
+use crate::error::GzipError;
+use crate::gz::{GzHeader, MAGIC};
+use crate::inflate::{read_bits, read_code_lengths, Decoder, InflateError};
+use crate::lz77::{Token, LOOKAHEAD_SIZE};
+
+/// How a block is coded (RFC 1951, section 3.2.3)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlockKind {
+    Stored,
+    Fixed,
+    Dynamic,
+}
+
+impl BlockKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            BlockKind::Stored => "stored",
+            BlockKind::Fixed => "fixed",
+            BlockKind::Dynamic => "dynamic",
+        }
+    }
+}
+
+/// Where the bits of a block go
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BitCosts {
+    /// Block header: BTYPE and BFINAL, plus the code tables of a dynamic
+    /// block or the padding and LEN/NLEN of a stored one
+    pub header: usize,
+    pub literals: usize,
+    /// Length symbols, without their extra bits
+    pub lengths: usize,
+    pub length_extra: usize,
+    /// Distance symbols, without their extra bits
+    pub distances: usize,
+    pub distance_extra: usize,
+    pub end_of_block: usize,
+    /// Raw bytes of a stored block
+    pub stored: usize,
+}
+
+impl BitCosts {
+    pub fn total(&self) -> usize {
+        self.header
+            + self.literals
+            + self.lengths
+            + self.length_extra
+            + self.distances
+            + self.distance_extra
+            + self.end_of_block
+            + self.stored
+    }
+
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"header\":{},\"literals\":{},\"lengths\":{},\"length_extra\":{},\"distances\":{},\
+             \"distance_extra\":{},\"end_of_block\":{},\"stored\":{},\"total\":{}}}",
+            self.header,
+            self.literals,
+            self.lengths,
+            self.length_extra,
+            self.distances,
+            self.distance_extra,
+            self.end_of_block,
+            self.stored,
+            self.total()
+        )
+    }
+}
+
+/// What one block of a DEFLATE stream is made of
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BlockReport {
+    pub kind: BlockKind,
+    pub is_final: bool,
+    /// Position of the block header, in bits from the start of the stream
+    pub bit_offset: usize,
+    /// Uncompressed bytes the block produces
+    pub output_len: usize,
+    /// Code lengths of a dynamic block, indexed by symbol; empty otherwise
+    pub litlen_lengths: Vec<u8>,
+    pub distance_lengths: Vec<u8>,
+    pub literals: usize,
+    pub matches: usize,
+    /// Matches of each length, indexed by length (3..=258)
+    pub length_histogram: Vec<usize>,
+    /// Matches in each distance code (0..=29)
+    pub distance_histogram: [usize; NUM_DISTANCE_SYMBOLS],
+    pub costs: BitCosts,
+    /// The block's tokens, only kept when asked for
+    pub tokens: Vec<Token>,
+}
+
+/// One gzip member, or the whole input when it is a raw DEFLATE stream
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MemberReport {
+    /// The gzip header; `None` for a raw DEFLATE stream
+    pub header: Option<GzHeader>,
+    /// Byte offset of the member in the input
+    pub offset: usize,
+    /// Bytes of DEFLATE data, headers and trailer excluded
+    pub compressed_len: usize,
+    /// CRC-32 and ISIZE from the gzip trailer
+    pub trailer: Option<(u32, u32)>,
+    pub blocks: Vec<BlockReport>,
+}
+
+impl MemberReport {
+    /// Uncompressed bytes over all blocks
+    pub fn output_len(&self) -> usize {
+        self.blocks.iter().map(|block| block.output_len).sum()
+    }
+
+    pub fn to_json(&self) -> String {
+        let header = match &self.header {
+            None => "null".to_string(),
+            Some(header) => {
+                let text = |field: &Option<Vec<u8>>| {
+                    field.as_ref().map_or("null".to_string(), |bytes| json_string(&String::from_utf8_lossy(bytes)))
+                };
+                format!(
+                    "{{\"filename\":{},\"comment\":{},\"mtime\":{},\"os\":{},\"extra_flags\":{}}}",
+                    text(&header.filename),
+                    text(&header.comment),
+                    header.mtime,
+                    header.os,
+                    header.extra_flags
+                )
+            }
+        };
+        let trailer = match self.trailer {
+            None => "null".to_string(),
+            Some((crc, size)) => format!("{{\"crc32\":{},\"isize\":{}}}", crc, size),
+        };
+        let blocks: Vec<String> = self.blocks.iter().map(BlockReport::to_json).collect();
+        format!(
+            "{{\"offset\":{},\"header\":{},\"compressed_len\":{},\"output_len\":{},\"trailer\":{},\"blocks\":[{}]}}",
+            self.offset,
+            header,
+            self.compressed_len,
+            self.output_len(),
+            trailer,
+            blocks.join(",")
+        )
+    }
+}
+
+/// Decode one symbol, returning it and the number of bits its code took
+fn read_symbol<R: Read>(reader: &mut BitReader<R>, decoder: &Decoder) -> Result<(usize, usize), InflateError> {
+    let start = reader.bit_offset();
+    let symbol = decoder.decode(reader)? as usize;
+    Ok((symbol, reader.bit_offset() - start))
+}
+
+/// Walk the Huffman-coded body of a block, filling in `block`. `output` is
+/// the stream's output length so far, for validating distances.
+fn analyze_codes<R: Read>(
+    reader: &mut BitReader<R>,
+    litlen: &Decoder,
+    distance: &Decoder,
+    block: &mut BlockReport,
+    output: usize,
+    keep_tokens: bool,
+) -> Result<(), InflateError> {
+    loop {
+        let offset = reader.byte_offset();
+        let (symbol, bits) = read_symbol(reader, litlen)?;
+
+        if symbol < END_OF_BLOCK {
+            block.literals += 1;
+            block.costs.literals += bits;
+            block.output_len += 1;
+            if keep_tokens {
+                block.tokens.push(Token::Literal(symbol as u8));
+            }
+            continue;
+        }
+        if symbol == END_OF_BLOCK {
+            block.costs.end_of_block += bits;
+            return Ok(());
+        }
+
+        let len_idx = symbol - 257;
+        if len_idx >= LENGTH_BASE.len() {
+            return Err(InflateError::InvalidSymbol { offset });
+        }
+        let length = LENGTH_BASE[len_idx] as usize + read_bits(reader, LENGTH_EXTRA[len_idx] as u32)? as usize;
+        block.costs.lengths += bits;
+        block.costs.length_extra += LENGTH_EXTRA[len_idx] as usize;
+
+        let offset = reader.byte_offset();
+        let (dist_idx, bits) = read_symbol(reader, distance)?;
+        if dist_idx >= DISTANCE_BASE.len() {
+            return Err(InflateError::InvalidSymbol { offset });
+        }
+        let dist = DISTANCE_BASE[dist_idx] as usize + read_bits(reader, DISTANCE_EXTRA[dist_idx] as u32)? as usize;
+        block.costs.distances += bits;
+        block.costs.distance_extra += DISTANCE_EXTRA[dist_idx] as usize;
+
+        let available = output + block.output_len;
+        if dist > available {
+            return Err(InflateError::DistanceTooFar { offset, distance: dist, available });
+        }
+
+        block.matches += 1;
+        block.length_histogram[length] += 1;
+        block.distance_histogram[dist_idx] += 1;
+        block.output_len += length;
+        if keep_tokens {
+            block.tokens.push(Token::Reference { length, distance: dist });
+        }
+    }
+}
+
+/// Analyze the DEFLATE stream at the reader's position, block by block
+fn analyze_stream<R: Read>(
+    reader: &mut BitReader<R>,
+    keep_tokens: bool,
+) -> Result<Vec<BlockReport>, InflateError> {
+    let stream_start = reader.bit_offset();
+    let mut blocks = Vec::new();
+    let mut output = 0;
+
+    loop {
+        let bit_offset = reader.bit_offset();
+        let offset = reader.byte_offset();
+        let is_final = read_bits(reader, 1)? == 1;
+        let btype = read_bits(reader, 2)?;
+
+        let mut block = BlockReport {
+            kind: BlockKind::Stored,
+            is_final,
+            bit_offset: bit_offset - stream_start,
+            output_len: 0,
+            litlen_lengths: Vec::new(),
+            distance_lengths: Vec::new(),
+            literals: 0,
+            matches: 0,
+            length_histogram: vec![0; LOOKAHEAD_SIZE + 1],
+            distance_histogram: [0; NUM_DISTANCE_SYMBOLS],
+            costs: BitCosts::default(),
+            tokens: Vec::new(),
+        };
+
+        match btype {
+            0b00 => {
+                reader.align_to_byte();
+                let offset = reader.byte_offset();
+                let len = read_bits(reader, 16)?;
+                let nlen = read_bits(reader, 16)?;
+                if len != !nlen & 0xffff {
+                    return Err(InflateError::StoredLengthMismatch { offset });
+                }
+                block.costs.header = reader.bit_offset() - bit_offset;
+
+                let bytes = reader
+                    .read_bytes(len as usize)
+                    .ok_or(InflateError::UnexpectedEof { offset: reader.byte_offset() })?;
+                block.costs.stored = bytes.len() * 8;
+                block.output_len = bytes.len();
+                block.literals = bytes.len();
+                if keep_tokens {
+                    block.tokens.extend(bytes.into_iter().map(Token::Literal));
+                }
+            }
+            0b01 | 0b10 => {
+                let (litlen, distance) = if btype == 0b01 {
+                    block.kind = BlockKind::Fixed;
+                    (fixed_litlen_lengths(), fixed_distance_lengths())
+                } else {
+                    block.kind = BlockKind::Dynamic;
+                    read_code_lengths(reader)?
+                };
+                let invalid = InflateError::InvalidCodeLengths { offset };
+                let litlen_decoder = Decoder::from_lengths(&litlen, true).ok_or(invalid.clone())?;
+                let distance_decoder = Decoder::from_lengths(&distance, true).ok_or(invalid)?;
+                block.costs.header = reader.bit_offset() - bit_offset;
+                if block.kind == BlockKind::Dynamic {
+                    block.litlen_lengths = litlen;
+                    block.distance_lengths = distance;
+                }
+                analyze_codes(reader, &litlen_decoder, &distance_decoder, &mut block, output, keep_tokens)?;
+            }
+            _ => return Err(InflateError::InvalidBlockType { offset }),
+        }
+
+        output += block.output_len;
+        blocks.push(block);
+        if is_final {
+            return Ok(blocks);
+        }
+    }
+}
+
+/// Analyze a raw DEFLATE stream
+pub fn analyze_deflate(data: &[u8], keep_tokens: bool) -> Result<Vec<BlockReport>, GzipError> {
+    Ok(analyze_stream(&mut BitReader::new(data), keep_tokens)?)
+}
+
+/// Analyze every member of a gzip file, or `data` as a single raw DEFLATE
+/// stream if it does not start with the gzip magic bytes. With
+/// `keep_tokens`, each block also lists its literals and matches.
+pub fn analyze(data: &[u8], keep_tokens: bool) -> Result<Vec<MemberReport>, GzipError> {
+    if !data.starts_with(&MAGIC) {
+        let mut reader = BitReader::new(data);
+        let blocks = analyze_stream(&mut reader, keep_tokens)?;
+        reader.align_to_byte();
+        return Ok(vec![MemberReport {
+            header: None,
+            offset: 0,
+            compressed_len: reader.byte_offset(),
+            trailer: None,
+            blocks,
+        }]);
+    }
+
+    let mut reader = BitReader::new(data);
+    let mut members = Vec::new();
+    loop {
+        let offset = reader.byte_offset();
+        let header = GzHeader::read_from(&mut reader)?;
+        let body = reader.byte_offset();
+        let blocks = analyze_stream(&mut reader, keep_tokens)?;
+        reader.align_to_byte();
+        let compressed_len = reader.byte_offset() - body;
+
+        let trailer = reader
+            .read_bytes(8)
+            .ok_or(GzipError::TruncatedStream { offset: reader.byte_offset() })?;
+        let crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+        let size = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+        members.push(MemberReport { header: Some(header), offset, compressed_len, trailer: Some((crc, size)), blocks });
+
+        if reader.is_at_end() {
+            return Ok(members);
+        }
+    }
+}
+
+/// `value` as a JSON string literal
+fn json_string(value: &str) -> String {
+    let mut out = String::with_capacity(value.len() + 2);
+    out.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+fn json_array<T: ToString>(values: &[T]) -> String {
+    let items: Vec<String> = values.iter().map(T::to_string).collect();
+    format!("[{}]", items.join(","))
+}
+
+impl BlockReport {
+    pub fn to_json(&self) -> String {
+        // Only lengths that occur, keyed by length
+        let lengths: Vec<String> = (0..self.length_histogram.len())
+            .filter(|&length| self.length_histogram[length] > 0)
+            .map(|length| format!("\"{}\":{}", length, self.length_histogram[length]))
+            .collect();
+        let mut json = format!(
+            "{{\"type\":\"{}\",\"final\":{},\"bit_offset\":{},\"output_len\":{},\"literals\":{},\"matches\":{},\
+             \"bits\":{},\"litlen_lengths\":{},\"distance_lengths\":{},\"length_histogram\":{{{}}},\
+             \"distance_histogram\":{}",
+            self.kind.name(),
+            self.is_final,
+            self.bit_offset,
+            self.output_len,
+            self.literals,
+            self.matches,
+            self.costs.to_json(),
+            json_array(&self.litlen_lengths),
+            json_array(&self.distance_lengths),
+            lengths.join(","),
+            json_array(&self.distance_histogram),
+        );
+        if !self.tokens.is_empty() {
+            let tokens: Vec<String> = self
+                .tokens
+                .iter()
+                .map(|token| match *token {
+                    Token::Literal(byte) => byte.to_string(),
+                    Token::Reference { length, distance } => format!("[{},{}]", length, distance),
+                })
+                .collect();
+            json.push_str(&format!(",\"tokens\":[{}]", tokens.join(",")));
+        }
+        json.push('}');
+        json
+    }
+}
+
+/// A whole analysis as one JSON document, labelled with the input's name
+pub fn to_json(name: &str, members: &[MemberReport]) -> String {
+    let members: Vec<String> = members.iter().map(MemberReport::to_json).collect();
+    format!("{{\"file\":{},\"members\":[{}]}}", json_string(name), members.join(","))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::{deflate, deflate_with_level, CompressionLevel};
+    use crate::gz::gzip;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.txt");
+
+    #[test]
+    fn test_costs_add_up_to_stream_size() {
+        let deflated = deflate(SAMPLE);
+        let blocks = analyze_deflate(&deflated, false).unwrap();
+
+        let bits: usize = blocks.iter().map(|block| block.costs.total()).sum();
+        assert_eq!(bits.div_ceil(8), deflated.len());
+        assert_eq!(blocks.iter().map(|block| block.output_len).sum::<usize>(), SAMPLE.len());
+        assert!(blocks.last().unwrap().is_final);
+        for block in &blocks {
+            let matched: usize = (0..block.length_histogram.len()).map(|len| len * block.length_histogram[len]).sum();
+            assert_eq!(block.literals + matched, block.output_len);
+            assert_eq!(block.distance_histogram.iter().sum::<usize>(), block.matches);
+        }
+    }
+
+    #[test]
+    fn test_block_kinds() {
+        let stored = analyze_deflate(&deflate_with_level(SAMPLE, CompressionLevel::NONE), false).unwrap();
+        assert!(stored.iter().all(|block| block.kind == BlockKind::Stored));
+
+        let fixed = analyze_deflate(&deflate(b"abc"), false).unwrap();
+        assert_eq!(fixed[0].kind, BlockKind::Fixed);
+        assert_eq!(fixed[0].costs.header, 3);
+
+        let dynamic = analyze_deflate(&deflate(SAMPLE), false).unwrap();
+        assert_eq!(dynamic[0].kind, BlockKind::Dynamic);
+        assert!(dynamic[0].litlen_lengths.len() >= 257);
+    }
+
+    #[test]
+    fn test_tokens_reproduce_input() {
+        let data = b"Hello, World! Hello, World! This is a test. Hello, World!";
+        let members = analyze(&gzip(data), true).unwrap();
+        assert_eq!(members.len(), 1);
+        assert_eq!(members[0].trailer.unwrap().1, data.len() as u32);
+
+        let mut out: Vec<u8> = Vec::new();
+        for token in members[0].blocks.iter().flat_map(|block| &block.tokens) {
+            match *token {
+                Token::Literal(byte) => out.push(byte),
+                Token::Reference { length, distance } => {
+                    let start = out.len() - distance;
+                    for i in 0..length {
+                        out.push(out[start + i]);
+                    }
+                }
+            }
+        }
+        assert_eq!(out, data);
+    }
+
+    #[test]
+    fn test_multi_member_and_json() {
+        let members = analyze(include_bytes!("../tests/fixtures/multi.gz"), false).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].header.as_ref().unwrap().filename.as_deref(), Some(&b"hello.txt"[..]));
+
+        let json = members[0].to_json();
+        assert!(json.starts_with("{\"offset\":0,\"header\":{\"filename\":\"hello.txt\""), "{}", json);
+        assert_eq!(json.matches('{').count(), json.matches('}').count());
+        assert_eq!(json_string("a\"b\\\n\u{1}"), "\"a\\\"b\\\\\\n\\u0001\"");
+    }
+
+    #[test]
+    fn test_rejects_corrupt_streams() {
+        assert_eq!(analyze_deflate(&[0b111], false), Err(GzipError::BadBlockType { offset: 0 }));
+        let deflated = deflate(SAMPLE);
+        assert!(matches!(
+            analyze_deflate(&deflated[..deflated.len() / 2], false),
+            Err(GzipError::TruncatedStream { .. })
+        ));
+    }
+}
@@ -0,0 +1,312 @@
+/// Packs bits into bytes the way DEFLATE expects (RFC 1951, section 3.1.1):
+/// data elements fill each byte starting at the least-significant bit.
+#[derive(Debug, Default)]
+pub struct BitWriter {
+    out: Vec<u8>,
+    bit_buf: u64,
+    bit_count: u32,
+}
+
+impl BitWriter {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write the low `count` bits of `value`, least-significant bit first
+    pub fn write_bits(&mut self, value: u32, count: u32) {
+        debug_assert!(count <= 32);
+        debug_assert!(count == 32 || value >> count == 0);
+
+        self.bit_buf |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+
+        while self.bit_count >= 8 {
+            self.out.push(self.bit_buf as u8);
+            self.bit_buf >>= 8;
+            self.bit_count -= 8;
+        }
+    }
+
+    /// Write a Huffman code, which is packed starting with its most-significant bit
+    pub fn write_code(&mut self, code: u16, length: u8) {
+        self.write_bits(reverse_bits(code, length) as u32, length as u32);
+    }
+
+    /// Pad with zero bits up to the next byte boundary
+    pub fn align_to_byte(&mut self) {
+        if self.bit_count > 0 {
+            self.write_bits(0, 8 - self.bit_count);
+        }
+    }
+
+    /// Append whole bytes; the writer must be byte aligned
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        debug_assert_eq!(self.bit_count, 0, "write_bytes on an unaligned writer");
+        self.out.extend_from_slice(bytes);
+    }
+
+    /// Number of bits written so far and not yet taken
+    pub fn bit_len(&self) -> usize {
+        self.out.len() * 8 + self.bit_count as usize
+    }
+
+    /// Take the bytes completed so far, keeping any partial byte for later
+    pub fn take_bytes(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.out)
+    }
+
+    /// Flush any partial byte and return the packed output
+    pub fn finish(mut self) -> Vec<u8> {
+        self.align_to_byte();
+        self.out
+    }
+}
+
+/// Size of the buffer `BitReader` reads its input into
+const READ_BUFFER_SIZE: usize = 32 * 1024;
+
+/// Reads bits in the same order `BitWriter` packs them, pulling input from
+/// any `Read` through a fixed-size buffer.
+///
+/// Running out of input and I/O errors both look like end of input to the
+/// bit-level methods; `take_error` tells the two apart afterwards.
+#[derive(Debug)]
+pub struct BitReader<R> {
+    inner: R,
+    buf: Box<[u8]>,
+    start: usize,
+    end: usize,
+    /// Bytes moved out of `buf` so far, for reporting offsets
+    consumed: usize,
+    bit_buf: u64,
+    bit_count: u32,
+    eof: bool,
+    error: Option<std::io::Error>,
+}
+
+impl<R: std::io::Read> BitReader<R> {
+    pub fn new(inner: R) -> Self {
+        BitReader {
+            inner,
+            buf: vec![0; READ_BUFFER_SIZE].into_boxed_slice(),
+            start: 0,
+            end: 0,
+            consumed: 0,
+            bit_buf: 0,
+            bit_count: 0,
+            eof: false,
+            error: None,
+        }
+    }
+
+    /// A reader over input that starts `offset` bytes into a larger stream,
+    /// such as a file after a seek, so reported offsets count from the
+    /// start of the whole stream
+    pub fn starting_at(inner: R, offset: usize) -> Self {
+        BitReader { consumed: offset, ..Self::new(inner) }
+    }
+
+    /// Read the next chunk of input into the buffer; false at end of input
+    fn fill_buffer(&mut self) -> bool {
+        while self.start == self.end && !self.eof {
+            match self.inner.read(&mut self.buf) {
+                Ok(0) => self.eof = true,
+                Ok(n) => {
+                    self.start = 0;
+                    self.end = n;
+                }
+                Err(err) if err.kind() == std::io::ErrorKind::Interrupted => {}
+                Err(err) => {
+                    self.error = Some(err);
+                    self.eof = true;
+                }
+            }
+        }
+        self.start < self.end
+    }
+
+    /// Top up the bit buffer from the input, as far as it will go
+    fn refill(&mut self) {
+        while self.bit_count <= 56 && (self.start < self.end || self.fill_buffer()) {
+            self.bit_buf |= (self.buf[self.start] as u64) << self.bit_count;
+            self.start += 1;
+            self.consumed += 1;
+            self.bit_count += 8;
+        }
+    }
+
+    /// Look at the next `count` bits without consuming them. Bits past the
+    /// end of the input read as zero; check `buffered_bits` before trusting them.
+    pub fn peek_bits(&mut self, count: u32) -> u32 {
+        debug_assert!(count <= 32);
+        self.refill();
+        (self.bit_buf & ((1u64 << count) - 1)) as u32
+    }
+
+    /// Bits ready to consume; fewer than 57 only once the input is exhausted
+    pub fn buffered_bits(&mut self) -> u32 {
+        self.refill();
+        self.bit_count
+    }
+
+    /// Drop `count` bits previously returned by `peek_bits`
+    pub fn consume(&mut self, count: u32) {
+        debug_assert!(count <= self.bit_count);
+        self.bit_buf >>= count;
+        self.bit_count -= count;
+    }
+
+    /// Read `count` bits, least-significant bit first; `None` at end of input
+    pub fn read_bits(&mut self, count: u32) -> Option<u32> {
+        let value = self.peek_bits(count);
+        if self.bit_count < count {
+            return None;
+        }
+        self.consume(count);
+        Some(value)
+    }
+
+    /// Skip to the next byte boundary
+    pub fn align_to_byte(&mut self) {
+        self.consume(self.bit_count % 8);
+    }
+
+    /// Append up to `max` whole bytes to `out`, returning how many were
+    /// copied (0 only at end of input). The reader must be byte aligned.
+    pub fn read_aligned(&mut self, out: &mut Vec<u8>, max: usize) -> usize {
+        debug_assert_eq!(self.bit_count % 8, 0, "read_aligned on an unaligned reader");
+
+        // Bytes already in the bit buffer come first
+        let mut copied = 0;
+        while copied < max && self.bit_count > 0 {
+            out.push(self.bit_buf as u8);
+            self.consume(8);
+            copied += 1;
+        }
+
+        if copied < max && (self.start < self.end || self.fill_buffer()) {
+            let n = (max - copied).min(self.end - self.start);
+            out.extend_from_slice(&self.buf[self.start..self.start + n]);
+            self.start += n;
+            self.consumed += n;
+            copied += n;
+        }
+
+        copied
+    }
+
+    /// Read exactly `len` whole bytes, or `None` if the input ends first
+    pub fn read_bytes(&mut self, len: usize) -> Option<Vec<u8>> {
+        let mut out = Vec::with_capacity(len);
+        while out.len() < len {
+            let remaining = len - out.len();
+            if self.read_aligned(&mut out, remaining) == 0 {
+                return None;
+            }
+        }
+        Some(out)
+    }
+
+    /// True when whole bytes are ready without another read from the input
+    pub fn has_buffered_input(&self) -> bool {
+        self.bit_count >= 8 || self.start < self.end
+    }
+
+    /// True when every bit has been consumed and the input is exhausted
+    pub fn is_at_end(&mut self) -> bool {
+        self.buffered_bits() == 0
+    }
+
+    /// Offset of the byte holding the next unread bit
+    pub fn byte_offset(&self) -> usize {
+        self.consumed - (self.bit_count as usize).div_ceil(8)
+    }
+
+    /// Offset of the next unread bit, counting from the first bit of input
+    pub fn bit_offset(&self) -> usize {
+        self.consumed * 8 - self.bit_count as usize
+    }
+
+    /// Give back the underlying reader. Input already buffered is lost.
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// The I/O error that cut the input short, if any
+    pub fn take_error(&mut self) -> Option<std::io::Error> {
+        self.error.take()
+    }
+}
+
+/// Reverse the low `length` bits of `code`
+pub fn reverse_bits(code: u16, length: u8) -> u16 {
+    if length == 0 {
+        return 0;
+    }
+    code.reverse_bits() >> (16 - length)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bits_are_packed_lsb_first() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b1, 1);
+        writer.write_bits(0b01, 2);
+        writer.write_bits(0b11111, 5);
+        writer.write_bits(0b101, 3);
+
+        assert_eq!(writer.bit_len(), 11);
+        assert_eq!(writer.finish(), vec![0b1111_1011, 0b0000_0101]);
+    }
+
+    #[test]
+    fn test_codes_are_packed_msb_first() {
+        let mut writer = BitWriter::new();
+        writer.write_code(0b110, 3);
+
+        assert_eq!(writer.finish(), vec![0b0000_0011]);
+    }
+
+    #[test]
+    fn test_reader_round_trip() {
+        let mut writer = BitWriter::new();
+        writer.write_bits(0b101, 3);
+        writer.write_bits(0x1234, 16);
+        writer.align_to_byte();
+        writer.write_bytes(b"xyz");
+        let bytes = writer.finish();
+
+        let mut reader = BitReader::new(&bytes[..]);
+        assert_eq!(reader.read_bits(3), Some(0b101));
+        assert_eq!(reader.read_bits(16), Some(0x1234));
+        reader.align_to_byte();
+        assert_eq!(reader.byte_offset(), 3);
+        assert_eq!(reader.read_bytes(3), Some(b"xyz".to_vec()));
+        assert!(reader.is_at_end());
+        assert_eq!(reader.read_bits(1), None);
+    }
+
+    #[test]
+    fn test_reader_pulls_small_chunks() {
+        // A reader that hands out one byte per call still yields every bit
+        struct Trickle<'a>(&'a [u8]);
+        impl std::io::Read for Trickle<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                let n = self.0.len().min(buf.len()).min(1);
+                buf[..n].copy_from_slice(&self.0[..n]);
+                self.0 = &self.0[n..];
+                Ok(n)
+            }
+        }
+
+        let data: Vec<u8> = (0..100).collect();
+        let mut reader = BitReader::new(Trickle(&data));
+        assert_eq!(reader.read_bits(4), Some(0));
+        reader.align_to_byte();
+        assert_eq!(reader.read_bytes(99), Some(data[1..].to_vec()));
+        assert!(reader.is_at_end());
+    }
+}
@@ -0,0 +1,149 @@
+/// Reversed CRC-32 polynomial used by gzip, zip and PNG (RFC 1952, section 8)
+const POLYNOMIAL: u32 = 0xedb8_8320;
+
+/// Byte-at-a-time lookup table, built at compile time
+const TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 { POLYNOMIAL ^ (c >> 1) } else { c >> 1 };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+/// Running CRC-32 over data fed in pieces
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Crc32 {
+    crc: u32,
+}
+
+impl Crc32 {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Carry on from a CRC of earlier data whose value was `crc`
+    pub fn from_value(crc: u32) -> Self {
+        Crc32 { crc }
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        let mut c = !self.crc;
+        for &byte in data {
+            c = TABLE[((c ^ byte as u32) & 0xff) as usize] ^ (c >> 8);
+        }
+        self.crc = !c;
+    }
+
+    pub fn value(&self) -> u32 {
+        self.crc
+    }
+}
+
+/// CRC-32 of a complete buffer
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = Crc32::new();
+    crc.update(data);
+    crc.value()
+}
+
+/// Multiply the 32x32 bit matrix `matrix` by `vector` over GF(2)
+fn gf2_times(matrix: &[u32; 32], mut vector: u32) -> u32 {
+    let mut sum = 0;
+    let mut row = 0;
+    while vector != 0 {
+        if vector & 1 != 0 {
+            sum ^= matrix[row];
+        }
+        vector >>= 1;
+        row += 1;
+    }
+    sum
+}
+
+fn gf2_square(matrix: &[u32; 32]) -> [u32; 32] {
+    let mut square = [0; 32];
+    for (row, &value) in square.iter_mut().zip(matrix) {
+        *row = gf2_times(matrix, value);
+    }
+    square
+}
+
+/// CRC-32 of two buffers joined together, given the CRC of each and the
+/// length of the second, without touching the data (zlib's crc32_combine).
+/// Appending `len2` zero bits to the first CRC is a linear operator, built
+/// here by repeated squaring.
+pub fn crc32_combine(crc1: u32, crc2: u32, len2: u64) -> u32 {
+    if len2 == 0 {
+        return crc1;
+    }
+
+    // Operator for one zero bit: shift, folding in the polynomial
+    let mut odd = [0; 32];
+    odd[0] = POLYNOMIAL;
+    for (n, row) in odd.iter_mut().enumerate().skip(1) {
+        *row = 1 << (n - 1);
+    }
+    // Two, then four zero bits; the loop starts at one zero byte
+    let even = gf2_square(&odd);
+    let mut odd = gf2_square(&even);
+
+    let mut crc = crc1;
+    let mut len = len2;
+    loop {
+        let even = gf2_square(&odd);
+        if len & 1 != 0 {
+            crc = gf2_times(&even, crc);
+        }
+        len >>= 1;
+        if len == 0 {
+            break;
+        }
+        odd = gf2_square(&even);
+        if len & 1 != 0 {
+            crc = gf2_times(&odd, crc);
+        }
+        len >>= 1;
+        if len == 0 {
+            break;
+        }
+    }
+
+    crc ^ crc2
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_known_values() {
+        assert_eq!(crc32(b""), 0);
+        assert_eq!(crc32(b"123456789"), 0xcbf4_3926);
+        assert_eq!(crc32(b"The quick brown fox jumps over the lazy dog"), 0x414f_a339);
+    }
+
+    #[test]
+    fn test_incremental_update() {
+        let mut crc = Crc32::new();
+        crc.update(b"1234");
+        crc.update(b"56789");
+        assert_eq!(crc.value(), crc32(b"123456789"));
+    }
+
+    #[test]
+    fn test_combine() {
+        let data: Vec<u8> = (0..100_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect();
+        for split in [0, 1, 4096, 65_537, 100_000] {
+            let (left, right) = data.split_at(split);
+            assert_eq!(crc32_combine(crc32(left), crc32(right), right.len() as u64), crc32(&data));
+        }
+    }
+}
@@ -0,0 +1,806 @@
+use crate::bits::BitWriter;
+use crate::huffman::{canonical_codes, code_lengths};
+use crate::lz77::{lz77_compress_from, lz77_compress_with, MatchConfig, Token, LOOKAHEAD_SIZE, WINDOW_SIZE};
+use crate::optimal::optimal_parse;
+use crate::split::block_boundaries;
+
+/// Symbol that terminates every compressed block
+pub(crate) const END_OF_BLOCK: usize = 256;
+
+/// Literal/length alphabet: 256 literals, end-of-block and 29 length codes
+/// (286 and 287 take part in the fixed code but never occur in data)
+pub(crate) const NUM_LITLEN_SYMBOLS: usize = 288;
+pub(crate) const NUM_DISTANCE_SYMBOLS: usize = 30;
+pub(crate) const NUM_CODE_LENGTH_SYMBOLS: usize = 19;
+
+/// Longest code allowed for literal/length and distance codes
+pub(crate) const MAX_CODE_BITS: u8 = 15;
+/// Longest code allowed for the code length alphabet
+pub(crate) const MAX_CODE_LENGTH_BITS: u8 = 7;
+
+/// Largest payload of a single stored block (LEN is 16 bits)
+pub(crate) const MAX_STORED_BLOCK: usize = 65_535;
+
+/// Match finder settings per level, straight from zlib's configuration_table:
+/// (good_length, max_lazy, nice_length, max_chain). Levels 1-3 parse greedily,
+/// 4-9 lazily.
+const LEVEL_CONFIG: [(usize, usize, usize, usize); 10] = [
+    (0, 0, 0, 0),
+    (4, 4, 8, 4),
+    (4, 5, 16, 8),
+    (4, 6, 32, 32),
+    (4, 4, 16, 16),
+    (8, 16, 32, 32),
+    (8, 16, 128, 128),
+    (8, 32, 128, 256),
+    (32, 128, 258, 1024),
+    (32, 258, 258, 4096),
+];
+
+/// Speed/size trade-off, 0 (store only) through 9 (smallest output)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct CompressionLevel(u8);
+
+impl CompressionLevel {
+    /// No compression: the input is copied into stored blocks
+    pub const NONE: CompressionLevel = CompressionLevel(0);
+    pub const FASTEST: CompressionLevel = CompressionLevel(1);
+    pub const DEFAULT: CompressionLevel = CompressionLevel(6);
+    pub const BEST: CompressionLevel = CompressionLevel(9);
+
+    /// `None` unless `level` is in 0..=9
+    pub fn new(level: u8) -> Option<Self> {
+        (level <= 9).then_some(CompressionLevel(level))
+    }
+
+    pub fn level(self) -> u8 {
+        self.0
+    }
+
+    /// Match finder settings for this level (meaningless for level 0)
+    pub fn match_config(self) -> MatchConfig {
+        let (good_length, max_lazy, nice_length, max_chain) = LEVEL_CONFIG[self.0 as usize];
+        MatchConfig {
+            window_size: WINDOW_SIZE,
+            lookahead_size: LOOKAHEAD_SIZE,
+            max_chain,
+            nice_length,
+            lazy: self.0 >= 4,
+            max_lazy,
+            good_length,
+        }
+    }
+}
+
+impl Default for CompressionLevel {
+    fn default() -> Self {
+        CompressionLevel::DEFAULT
+    }
+}
+
+/// Base match length for length codes 257..=285 (RFC 1951, section 3.2.5)
+pub(crate) const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31,
+    35, 43, 51, 59, 67, 83, 99, 115, 131, 163, 195, 227, 258,
+];
+
+/// Extra bits following each length code
+pub(crate) const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2,
+    3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+
+/// Base distance for distance codes 0..=29
+pub(crate) const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193,
+    257, 385, 513, 769, 1025, 1537, 2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+
+/// Extra bits following each distance code
+pub(crate) const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6,
+    7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13, 13,
+];
+
+/// Index of the last table entry whose base is at most `value`
+const fn base_index(bases: &[u16], value: usize) -> u8 {
+    let mut index = 0;
+    while index + 1 < bases.len() && bases[index + 1] as usize <= value {
+        index += 1;
+    }
+    index as u8
+}
+
+/// LENGTH_BASE index for every match length (zlib's _length_code)
+const LENGTH_INDEX: [u8; 259] = {
+    let mut table = [0u8; 259];
+    let mut length = 3;
+    while length <= 258 {
+        table[length] = base_index(&LENGTH_BASE, length);
+        length += 1;
+    }
+    table
+};
+
+/// DISTANCE_BASE index, looked up by `distance - 1` for distances up to 256
+/// and by `256 + ((distance - 1) >> 7)` beyond, where every code covers a
+/// multiple of 128 distances (zlib's _dist_code)
+const DISTANCE_INDEX: [u8; 512] = {
+    let mut table = [0u8; 512];
+    let mut key = 0;
+    while key < 512 {
+        let distance = if key < 256 { key + 1 } else { ((key - 256) << 7) + 1 };
+        table[key] = base_index(&DISTANCE_BASE, distance);
+        key += 1;
+    }
+    table
+};
+
+/// Order in which code length code lengths are transmitted
+pub(crate) const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+/// A value coded as a Huffman symbol plus raw extra bits (RFC 1951, section 3.2.5)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Symbol {
+    pub symbol: u16,
+    pub extra_bits: u8,
+    pub extra: u16,
+}
+
+/// Literal/length symbol (257..=285) and extra bits for a match length in 3..=258
+pub fn length_symbol(length: usize) -> Symbol {
+    debug_assert!((3..=258).contains(&length));
+    let index = LENGTH_INDEX[length] as usize;
+    Symbol {
+        symbol: 257 + index as u16,
+        extra_bits: LENGTH_EXTRA[index],
+        extra: (length - LENGTH_BASE[index] as usize) as u16,
+    }
+}
+
+/// Distance symbol (0..=29) and extra bits for a match distance in 1..=32768
+pub fn distance_symbol(distance: usize) -> Symbol {
+    debug_assert!((1..=WINDOW_SIZE).contains(&distance));
+    let index = if distance <= 256 {
+        DISTANCE_INDEX[distance - 1]
+    } else {
+        DISTANCE_INDEX[256 + ((distance - 1) >> 7)]
+    } as usize;
+    Symbol {
+        symbol: index as u16,
+        extra_bits: DISTANCE_EXTRA[index],
+        extra: (distance - DISTANCE_BASE[index] as usize) as u16,
+    }
+}
+
+/// Code lengths of the fixed Huffman code (RFC 1951, section 3.2.6)
+pub(crate) fn fixed_litlen_lengths() -> Vec<u8> {
+    let mut lengths = vec![8u8; NUM_LITLEN_SYMBOLS];
+    lengths[144..256].fill(9);
+    lengths[256..280].fill(7);
+    lengths
+}
+
+/// Fixed distance codes are plain 5-bit numbers (30 and 31 are never used)
+pub(crate) fn fixed_distance_lengths() -> Vec<u8> {
+    vec![5u8; 32]
+}
+
+/// A Huffman code ready for writing: one (code, length) pair per symbol
+struct CodeTable {
+    codes: Vec<u16>,
+    lengths: Vec<u8>,
+}
+
+impl CodeTable {
+    fn from_lengths(lengths: Vec<u8>) -> Self {
+        Self { codes: canonical_codes(&lengths), lengths }
+    }
+
+    fn write(&self, writer: &mut BitWriter, symbol: usize) {
+        debug_assert!(self.lengths[symbol] > 0, "symbol {} has no code", symbol);
+        writer.write_code(self.codes[symbol], self.lengths[symbol]);
+    }
+
+    /// Write a symbol followed by its extra bits
+    fn write_symbol(&self, writer: &mut BitWriter, symbol: Symbol) {
+        self.write(writer, symbol.symbol as usize);
+        writer.write_bits(symbol.extra as u32, symbol.extra_bits as u32);
+    }
+
+    /// Bits needed to encode the given symbol frequencies with this table
+    fn cost(&self, frequencies: &[usize]) -> usize {
+        frequencies
+            .iter()
+            .zip(&self.lengths)
+            .map(|(&freq, &len)| freq * len as usize)
+            .sum()
+    }
+}
+
+/// Symbol statistics for the tokens of one block
+#[derive(Clone)]
+pub(crate) struct BlockStats {
+    pub(crate) litlen: [usize; NUM_LITLEN_SYMBOLS],
+    pub(crate) distance: [usize; NUM_DISTANCE_SYMBOLS],
+    /// Bits spent on length and distance extra bits, the same for every block type
+    pub(crate) extra_bits: usize,
+}
+
+impl BlockStats {
+    /// Statistics of no tokens at all, not even the end-of-block symbol
+    pub(crate) fn new() -> Self {
+        BlockStats {
+            litlen: [0; NUM_LITLEN_SYMBOLS],
+            distance: [0; NUM_DISTANCE_SYMBOLS],
+            extra_bits: 0,
+        }
+    }
+
+    pub(crate) fn add(&mut self, token: &Token) {
+        match *token {
+            Token::Literal(byte) => self.litlen[byte as usize] += 1,
+            Token::Reference { length, distance } => {
+                let length = length_symbol(length);
+                let distance = distance_symbol(distance);
+                self.litlen[length.symbol as usize] += 1;
+                self.distance[distance.symbol as usize] += 1;
+                self.extra_bits += (length.extra_bits + distance.extra_bits) as usize;
+            }
+        }
+    }
+
+    pub(crate) fn from_tokens(tokens: &[Token]) -> Self {
+        let mut stats = Self::new();
+        for token in tokens {
+            stats.add(token);
+        }
+        stats.litlen[END_OF_BLOCK] += 1;
+        stats
+    }
+}
+
+/// Code lengths for a dynamic block, with at least two codes present.
+///
+/// Decoders such as zlib reject incomplete code sets, so (like zlib's encoder)
+/// a lone symbol gets a partner with frequency 1 to keep the code complete.
+fn dynamic_lengths(frequencies: &[usize], max_bits: u8) -> Vec<u8> {
+    let mut frequencies = frequencies.to_vec();
+    let mut used = frequencies.iter().filter(|&&freq| freq > 0).count();
+
+    for freq in frequencies.iter_mut() {
+        if used >= 2 {
+            break;
+        }
+        if *freq == 0 {
+            *freq = 1;
+            used += 1;
+        }
+    }
+
+    code_lengths(&frequencies, max_bits)
+}
+
+/// Run-length encode code lengths with symbols 16, 17 and 18 (RFC 1951, section 3.2.7).
+/// Each item is (symbol, extra bit count, extra bit value).
+fn run_length_encode(lengths: &[u8]) -> Vec<(u8, u8, u8)> {
+    let mut encoded = Vec::new();
+    let mut i = 0;
+
+    while i < lengths.len() {
+        let len = lengths[i];
+        let run = lengths[i..].iter().take_while(|&&l| l == len).count();
+
+        if len == 0 && run >= 3 {
+            let run = run.min(138);
+            if run >= 11 {
+                encoded.push((18, 7, (run - 11) as u8));
+            } else {
+                encoded.push((17, 3, (run - 3) as u8));
+            }
+            i += run;
+        } else if len != 0 && run >= 4 {
+            // The first length is sent literally, the rest repeat it
+            encoded.push((len, 0, 0));
+            let repeat = (run - 1).min(6);
+            encoded.push((16, 2, (repeat - 3) as u8));
+            i += 1 + repeat;
+        } else {
+            encoded.push((len, 0, 0));
+            i += 1;
+        }
+    }
+
+    encoded
+}
+
+/// Everything needed to write the header and body of a dynamic block
+struct DynamicHeader {
+    litlen: CodeTable,
+    distance: CodeTable,
+    code_length: CodeTable,
+    num_litlen: usize,
+    num_distance: usize,
+    num_code_length: usize,
+    encoded_lengths: Vec<(u8, u8, u8)>,
+}
+
+impl DynamicHeader {
+    fn new(stats: &BlockStats) -> Self {
+        let litlen_lengths = dynamic_lengths(&stats.litlen[..286], MAX_CODE_BITS);
+        let distance_lengths = dynamic_lengths(&stats.distance, MAX_CODE_BITS);
+
+        let num_litlen = litlen_lengths.iter().rposition(|&len| len > 0).map_or(0, |i| i + 1).max(257);
+        let num_distance = distance_lengths.iter().rposition(|&len| len > 0).map_or(0, |i| i + 1).max(1);
+
+        let mut all_lengths = litlen_lengths[..num_litlen].to_vec();
+        all_lengths.extend_from_slice(&distance_lengths[..num_distance]);
+        let encoded_lengths = run_length_encode(&all_lengths);
+
+        let mut cl_freqs = [0usize; NUM_CODE_LENGTH_SYMBOLS];
+        for &(symbol, _, _) in &encoded_lengths {
+            cl_freqs[symbol as usize] += 1;
+        }
+        let cl_lengths = dynamic_lengths(&cl_freqs, MAX_CODE_LENGTH_BITS);
+        let num_code_length = CODE_LENGTH_ORDER
+            .iter()
+            .rposition(|&symbol| cl_lengths[symbol] > 0)
+            .map_or(0, |i| i + 1)
+            .max(4);
+
+        DynamicHeader {
+            litlen: CodeTable::from_lengths(litlen_lengths),
+            distance: CodeTable::from_lengths(distance_lengths),
+            code_length: CodeTable::from_lengths(cl_lengths),
+            num_litlen,
+            num_distance,
+            num_code_length,
+            encoded_lengths,
+        }
+    }
+
+    /// Size of the header alone, in bits (excluding the 3-bit block header)
+    fn cost(&self) -> usize {
+        let tables: usize = self
+            .encoded_lengths
+            .iter()
+            .map(|&(symbol, extra, _)| (self.code_length.lengths[symbol as usize] + extra) as usize)
+            .sum();
+        5 + 5 + 4 + 3 * self.num_code_length + tables
+    }
+
+    fn write(&self, writer: &mut BitWriter) {
+        writer.write_bits((self.num_litlen - 257) as u32, 5);
+        writer.write_bits((self.num_distance - 1) as u32, 5);
+        writer.write_bits((self.num_code_length - 4) as u32, 4);
+
+        for &symbol in &CODE_LENGTH_ORDER[..self.num_code_length] {
+            writer.write_bits(self.code_length.lengths[symbol] as u32, 3);
+        }
+
+        for &(symbol, extra, value) in &self.encoded_lengths {
+            self.code_length.write(writer, symbol as usize);
+            writer.write_bits(value as u32, extra as u32);
+        }
+    }
+}
+
+/// Bits taken by a stored block (or run of stored blocks) holding `len` bytes,
+/// starting at bit offset `bit_pos` in the output
+fn stored_cost(bit_pos: usize, len: usize) -> usize {
+    let blocks = len.div_ceil(MAX_STORED_BLOCK).max(1);
+    // The first header may leave us mid-byte; later headers always start aligned
+    let first_pad = (8 - (bit_pos + 3) % 8) % 8;
+    let later_pad = 5;
+    3 + first_pad + (blocks - 1) * (3 + later_pad) + blocks * 32 + len * 8
+}
+
+fn write_stored(writer: &mut BitWriter, bytes: &[u8], is_final: bool) {
+    let mut chunks: Vec<&[u8]> = bytes.chunks(MAX_STORED_BLOCK).collect();
+    if chunks.is_empty() {
+        chunks.push(&[]);
+    }
+
+    let last = chunks.len() - 1;
+    for (i, chunk) in chunks.into_iter().enumerate() {
+        writer.write_bits((is_final && i == last) as u32, 1);
+        writer.write_bits(0b00, 2);
+        writer.align_to_byte();
+
+        let len = chunk.len() as u16;
+        writer.write_bits(len as u32, 16);
+        writer.write_bits(!len as u32, 16);
+        writer.write_bytes(chunk);
+    }
+}
+
+fn write_tokens(writer: &mut BitWriter, tokens: &[Token], litlen: &CodeTable, distance: &CodeTable) {
+    for token in tokens {
+        match *token {
+            Token::Literal(byte) => litlen.write(writer, byte as usize),
+            Token::Reference { length, distance: dist } => {
+                litlen.write_symbol(writer, length_symbol(length));
+                distance.write_symbol(writer, distance_symbol(dist));
+            }
+        }
+    }
+    litlen.write(writer, END_OF_BLOCK);
+}
+
+/// The cheapest way to code a block
+enum BlockType {
+    Stored,
+    Fixed,
+    Dynamic(DynamicHeader),
+}
+
+/// Pick fixed or dynamic Huffman coding, whichever is smaller for a block
+/// with these statistics. Returns the type and its size in bits.
+fn choose_huffman_type(stats: &BlockStats) -> (BlockType, usize) {
+    let fixed_cost = 3
+        + CodeTable::from_lengths(fixed_litlen_lengths()).cost(&stats.litlen)
+        + CodeTable::from_lengths(fixed_distance_lengths()).cost(&stats.distance)
+        + stats.extra_bits;
+
+    let dynamic = DynamicHeader::new(stats);
+    let dynamic_cost = 3
+        + dynamic.cost()
+        + dynamic.litlen.cost(&stats.litlen)
+        + dynamic.distance.cost(&stats.distance)
+        + stats.extra_bits;
+
+    if fixed_cost <= dynamic_cost {
+        (BlockType::Fixed, fixed_cost)
+    } else {
+        (BlockType::Dynamic(dynamic), dynamic_cost)
+    }
+}
+
+/// Pick the block type with the smallest exact size for a block with these
+/// statistics covering `len` input bytes, starting at output bit `bit_pos`.
+/// Returns the type and its size in bits, block header included.
+fn choose_block_type(stats: &BlockStats, bit_pos: usize, len: usize) -> (BlockType, usize) {
+    let stored_cost = stored_cost(bit_pos, len);
+    let (huffman, huffman_cost) = choose_huffman_type(stats);
+
+    if stored_cost <= huffman_cost {
+        (BlockType::Stored, stored_cost)
+    } else {
+        (huffman, huffman_cost)
+    }
+}
+
+/// Exact size in bits of the best block for these statistics, taking the
+/// block to start on a byte boundary
+pub(crate) fn block_cost(stats: &BlockStats, len: usize) -> usize {
+    choose_block_type(stats, 0, len).1
+}
+
+/// Write one block, picking whichever of stored, fixed or dynamic Huffman
+/// coding is smallest. `bytes` is the uncompressed data the tokens describe.
+fn write_block(writer: &mut BitWriter, tokens: &[Token], bytes: &[u8], is_final: bool) {
+    let stats = BlockStats::from_tokens(tokens);
+    write_typed_block(writer, tokens, bytes, is_final, choose_block_type(&stats, writer.bit_len(), bytes.len()).0);
+}
+
+fn write_typed_block(writer: &mut BitWriter, tokens: &[Token], bytes: &[u8], is_final: bool, block_type: BlockType) {
+    match block_type {
+        BlockType::Stored => write_stored(writer, bytes, is_final),
+        BlockType::Fixed => {
+            writer.write_bits(is_final as u32, 1);
+            writer.write_bits(0b01, 2);
+            let litlen = CodeTable::from_lengths(fixed_litlen_lengths());
+            let distance = CodeTable::from_lengths(fixed_distance_lengths());
+            write_tokens(writer, tokens, &litlen, &distance);
+        }
+        BlockType::Dynamic(dynamic) => {
+            writer.write_bits(is_final as u32, 1);
+            writer.write_bits(0b10, 2);
+            dynamic.write(writer);
+            write_tokens(writer, tokens, &dynamic.litlen, &dynamic.distance);
+        }
+    }
+}
+
+/// Number of input bytes a token covers
+pub(crate) fn token_len(token: &Token) -> usize {
+    match *token {
+        Token::Literal(_) => 1,
+        Token::Reference { length, .. } => length,
+    }
+}
+
+/// Write `tokens` (the LZ77 parse of `data`) as a sequence of DEFLATE blocks,
+/// split wherever the statistics change enough to pay for a new header
+pub(crate) fn write_blocks(writer: &mut BitWriter, data: &[u8], tokens: &[Token], is_final: bool) {
+    if tokens.is_empty() {
+        write_block(writer, tokens, data, is_final);
+        return;
+    }
+
+    let ends = block_boundaries(tokens);
+    let (mut start, mut start_byte) = (0, 0);
+    for (i, &end) in ends.iter().enumerate() {
+        let block = &tokens[start..end];
+        let len: usize = block.iter().map(token_len).sum();
+        write_block(writer, block, &data[start_byte..start_byte + len], is_final && i + 1 == ends.len());
+        start = end;
+        start_byte += len;
+    }
+}
+
+/// Write `tokens` as a complete DEFLATE stream of Huffman-coded blocks
+/// only, for coding a parse without the bytes it came from. With `split`,
+/// blocks are split as in `write_blocks` and each uses fixed or dynamic
+/// codes, whichever is smaller; without it, one block with the fixed code.
+pub(crate) fn write_huffman_blocks(writer: &mut BitWriter, tokens: &[Token], split: bool) {
+    let ends = if split && !tokens.is_empty() { block_boundaries(tokens) } else { vec![tokens.len()] };
+    let mut start = 0;
+    for (i, &end) in ends.iter().enumerate() {
+        let block = &tokens[start..end];
+        let block_type = if split { choose_huffman_type(&BlockStats::from_tokens(block)).0 } else { BlockType::Fixed };
+        write_typed_block(writer, block, &[], i + 1 == ends.len(), block_type);
+        start = end;
+    }
+}
+
+/// Compress `data` into a raw DEFLATE stream (RFC 1951) at the default level
+pub fn deflate(data: &[u8]) -> Vec<u8> {
+    deflate_with_level(data, CompressionLevel::DEFAULT)
+}
+
+/// Compress `data` into a raw DEFLATE stream at the given level
+pub fn deflate_with_level(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+
+    if level == CompressionLevel::NONE {
+        write_stored(&mut writer, data, true);
+    } else {
+        let tokens = lz77_compress_with(data, level.match_config());
+        write_blocks(&mut writer, data, &tokens, true);
+    }
+
+    writer.finish()
+}
+
+/// Compress `data` into a raw DEFLATE stream as small as optimal parsing
+/// can make it, refining the cost model `iterations` times. Much slower
+/// than level 9; meant for data compressed once and decompressed often.
+pub fn deflate_ultra(data: &[u8], iterations: usize) -> Vec<u8> {
+    let mut writer = BitWriter::new();
+    let tokens = optimal_parse(data, 0, iterations);
+    write_blocks(&mut writer, data, &tokens, true);
+    writer.finish()
+}
+
+/// Compress `data` as a raw DEFLATE stream whose back-references may reach
+/// into `dictionary`; the decoder must be given the same dictionary
+pub fn deflate_with_dictionary(data: &[u8], level: CompressionLevel, dictionary: &[u8]) -> Vec<u8> {
+    let mut deflater = Deflater::with_dictionary(level, dictionary);
+    deflater.write(data);
+    deflater.finish();
+    deflater.take_output()
+}
+
+/// New input the streaming compressor collects before parsing it; together
+/// with the 32 KiB of history this bounds its buffer
+const STREAM_CHUNK_SIZE: usize = 128 * 1024;
+
+/// Incremental DEFLATE compression with bounded memory. Input is collected
+/// and parsed a chunk at a time, each chunk able to match against the last
+/// 32 KiB of what came before, so the stream is the same format `deflate`
+/// produces without holding the whole input.
+#[derive(Debug)]
+pub struct Deflater {
+    level: CompressionLevel,
+    /// History (at most `WINDOW_SIZE` bytes) followed by input not yet parsed
+    buffer: Vec<u8>,
+    /// Start of the unparsed input in `buffer`
+    pending: usize,
+    writer: BitWriter,
+    finished: bool,
+}
+
+impl Deflater {
+    pub fn new(level: CompressionLevel) -> Self {
+        Deflater {
+            level,
+            buffer: Vec::new(),
+            pending: 0,
+            writer: BitWriter::new(),
+            finished: false,
+        }
+    }
+
+    /// A compressor whose first input can refer back into `dictionary`, as with
+    /// a zlib preset dictionary. Only its last 32 KiB are reachable.
+    pub fn with_dictionary(level: CompressionLevel, dictionary: &[u8]) -> Self {
+        let history = &dictionary[dictionary.len().saturating_sub(WINDOW_SIZE)..];
+        Deflater {
+            buffer: history.to_vec(),
+            pending: history.len(),
+            ..Self::new(level)
+        }
+    }
+
+    /// Feed more input; compressed bytes become available through `take_output`
+    pub fn write(&mut self, mut data: &[u8]) {
+        assert!(!self.finished, "write after finish");
+
+        while !data.is_empty() {
+            let room = STREAM_CHUNK_SIZE - (self.buffer.len() - self.pending);
+            let n = room.min(data.len());
+            self.buffer.extend_from_slice(&data[..n]);
+            data = &data[n..];
+
+            if self.buffer.len() - self.pending == STREAM_CHUNK_SIZE {
+                self.compress_pending(false);
+            }
+        }
+    }
+
+    /// Sync flush: compress everything written so far and byte-align the
+    /// output with an empty stored block, so a decoder can reproduce all of
+    /// the input from the bytes produced up to this point
+    pub fn flush(&mut self) {
+        assert!(!self.finished, "flush after finish");
+
+        self.compress_pending(false);
+        write_stored(&mut self.writer, &[], false);
+    }
+
+    /// Compress the remaining input into the final block
+    pub fn finish(&mut self) {
+        if !self.finished {
+            self.compress_pending(true);
+            self.writer.align_to_byte();
+            self.finished = true;
+        }
+    }
+
+    /// Compressed bytes completed so far
+    pub fn take_output(&mut self) -> Vec<u8> {
+        self.writer.take_bytes()
+    }
+
+    fn compress_pending(&mut self, is_final: bool) {
+        if self.pending == self.buffer.len() && !is_final {
+            return;
+        }
+
+        let input = &self.buffer[self.pending..];
+        if self.level == CompressionLevel::NONE {
+            write_stored(&mut self.writer, input, is_final);
+        } else {
+            let tokens = lz77_compress_from(&self.buffer, self.pending, self.level.match_config());
+            write_blocks(&mut self.writer, input, &tokens, is_final);
+        }
+
+        // Slide: keep only the window later chunks may refer back to
+        let keep_from = self.buffer.len().saturating_sub(WINDOW_SIZE);
+        self.buffer.drain(..keep_from);
+        self.pending = self.buffer.len();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::values;
+
+    #[test]
+    fn test_symbol_tables() {
+        let length = |len| {
+            let Symbol { symbol, extra_bits, extra } = length_symbol(len);
+            (symbol, extra_bits, extra)
+        };
+        let distance = |dist| {
+            let Symbol { symbol, extra_bits, extra } = distance_symbol(dist);
+            (symbol, extra_bits, extra)
+        };
+
+        assert_eq!(length(3), (257, 0, 0));
+        assert_eq!(length(10), (264, 0, 0));
+        assert_eq!(length(11), (265, 1, 0));
+        assert_eq!(length(12), (265, 1, 1));
+        assert_eq!(length(257), (284, 5, 30));
+        assert_eq!(length(258), (285, 0, 0));
+        assert_eq!(distance(1), (0, 0, 0));
+        assert_eq!(distance(4), (3, 0, 0));
+        assert_eq!(distance(5), (4, 1, 0));
+        assert_eq!(distance(32_768), (29, 13, 8191));
+
+        // Every value lands in the range its code covers
+        for len in 3..=258 {
+            let Symbol { symbol, extra_bits, extra } = length_symbol(len);
+            let index = symbol as usize - 257;
+            assert_eq!(LENGTH_BASE[index] as usize + extra as usize, len);
+            assert!((extra as u32) < 1 << extra_bits);
+        }
+        for dist in 1..=WINDOW_SIZE {
+            let Symbol { symbol, extra_bits, extra } = distance_symbol(dist);
+            assert_eq!(DISTANCE_BASE[symbol as usize] as usize + extra as usize, dist);
+            assert!((extra as u32) < 1 << extra_bits);
+        }
+    }
+
+    #[test]
+    fn test_empty_input_is_a_fixed_block() {
+        // BFINAL=1, BTYPE=01, end-of-block (0000000)
+        assert_eq!(deflate(b""), vec![0x03, 0x00]);
+    }
+
+    #[test]
+    fn test_single_literal() {
+        // Same bytes zlib produces for raw deflate of "a"
+        assert_eq!(deflate(b"a"), vec![0x4b, 0x04, 0x00]);
+    }
+
+    #[test]
+    fn test_incompressible_data_is_stored() {
+        // A permutation of all byte values has no repeats to exploit
+        let data: Vec<u8> = (0..=255u8).map(|b| b.wrapping_mul(167)).collect();
+        let out = deflate(&data);
+
+        assert_eq!(out[0] & 0b111, 0b001);
+        assert_eq!(&out[1..5], &[0x00, 0x01, 0xff, 0xfe]);
+        assert_eq!(&out[5..], &data[..]);
+    }
+
+    #[test]
+    fn test_levels() {
+        let data = include_bytes!("../tests/fixtures/sample.txt");
+        let sizes: Vec<usize> = (0..=9)
+            .map(|level| deflate_with_level(data, CompressionLevel::new(level).unwrap()).len())
+            .collect();
+
+        // Level 0 is stored blocks only: 5 bytes of framing per block
+        assert_eq!(sizes[0], data.len() + 5);
+        assert!(sizes[1] < sizes[0] / 2);
+        assert!(sizes[1] > sizes[3] && sizes[3] > sizes[6]);
+        assert!(sizes[9] < sizes[3]);
+        assert_eq!(CompressionLevel::new(10), None);
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot_format() {
+        let data = include_bytes!("../tests/fixtures/sample.txt").repeat(20);
+        let mut deflater = Deflater::new(CompressionLevel::DEFAULT);
+        let mut out = Vec::new();
+
+        for piece in data.chunks(1000) {
+            deflater.write(piece);
+            out.extend(deflater.take_output());
+        }
+        deflater.finish();
+        out.extend(deflater.take_output());
+
+        assert_eq!(crate::inflate::inflate(&out).unwrap(), data);
+        // Chunked parsing loses little against the whole-buffer parse, where
+        // block splitting sees all of the input at once
+        assert!(out.len() < deflate(&data).len() * 102 / 100);
+    }
+
+    #[test]
+    fn test_sync_flush_ends_on_empty_stored_block() {
+        let mut deflater = Deflater::new(CompressionLevel::DEFAULT);
+        deflater.write(b"first part, ");
+        deflater.flush();
+        let flushed = deflater.take_output();
+
+        assert_eq!(&flushed[flushed.len() - 4..], &[0x00, 0x00, 0xff, 0xff]);
+    }
+
+    #[test]
+    fn test_skewed_text_uses_dynamic_block() {
+        // Few distinct letters with no long repeats: a tailored code beats the fixed one
+        let data: Vec<u8> = values(4096, 12_345).into_iter().map(|value| b"eeeeettaaoinsh "[value as usize % 15]).collect();
+        let out = deflate(&data);
+
+        // BTYPE of the first block (it may be split from the rest, so BFINAL varies)
+        assert_eq!(out[0] & 0b110, 0b100);
+        assert!(out.len() < data.len() / 2);
+    }
+}
@@ -0,0 +1,192 @@
+//! The entropy-coding half of an LZ77 compressor, made pluggable.
+//!
+//! An `EntropyCoder` turns a parse (the tokens from `lz77`) into bytes and
+//! back. The same parse can be handed to several coders to compare them:
+//! `HuffmanCoder` is what the DEFLATE compressor itself does,
+//! `StaticHuffmanCoder` uses DEFLATE's fixed code throughout, and
+//! `RangeCoder` adapts its probabilities token by token. `Lz77Codec` puts a
+//! match finder and a coder together into a complete compressor.
+//!
+//! Tokens must fit DEFLATE's limits: lengths 3..=258 and distances up to
+//! 32768, which is what every parser in this crate produces with the
+//! default window and lookahead.
+
+use crate::analyze::analyze_deflate;
+use crate::bits::BitWriter;
+use crate::deflate::{write_huffman_blocks, CompressionLevel};
+use crate::error::GzipError;
+use crate::lz77::{lz77_compress_with, lz77_decompress, MatchConfig, Token, LOOKAHEAD_SIZE, WINDOW_SIZE};
+
+pub use crate::range_coder::RangeCoder;
+
+/// Codes a sequence of LZ77 tokens
+pub trait EntropyCoder {
+    /// Short name for reports
+    fn name(&self) -> &'static str;
+
+    /// Code `tokens` into a self-contained byte stream. Every token must fit
+    /// DEFLATE's limits, as above; others may not survive the round trip.
+    fn encode(&self, tokens: &[Token]) -> Vec<u8>;
+
+    /// Recover the tokens from the output of `encode`
+    fn decode(&self, data: &[u8]) -> Result<Vec<Token>, GzipError>;
+}
+
+/// DEFLATE's own coding: blocks split where the statistics change, each
+/// with a code built for it unless the fixed code is smaller. The output
+/// is a raw DEFLATE stream that `inflate` can read.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct HuffmanCoder;
+
+impl EntropyCoder for HuffmanCoder {
+    fn name(&self) -> &'static str {
+        "huffman"
+    }
+
+    fn encode(&self, tokens: &[Token]) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        write_huffman_blocks(&mut writer, tokens, true);
+        writer.finish()
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<Token>, GzipError> {
+        deflate_tokens(data)
+    }
+}
+
+/// A single DEFLATE block with the fixed Huffman code: nothing is learnt
+/// from the data, so there are no tables to send
+#[derive(Debug, Clone, Copy, Default)]
+pub struct StaticHuffmanCoder;
+
+impl EntropyCoder for StaticHuffmanCoder {
+    fn name(&self) -> &'static str {
+        "static-huffman"
+    }
+
+    fn encode(&self, tokens: &[Token]) -> Vec<u8> {
+        let mut writer = BitWriter::new();
+        write_huffman_blocks(&mut writer, tokens, false);
+        writer.finish()
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<Token>, GzipError> {
+        deflate_tokens(data)
+    }
+}
+
+/// The tokens of a raw DEFLATE stream, in order
+fn deflate_tokens(data: &[u8]) -> Result<Vec<Token>, GzipError> {
+    let blocks = analyze_deflate(data, true)?;
+    Ok(blocks.into_iter().flat_map(|block| block.tokens).collect())
+}
+
+/// Size in bytes of `tokens` coded by each of `coders`, named
+pub fn compare(tokens: &[Token], coders: &[&dyn EntropyCoder]) -> Vec<(&'static str, usize)> {
+    coders.iter().map(|coder| (coder.name(), coder.encode(tokens).len())).collect()
+}
+
+/// An LZ77 compressor: a match finder followed by an entropy coder
+#[derive(Debug, Clone)]
+pub struct Lz77Codec<C> {
+    config: MatchConfig,
+    coder: C,
+}
+
+impl<C: EntropyCoder> Lz77Codec<C> {
+    /// Parse as the default compression level does
+    pub fn new(coder: C) -> Self {
+        Lz77Codec { config: CompressionLevel::DEFAULT.match_config(), coder }
+    }
+
+    /// Parse with these match finder settings, or `None` if `lookahead_size`
+    /// exceeds 258 or `window_size` 32768, since the coders cannot represent
+    /// longer or farther matches
+    pub fn with_config(coder: C, config: MatchConfig) -> Option<Self> {
+        (config.lookahead_size <= LOOKAHEAD_SIZE && config.window_size <= WINDOW_SIZE)
+            .then_some(Lz77Codec { config, coder })
+    }
+
+    pub fn coder(&self) -> &C {
+        &self.coder
+    }
+
+    /// The LZ77 parse of `data`, before entropy coding
+    pub fn parse(&self, data: &[u8]) -> Vec<Token> {
+        lz77_compress_with(data, self.config)
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Vec<u8> {
+        self.coder.encode(&self.parse(data))
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, GzipError> {
+        lz77_decompress(&self.coder.decode(data)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::deflate;
+    use crate::inflate::inflate;
+
+    const CODERS: [&dyn EntropyCoder; 3] = [&HuffmanCoder, &StaticHuffmanCoder, &RangeCoder];
+
+    #[test]
+    fn test_round_trip_every_coder() {
+        let sample = include_bytes!("../tests/fixtures/sample.txt");
+        let inputs: [&[u8]; 4] = [b"", b"a", sample, &[7u8; 100_000]];
+        for data in inputs {
+            let tokens = Lz77Codec::new(HuffmanCoder).parse(data);
+            for coder in CODERS {
+                let encoded = coder.encode(&tokens);
+                assert_eq!(coder.decode(&encoded).unwrap(), tokens, "{}", coder.name());
+            }
+        }
+    }
+
+    #[test]
+    fn test_huffman_coders_write_deflate() {
+        let data = include_bytes!("../tests/fixtures/sample.txt");
+        let codec = Lz77Codec::new(HuffmanCoder);
+        let encoded = codec.compress(data);
+        assert_eq!(inflate(&encoded).unwrap(), data);
+        // The same parse and block choices as the compressor, minus stored blocks
+        assert_eq!(encoded, deflate(data));
+
+        let codec = Lz77Codec::new(StaticHuffmanCoder);
+        assert_eq!(inflate(&codec.compress(data)).unwrap(), data);
+        assert_eq!(codec.decompress(&codec.compress(data)).unwrap(), data);
+    }
+
+    #[test]
+    fn test_compare_on_one_parse() {
+        let data = include_bytes!("../tests/fixtures/sample.txt").repeat(3);
+        let tokens = Lz77Codec::new(RangeCoder).parse(&data);
+        let sizes = compare(&tokens, &CODERS);
+
+        assert_eq!(sizes.iter().map(|&(name, _)| name).collect::<Vec<_>>(), ["huffman", "static-huffman", "range"]);
+        let size = |name| sizes.iter().find(|&&(n, _)| n == name).unwrap().1;
+        assert!(size("huffman") < size("static-huffman"));
+        assert!(size("range") < size("static-huffman"));
+    }
+
+    #[test]
+    fn test_rejects_long_matches() {
+        let config = MatchConfig { lookahead_size: 400, ..MatchConfig::default() };
+        assert!(Lz77Codec::with_config(RangeCoder, config).is_none());
+        let config = MatchConfig { window_size: 1 << 16, ..MatchConfig::default() };
+        assert!(Lz77Codec::with_config(RangeCoder, config).is_none());
+        assert!(Lz77Codec::with_config(RangeCoder, MatchConfig::default()).is_some());
+    }
+
+    #[test]
+    fn test_corrupt_input() {
+        let codec = Lz77Codec::new(RangeCoder);
+        let mut encoded = codec.compress(b"abcabcabcabc");
+        encoded[0] = 0xff;
+        assert!(codec.decompress(&encoded).is_err());
+        assert!(Lz77Codec::new(HuffmanCoder).decompress(&[0b111]).is_err());
+    }
+}
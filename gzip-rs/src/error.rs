@@ -0,0 +1,314 @@
+//! One error type for the whole crate. Internally each decoder reports
+//! failures in its own enum, which knows the exact fault; every public
+//! entry point converts those into `GzipError`, so callers match on one set
+//! of cases whichever format they decode. Streaming decoders return
+//! `io::Error` as `Read` requires, and `GzipError::from` recovers the
+//! `GzipError` inside.
+
+use std::fmt;
+use std::io;
+
+use crate::gz::DecodeError;
+use crate::inflate::InflateError;
+use crate::limits::LimitError;
+use crate::zlib::ZlibError;
+
+/// Why a gzip or zlib header was rejected
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HeaderError {
+    /// The gzip magic bytes 1f 8b are missing
+    InvalidMagic,
+    /// CM is something other than DEFLATE
+    UnsupportedMethod(u8),
+    /// One of the reserved gzip FLG bits is set
+    ReservedFlags,
+    /// The gzip FHCRC value does not match the header
+    HeaderCrcMismatch,
+    /// zlib CMF and FLG are not a multiple of 31
+    HeaderCheck,
+    /// zlib CINFO asks for a window of more than 2^15 bytes
+    InvalidWindowSize(u8),
+    /// The stream needs a preset dictionary with this Adler-32
+    DictionaryRequired(u32),
+    /// The supplied preset dictionary is not the one the stream was made with
+    DictionaryMismatch { expected: u32, actual: u32 },
+}
+
+impl fmt::Display for HeaderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HeaderError::InvalidMagic => f.write_str("not in gzip format"),
+            HeaderError::UnsupportedMethod(method) => write!(f, "unknown compression method {}", method),
+            HeaderError::ReservedFlags => f.write_str("reserved header flags set"),
+            HeaderError::HeaderCrcMismatch => f.write_str("header CRC mismatch"),
+            HeaderError::HeaderCheck => f.write_str("incorrect header check"),
+            HeaderError::InvalidWindowSize(bits) => write!(f, "invalid window size 2^{}", bits),
+            HeaderError::DictionaryRequired(id) => write!(f, "preset dictionary {:08x} required", id),
+            HeaderError::DictionaryMismatch { expected, actual } => {
+                write!(f, "wrong preset dictionary (expected {:08x}, got {:08x})", expected, actual)
+            }
+        }
+    }
+}
+
+/// Every way compressing or decompressing can fail. Offsets are byte
+/// positions in the compressed input.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GzipError {
+    /// There was nothing to compress
+    EmptyInput,
+    /// The gzip or zlib header is malformed or unsupported
+    InvalidHeader { offset: usize, reason: HeaderError },
+    /// A DEFLATE block has the reserved type 11
+    BadBlockType { offset: usize },
+    /// A dynamic block header describes an impossible Huffman code
+    InvalidCodeLengths { offset: usize },
+    /// The bits do not decode to a valid symbol
+    InvalidSymbol { offset: usize },
+    /// NLEN of a stored block is not the complement of LEN
+    StoredLengthMismatch { offset: usize },
+    /// A back-reference points before the start of the output
+    DistanceTooFar { offset: usize, distance: usize, available: usize },
+    /// The CRC-32 or Adler-32 in the trailer does not match the data
+    ChecksumMismatch { offset: usize, expected: u32, actual: u32 },
+    /// The gzip ISIZE does not match the decompressed length
+    LengthMismatch { offset: usize, expected: u32, actual: u32 },
+    /// The input ended before the stream did
+    TruncatedStream { offset: usize },
+    /// Decoding stopped at one of the caller's `Limits`
+    LimitExceeded(LimitError),
+    /// An LZ77 reference points before the start of the output; `index`
+    /// counts tokens, not bytes
+    InvalidToken { index: usize, distance: usize, available: usize },
+    /// A random-access index is malformed; the offset is into the index
+    InvalidIndex { offset: usize },
+    /// A random-access index was built for a file of another length
+    IndexMismatch { expected: u64, actual: u64 },
+    /// A `GzHeader` has no gzip encoding: its FEXTRA payload is over 65535
+    /// bytes, or its name or comment holds a zero byte
+    UnencodableHeader(&'static str),
+    /// Reading or writing failed
+    Io(io::ErrorKind, String),
+}
+
+impl GzipError {
+    /// Where in the input the problem was found, when that is known
+    pub fn offset(&self) -> Option<usize> {
+        match *self {
+            GzipError::InvalidHeader { offset, .. }
+            | GzipError::BadBlockType { offset }
+            | GzipError::InvalidCodeLengths { offset }
+            | GzipError::InvalidSymbol { offset }
+            | GzipError::StoredLengthMismatch { offset }
+            | GzipError::DistanceTooFar { offset, .. }
+            | GzipError::ChecksumMismatch { offset, .. }
+            | GzipError::LengthMismatch { offset, .. }
+            | GzipError::TruncatedStream { offset }
+            | GzipError::InvalidIndex { offset } => Some(offset),
+            GzipError::EmptyInput
+            | GzipError::LimitExceeded(_)
+            | GzipError::InvalidToken { .. }
+            | GzipError::IndexMismatch { .. }
+            | GzipError::UnencodableHeader(_)
+            | GzipError::Io(..) => None,
+        }
+    }
+}
+
+impl fmt::Display for GzipError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GzipError::EmptyInput => f.write_str("empty input"),
+            GzipError::InvalidHeader { offset, reason } => write!(f, "{} at byte {}", reason, offset),
+            GzipError::BadBlockType { offset } => write!(f, "invalid block type at byte {}", offset),
+            GzipError::InvalidCodeLengths { offset } => {
+                write!(f, "invalid Huffman code lengths at byte {}", offset)
+            }
+            GzipError::InvalidSymbol { offset } => write!(f, "invalid Huffman code at byte {}", offset),
+            GzipError::StoredLengthMismatch { offset } => {
+                write!(f, "stored block length does not match its complement at byte {}", offset)
+            }
+            GzipError::DistanceTooFar { offset, distance, available } => write!(
+                f,
+                "distance {} too far back at byte {} (only {} bytes available)",
+                distance, offset, available
+            ),
+            GzipError::ChecksumMismatch { offset, expected, actual } => write!(
+                f,
+                "checksum mismatch at byte {} (expected {:08x}, got {:08x})",
+                offset, expected, actual
+            ),
+            GzipError::LengthMismatch { offset, expected, actual } => write!(
+                f,
+                "length mismatch at byte {} (expected {}, got {})",
+                offset, expected, actual
+            ),
+            GzipError::TruncatedStream { offset } => write!(f, "unexpected end of input at byte {}", offset),
+            GzipError::LimitExceeded(err) => err.fmt(f),
+            GzipError::InvalidToken { index, distance, available } => write!(
+                f,
+                "distance {} too far back in token {} (only {} bytes available)",
+                distance, index, available
+            ),
+            GzipError::InvalidIndex { offset } => write!(f, "corrupt index at byte {}", offset),
+            GzipError::IndexMismatch { expected, actual } => write!(
+                f,
+                "index does not match the file (built for {} bytes, file has {})",
+                expected, actual
+            ),
+            GzipError::UnencodableHeader(reason) => write!(f, "cannot write gzip header: {}", reason),
+            GzipError::Io(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for GzipError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            GzipError::LimitExceeded(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<InflateError> for GzipError {
+    fn from(err: InflateError) -> Self {
+        match err {
+            InflateError::UnexpectedEof { offset } => GzipError::TruncatedStream { offset },
+            InflateError::InvalidBlockType { offset } => GzipError::BadBlockType { offset },
+            InflateError::StoredLengthMismatch { offset } => GzipError::StoredLengthMismatch { offset },
+            InflateError::InvalidCodeLengths { offset } => GzipError::InvalidCodeLengths { offset },
+            InflateError::InvalidSymbol { offset } => GzipError::InvalidSymbol { offset },
+            InflateError::DistanceTooFar { offset, distance, available } => {
+                GzipError::DistanceTooFar { offset, distance, available }
+            }
+        }
+    }
+}
+
+impl From<DecodeError> for GzipError {
+    fn from(err: DecodeError) -> Self {
+        let header = |offset, reason| GzipError::InvalidHeader { offset, reason };
+        match err {
+            DecodeError::UnexpectedEof { offset } => GzipError::TruncatedStream { offset },
+            DecodeError::InvalidMagic { offset } => header(offset, HeaderError::InvalidMagic),
+            DecodeError::UnsupportedMethod { offset, method } => {
+                header(offset, HeaderError::UnsupportedMethod(method))
+            }
+            DecodeError::ReservedFlags { offset } => header(offset, HeaderError::ReservedFlags),
+            DecodeError::HeaderCrcMismatch { offset } => header(offset, HeaderError::HeaderCrcMismatch),
+            DecodeError::CrcMismatch { offset, expected, actual } => {
+                GzipError::ChecksumMismatch { offset, expected, actual }
+            }
+            DecodeError::LengthMismatch { offset, expected, actual } => {
+                GzipError::LengthMismatch { offset, expected, actual }
+            }
+            DecodeError::Inflate(err) => err.into(),
+            DecodeError::Limit(err) => GzipError::LimitExceeded(err),
+            DecodeError::Io(kind, message) => GzipError::Io(kind, message),
+        }
+    }
+}
+
+impl From<ZlibError> for GzipError {
+    fn from(err: ZlibError) -> Self {
+        let header = |offset, reason| GzipError::InvalidHeader { offset, reason };
+        match err {
+            ZlibError::UnexpectedEof { offset } => GzipError::TruncatedStream { offset },
+            ZlibError::HeaderCheck { offset } => header(offset, HeaderError::HeaderCheck),
+            ZlibError::UnsupportedMethod { offset, method } => {
+                header(offset, HeaderError::UnsupportedMethod(method))
+            }
+            ZlibError::InvalidWindowSize { offset, window_bits } => {
+                header(offset, HeaderError::InvalidWindowSize(window_bits))
+            }
+            ZlibError::DictionaryRequired { offset, id } => header(offset, HeaderError::DictionaryRequired(id)),
+            ZlibError::DictionaryMismatch { offset, expected, actual } => {
+                header(offset, HeaderError::DictionaryMismatch { expected, actual })
+            }
+            ZlibError::ChecksumMismatch { offset, expected, actual } => {
+                GzipError::ChecksumMismatch { offset, expected, actual }
+            }
+            ZlibError::Inflate(err) => err.into(),
+            ZlibError::Limit(err) => GzipError::LimitExceeded(err),
+            ZlibError::Io(kind, message) => GzipError::Io(kind, message),
+        }
+    }
+}
+
+impl From<LimitError> for GzipError {
+    fn from(err: LimitError) -> Self {
+        GzipError::LimitExceeded(err)
+    }
+}
+
+impl From<io::Error> for GzipError {
+    fn from(err: io::Error) -> Self {
+        // Decoders wrap their own errors in io::Error for the Read trait;
+        // unwrap those rather than flattening them to a message
+        if let Some(inner) = err.get_ref() {
+            if let Some(err) = inner.downcast_ref::<DecodeError>() {
+                return err.clone().into();
+            }
+            if let Some(err) = inner.downcast_ref::<ZlibError>() {
+                return err.clone().into();
+            }
+        }
+        GzipError::Io(err.kind(), err.to_string())
+    }
+}
+
+impl From<GzipError> for io::Error {
+    fn from(err: GzipError) -> Self {
+        match err {
+            GzipError::Io(kind, message) => io::Error::new(kind, message),
+            GzipError::TruncatedStream { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gz::{gunzip, gzip, GzDecoder};
+    use crate::inflate::inflate;
+    use crate::zlib::zlib_decompress;
+    use std::io::Read;
+
+    #[test]
+    fn test_compress_empty_input() {
+        assert_eq!(crate::compress(b"").unwrap_err(), GzipError::EmptyInput);
+        assert!(crate::compress(b"a").is_ok());
+    }
+
+    #[test]
+    fn test_one_shot_decoders_report_gzip_errors() {
+        assert_eq!(
+            gunzip(b"PK\x03\x04......").unwrap_err(),
+            GzipError::InvalidHeader { offset: 0, reason: HeaderError::InvalidMagic }
+        );
+        assert_eq!(gunzip(b"\x1f\x8b").unwrap_err(), GzipError::TruncatedStream { offset: 2 });
+
+        let mut member = gzip(b"hello");
+        let len = member.len();
+        member[len - 8] ^= 1;
+        assert!(matches!(gunzip(&member), Err(GzipError::ChecksumMismatch { offset, .. }) if offset == len - 8));
+
+        assert_eq!(inflate(&[0b111]).unwrap_err(), GzipError::BadBlockType { offset: 0 });
+        assert_eq!(
+            zlib_decompress(&[0x78, 0x9d]).unwrap_err(),
+            GzipError::InvalidHeader { offset: 0, reason: HeaderError::HeaderCheck }
+        );
+    }
+
+    #[test]
+    fn test_unwraps_streaming_errors() {
+        let mut member = gzip(b"hello");
+        member[3] = 0xe0;
+        let err = GzDecoder::new(&member[..]).read_to_end(&mut Vec::new()).unwrap_err();
+        let err = GzipError::from(err);
+        assert_eq!(err, GzipError::InvalidHeader { offset: 3, reason: HeaderError::ReservedFlags });
+        assert_eq!(err.offset(), Some(3));
+    }
+}
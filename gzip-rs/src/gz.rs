@@ -0,0 +1,856 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::bits::BitReader;
+use crate::crc32::{crc32, Crc32};
+use crate::deflate::{deflate_ultra, deflate_with_level, CompressionLevel, Deflater};
+use crate::error::GzipError;
+use crate::inflate::{InflateError, Inflater};
+use crate::limits::{LimitError, Limits};
+
+/// Every gzip member starts with these two bytes
+pub const MAGIC: [u8; 2] = [0x1f, 0x8b];
+/// CM = 8 is the only compression method defined: DEFLATE
+pub const METHOD_DEFLATE: u8 = 8;
+
+const FTEXT: u8 = 0x01;
+const FHCRC: u8 = 0x02;
+const FEXTRA: u8 = 0x04;
+const FNAME: u8 = 0x08;
+const FCOMMENT: u8 = 0x10;
+const RESERVED_FLAGS: u8 = 0xe0;
+
+/// OS values from RFC 1952, section 2.3.1 (the common ones)
+pub const OS_FAT: u8 = 0;
+pub const OS_UNIX: u8 = 3;
+pub const OS_NTFS: u8 = 11;
+pub const OS_UNKNOWN: u8 = 255;
+
+/// Fields of a gzip member header (RFC 1952, section 2.3)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzHeader {
+    /// FTEXT: the data is probably ASCII text
+    pub text: bool,
+    /// Modification time of the original file, seconds since the Unix epoch (0 = none)
+    pub mtime: u32,
+    /// XFL: 2 = maximum compression, 4 = fastest
+    pub extra_flags: u8,
+    /// File system the member was created on
+    pub os: u8,
+    /// FEXTRA payload, at most 65535 bytes
+    pub extra: Option<Vec<u8>>,
+    /// Original file name, ISO 8859-1 without the terminating zero
+    pub filename: Option<Vec<u8>>,
+    /// Free-form comment, ISO 8859-1 without the terminating zero
+    pub comment: Option<Vec<u8>>,
+    /// FHCRC: protect the header with a CRC-16
+    pub header_crc: bool,
+}
+
+impl Default for GzHeader {
+    fn default() -> Self {
+        GzHeader {
+            text: false,
+            mtime: 0,
+            extra_flags: 0,
+            os: OS_UNKNOWN,
+            extra: None,
+            filename: None,
+            comment: None,
+            header_crc: false,
+        }
+    }
+}
+
+/// Reasons a gzip file can fail to decode. Offsets are byte positions in the input.
+/// Callers see these as `GzipError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum DecodeError {
+    /// The input ended inside a header or trailer
+    UnexpectedEof { offset: usize },
+    /// The member does not start with 1f 8b
+    InvalidMagic { offset: usize },
+    /// CM is something other than DEFLATE
+    UnsupportedMethod { offset: usize, method: u8 },
+    /// One of the reserved FLG bits is set
+    ReservedFlags { offset: usize },
+    /// The FHCRC value does not match the header
+    HeaderCrcMismatch { offset: usize },
+    /// The CRC-32 in the trailer does not match the decompressed data
+    CrcMismatch { offset: usize, expected: u32, actual: u32 },
+    /// ISIZE in the trailer does not match the decompressed length
+    LengthMismatch { offset: usize, expected: u32, actual: u32 },
+    /// The compressed data itself is corrupt
+    Inflate(InflateError),
+    /// Decoding stopped at one of the caller's `Limits`
+    Limit(LimitError),
+    /// Reading the input failed (streaming decoders only)
+    Io(io::ErrorKind, String),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DecodeError::UnexpectedEof { offset } => {
+                write!(f, "unexpected end of file at byte {}", offset)
+            }
+            DecodeError::InvalidMagic { offset } => {
+                write!(f, "not in gzip format at byte {}", offset)
+            }
+            DecodeError::UnsupportedMethod { offset, method } => {
+                write!(f, "unknown compression method {} at byte {}", method, offset)
+            }
+            DecodeError::ReservedFlags { offset } => {
+                write!(f, "reserved header flags set at byte {}", offset)
+            }
+            DecodeError::HeaderCrcMismatch { offset } => {
+                write!(f, "header CRC mismatch at byte {}", offset)
+            }
+            DecodeError::CrcMismatch { offset, expected, actual } => write!(
+                f,
+                "CRC-32 mismatch at byte {} (expected {:08x}, got {:08x})",
+                offset, expected, actual
+            ),
+            DecodeError::LengthMismatch { offset, expected, actual } => write!(
+                f,
+                "length mismatch at byte {} (expected {}, got {})",
+                offset, expected, actual
+            ),
+            DecodeError::Inflate(err) => err.fmt(f),
+            DecodeError::Limit(err) => err.fmt(f),
+            DecodeError::Io(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for DecodeError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DecodeError::Inflate(err) => Some(err),
+            DecodeError::Limit(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<DecodeError> for io::Error {
+    fn from(err: DecodeError) -> Self {
+        match err {
+            DecodeError::Io(kind, message) => io::Error::new(kind, message),
+            DecodeError::UnexpectedEof { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+impl From<InflateError> for DecodeError {
+    fn from(err: InflateError) -> Self {
+        DecodeError::Inflate(err)
+    }
+}
+
+impl From<LimitError> for DecodeError {
+    fn from(err: LimitError) -> Self {
+        DecodeError::Limit(err)
+    }
+}
+
+impl GzHeader {
+    /// Serialize the header. Fails with `GzipError::UnencodableHeader` if the
+    /// FEXTRA payload is over 65535 bytes or the name or comment contains a
+    /// zero byte, none of which the format can represent.
+    pub fn to_bytes(&self) -> Result<Vec<u8>, GzipError> {
+        if self.extra.as_ref().is_some_and(|extra| extra.len() > u16::MAX as usize) {
+            return Err(GzipError::UnencodableHeader("FEXTRA payload over 65535 bytes"));
+        }
+        if self.filename.as_ref().is_some_and(|name| name.contains(&0)) {
+            return Err(GzipError::UnencodableHeader("zero byte in file name"));
+        }
+        if self.comment.as_ref().is_some_and(|comment| comment.contains(&0)) {
+            return Err(GzipError::UnencodableHeader("zero byte in comment"));
+        }
+        Ok(self.encode())
+    }
+
+    /// `to_bytes` for a header already known to be valid
+    pub(crate) fn encode(&self) -> Vec<u8> {
+        let mut flags = 0;
+        if self.text {
+            flags |= FTEXT;
+        }
+        if self.header_crc {
+            flags |= FHCRC;
+        }
+        if self.extra.is_some() {
+            flags |= FEXTRA;
+        }
+        if self.filename.is_some() {
+            flags |= FNAME;
+        }
+        if self.comment.is_some() {
+            flags |= FCOMMENT;
+        }
+
+        let mut out = Vec::with_capacity(10);
+        out.extend_from_slice(&MAGIC);
+        out.push(METHOD_DEFLATE);
+        out.push(flags);
+        out.extend_from_slice(&self.mtime.to_le_bytes());
+        out.push(self.extra_flags);
+        out.push(self.os);
+
+        if let Some(extra) = &self.extra {
+            out.extend_from_slice(&(extra.len() as u16).to_le_bytes());
+            out.extend_from_slice(extra);
+        }
+        for field in [&self.filename, &self.comment].into_iter().flatten() {
+            out.extend_from_slice(field);
+            out.push(0);
+        }
+        if self.header_crc {
+            let crc16 = crc32(&out) as u16;
+            out.extend_from_slice(&crc16.to_le_bytes());
+        }
+
+        out
+    }
+
+    /// Read a header from `reader`, which must be positioned at a member's first byte
+    pub(crate) fn read_from<R: Read>(reader: &mut BitReader<R>) -> Result<GzHeader, DecodeError> {
+        let start = reader.byte_offset();
+        // Everything read is kept for the FHCRC check
+        let mut raw = Vec::with_capacity(10);
+
+        take(reader, &mut raw, 10)?;
+        if raw[..2] != MAGIC {
+            return Err(DecodeError::InvalidMagic { offset: start });
+        }
+        if raw[2] != METHOD_DEFLATE {
+            return Err(DecodeError::UnsupportedMethod { offset: start + 2, method: raw[2] });
+        }
+        let flags = raw[3];
+        if flags & RESERVED_FLAGS != 0 {
+            return Err(DecodeError::ReservedFlags { offset: start + 3 });
+        }
+
+        let mut header = GzHeader {
+            text: flags & FTEXT != 0,
+            mtime: u32::from_le_bytes([raw[4], raw[5], raw[6], raw[7]]),
+            extra_flags: raw[8],
+            os: raw[9],
+            header_crc: flags & FHCRC != 0,
+            ..GzHeader::default()
+        };
+
+        if flags & FEXTRA != 0 {
+            let xlen = take(reader, &mut raw, 2)?;
+            let xlen = u16::from_le_bytes([xlen[0], xlen[1]]) as usize;
+            header.extra = Some(take(reader, &mut raw, xlen)?);
+        }
+        if flags & FNAME != 0 {
+            header.filename = Some(take_zero_terminated(reader, &mut raw)?);
+        }
+        if flags & FCOMMENT != 0 {
+            header.comment = Some(take_zero_terminated(reader, &mut raw)?);
+        }
+        if flags & FHCRC != 0 {
+            let expected = crc32(&raw) as u16;
+            let offset = reader.byte_offset();
+            let stored = take(reader, &mut raw, 2)?;
+            if u16::from_le_bytes([stored[0], stored[1]]) != expected {
+                return Err(DecodeError::HeaderCrcMismatch { offset });
+            }
+        }
+
+        Ok(header)
+    }
+}
+
+/// Read `len` bytes, also appending them to `raw`
+fn take<R: Read>(
+    reader: &mut BitReader<R>,
+    raw: &mut Vec<u8>,
+    len: usize,
+) -> Result<Vec<u8>, DecodeError> {
+    let bytes = reader.read_bytes(len).ok_or_else(|| eof_error(reader))?;
+    raw.extend_from_slice(&bytes);
+    Ok(bytes)
+}
+
+fn take_zero_terminated<R: Read>(
+    reader: &mut BitReader<R>,
+    raw: &mut Vec<u8>,
+) -> Result<Vec<u8>, DecodeError> {
+    let mut field = Vec::new();
+    loop {
+        let byte = take(reader, raw, 1)?[0];
+        if byte == 0 {
+            return Ok(field);
+        }
+        field.push(byte);
+    }
+}
+
+fn eof_error<R: Read>(reader: &mut BitReader<R>) -> DecodeError {
+    match reader.take_error() {
+        Some(err) => DecodeError::Io(err.kind(), err.to_string()),
+        None => DecodeError::UnexpectedEof { offset: reader.byte_offset() },
+    }
+}
+
+/// Check the CRC-32 and ISIZE trailer that follows a member's DEFLATE stream
+pub(crate) fn read_trailer<R: Read>(reader: &mut BitReader<R>, crc: u32, len: u32) -> Result<(), DecodeError> {
+    reader.align_to_byte();
+    let offset = reader.byte_offset();
+    let trailer = reader.read_bytes(8).ok_or_else(|| eof_error(reader))?;
+    let expected_crc = u32::from_le_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    let expected_len = u32::from_le_bytes([trailer[4], trailer[5], trailer[6], trailer[7]]);
+
+    if crc != expected_crc {
+        return Err(DecodeError::CrcMismatch { offset, expected: expected_crc, actual: crc });
+    }
+    if len != expected_len {
+        return Err(DecodeError::LengthMismatch { offset: offset + 4, expected: expected_len, actual: len });
+    }
+
+    Ok(())
+}
+
+/// Turn an inflate failure into a decode error, surfacing I/O errors that
+/// the bit reader reported as end of input
+pub(crate) fn inflate_error<R: Read>(reader: &mut BitReader<R>, err: InflateError) -> DecodeError {
+    match (err, reader.take_error()) {
+        (InflateError::UnexpectedEof { .. }, Some(io_err)) => {
+            DecodeError::Io(io_err.kind(), io_err.to_string())
+        }
+        (err, _) => DecodeError::Inflate(err),
+    }
+}
+
+/// One decoded member of a gzip file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzMember {
+    pub header: GzHeader,
+    pub data: Vec<u8>,
+}
+
+/// XFL value gzip writes for a compression level
+pub fn extra_flags_for(level: CompressionLevel) -> u8 {
+    match level.level() {
+        9 => 2,
+        0 | 1 => 4,
+        _ => 0,
+    }
+}
+
+/// A gzip member holding `deflated`, the compressed form of `data`, after
+/// the serialized `header`
+fn member(mut out: Vec<u8>, data: &[u8], deflated: &[u8]) -> Vec<u8> {
+    out.extend_from_slice(deflated);
+    out.extend_from_slice(&crc32(data).to_le_bytes());
+    // ISIZE is the input length modulo 2^32
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Compress `data` into a single gzip member with the given header; fails
+/// only if the header cannot be written, see `GzHeader::to_bytes`
+pub fn encode_member(header: &GzHeader, data: &[u8], level: CompressionLevel) -> Result<Vec<u8>, GzipError> {
+    let header = header.to_bytes()?;
+    Ok(member(header, data, &deflate_with_level(data, level)))
+}
+
+/// Compress `data` into a single gzip member with optimal parsing; see
+/// `deflate_ultra` and `encode_member`
+pub fn encode_member_ultra(header: &GzHeader, data: &[u8], iterations: usize) -> Result<Vec<u8>, GzipError> {
+    let header = header.to_bytes()?;
+    Ok(member(header, data, &deflate_ultra(data, iterations)))
+}
+
+/// Compress `data` into a gzip file with a default header
+pub fn gzip(data: &[u8]) -> Vec<u8> {
+    gzip_with_level(data, CompressionLevel::DEFAULT)
+}
+
+/// Compress `data` into a gzip file at the given level
+pub fn gzip_with_level(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+    let header = GzHeader { extra_flags: extra_flags_for(level), ..GzHeader::default() };
+    member(header.encode(), data, &deflate_with_level(data, level))
+}
+
+/// Compress `data` into a gzip file as small as optimal parsing can make it
+pub fn gzip_ultra(data: &[u8], iterations: usize) -> Vec<u8> {
+    let header = GzHeader { extra_flags: extra_flags_for(CompressionLevel::BEST), ..GzHeader::default() };
+    member(header.encode(), data, &deflate_ultra(data, iterations))
+}
+
+/// Decode every member of a gzip file. Concatenated members are allowed
+/// (RFC 1952, section 2.2) and show up as separate entries.
+pub fn decode_members(data: &[u8]) -> Result<Vec<GzMember>, GzipError> {
+    decode_members_with_limits(data, &Limits::NONE)
+}
+
+/// `decode_members` for untrusted input: fails with
+/// `GzipError::LimitExceeded` as soon as the output, expansion ratio or
+/// member count goes over `limits`
+pub fn decode_members_with_limits(data: &[u8], limits: &Limits) -> Result<Vec<GzMember>, GzipError> {
+    let mut reader = BitReader::new(data);
+    let mut members = Vec::new();
+    let mut total = 0u64;
+
+    loop {
+        limits.check_members(members.len())?;
+        let header = GzHeader::read_from(&mut reader)?;
+        let mut inflater = Inflater::new();
+        loop {
+            inflater
+                .decode_more(&mut reader)
+                .map_err(|err| inflate_error(&mut reader, err))?;
+            limits.check_output(reader.byte_offset() as u64, total + inflater.output().len() as u64)?;
+            if inflater.is_done() {
+                break;
+            }
+        }
+        let data = inflater.into_output();
+        total += data.len() as u64;
+        read_trailer(&mut reader, crc32(&data), data.len() as u32)?;
+        members.push(GzMember { header, data });
+
+        if reader.is_at_end() {
+            return Ok(members);
+        }
+    }
+}
+
+/// Decompress a gzip file, concatenating the contents of all members
+pub fn gunzip(data: &[u8]) -> Result<Vec<u8>, GzipError> {
+    gunzip_with_limits(data, &Limits::NONE)
+}
+
+/// `gunzip` for untrusted input; see `decode_members_with_limits`
+pub fn gunzip_with_limits(data: &[u8], limits: &Limits) -> Result<Vec<u8>, GzipError> {
+    let members = decode_members_with_limits(data, limits)?;
+    Ok(members.into_iter().flat_map(|member| member.data).collect())
+}
+
+/// Streaming gzip compressor: everything written is compressed into a single
+/// member on the inner writer. Call `finish` to write the trailer; dropping
+/// the encoder does the same but has to ignore errors.
+pub struct GzEncoder<W: Write> {
+    inner: Option<W>,
+    deflater: Deflater,
+    /// Header bytes not yet handed to `inner`
+    header: Vec<u8>,
+    crc: Crc32,
+    size: u32,
+}
+
+impl<W: Write> GzEncoder<W> {
+    pub fn new(inner: W, level: CompressionLevel) -> Self {
+        let header = GzHeader { extra_flags: extra_flags_for(level), ..GzHeader::default() };
+        Self::with_header_bytes(inner, header.encode(), level)
+    }
+
+    /// An encoder writing `header`; fails if it cannot be written, see
+    /// `GzHeader::to_bytes`
+    pub fn with_header(inner: W, header: &GzHeader, level: CompressionLevel) -> Result<Self, GzipError> {
+        Ok(Self::with_header_bytes(inner, header.to_bytes()?, level))
+    }
+
+    fn with_header_bytes(inner: W, header: Vec<u8>, level: CompressionLevel) -> Self {
+        GzEncoder {
+            inner: Some(inner),
+            deflater: Deflater::new(level),
+            header,
+            crc: Crc32::new(),
+            size: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Hand all completed output to the inner writer
+    fn write_output(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        if !self.header.is_empty() {
+            inner.write_all(&self.header)?;
+            self.header.clear();
+        }
+        inner.write_all(&self.deflater.take_output())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        self.deflater.finish();
+        self.write_output()?;
+
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&self.crc.value().to_le_bytes())?;
+        inner.write_all(&self.size.to_le_bytes())?;
+        inner.flush()
+    }
+
+    /// Compress any remaining input, write the trailer and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+}
+
+impl<W: Write> Write for GzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deflater.write(buf);
+        self.crc.update(buf);
+        // ISIZE is the input length modulo 2^32
+        self.size = self.size.wrapping_add(buf.len() as u32);
+        self.write_output()?;
+        Ok(buf.len())
+    }
+
+    /// Sync flush: everything written so far can be decompressed from the
+    /// bytes the inner writer has received
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflater.flush();
+        self.write_output()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for GzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// Where a `GzDecoder` is within its input
+enum DecoderState {
+    Header,
+    Body,
+    Done,
+}
+
+/// Streaming gzip decompressor. Members are decoded one after another, so
+/// reading yields the concatenated contents of a multi-member file; memory
+/// use stays at the 32 KiB window plus buffers whatever the file size.
+pub struct GzDecoder<R: Read> {
+    reader: BitReader<R>,
+    inflater: Inflater,
+    state: DecoderState,
+    header: Option<GzHeader>,
+    members: usize,
+    crc: Crc32,
+    size: u32,
+    limits: Limits,
+    /// Bytes handed out so far, over all members
+    total: u64,
+}
+
+impl<R: Read> GzDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_limits(inner, Limits::NONE)
+    }
+
+    /// A decoder for untrusted input: reads fail with an `InvalidData` error,
+    /// which `GzipError::from` turns into `GzipError::LimitExceeded`, before
+    /// any output beyond `limits` is returned
+    pub fn with_limits(inner: R, limits: Limits) -> Self {
+        GzDecoder {
+            reader: BitReader::new(inner),
+            inflater: Inflater::new(),
+            state: DecoderState::Header,
+            header: None,
+            members: 0,
+            crc: Crc32::new(),
+            size: 0,
+            limits,
+            total: 0,
+        }
+    }
+
+    /// A decoder picking up partway through a member, as `index` does from a
+    /// checkpoint: `reader` is at a block boundary, `window` holds the
+    /// output just before it, and `crc` and `size` cover the member's
+    /// output so far, so its trailer is still checked
+    pub(crate) fn resume(reader: BitReader<R>, window: &[u8], crc: u32, size: u32) -> Self {
+        GzDecoder {
+            reader,
+            inflater: Inflater::with_dictionary(window),
+            state: DecoderState::Body,
+            header: None,
+            members: 0,
+            crc: Crc32::from_value(crc),
+            size,
+            limits: Limits::NONE,
+            total: 0,
+        }
+    }
+
+    /// Give back the underlying reader, at some point past what has been
+    /// decoded
+    pub(crate) fn into_inner(self) -> R {
+        self.reader.into_inner()
+    }
+
+    /// Header of the first member, once reading has started
+    pub fn header(&self) -> Option<&GzHeader> {
+        self.header.as_ref()
+    }
+
+    fn read_decoded(&mut self, buf: &mut [u8]) -> Result<usize, DecodeError> {
+        loop {
+            match self.state {
+                DecoderState::Done => return Ok(0),
+                DecoderState::Header => {
+                    if self.members > 0 && self.reader.is_at_end() {
+                        self.state = DecoderState::Done;
+                        continue;
+                    }
+                    self.limits.check_members(self.members)?;
+                    let header = GzHeader::read_from(&mut self.reader)?;
+                    self.header.get_or_insert(header);
+                    self.inflater = Inflater::new();
+                    self.crc = Crc32::new();
+                    self.size = 0;
+                    self.state = DecoderState::Body;
+                }
+                DecoderState::Body => {
+                    let output = self.inflater.output();
+                    if !output.is_empty() {
+                        let n = output.len().min(buf.len());
+                        self.limits.check_output(self.reader.byte_offset() as u64, self.total + n as u64)?;
+                        self.total += n as u64;
+                        buf[..n].copy_from_slice(&output[..n]);
+                        self.crc.update(&buf[..n]);
+                        self.size = self.size.wrapping_add(n as u32);
+                        self.inflater.consume(n);
+                        return Ok(n);
+                    }
+                    if self.inflater.is_done() {
+                        read_trailer(&mut self.reader, self.crc.value(), self.size)?;
+                        self.members += 1;
+                        self.state = DecoderState::Header;
+                        continue;
+                    }
+                    self.inflater
+                        .fill(&mut self.reader, buf.len())
+                        .map_err(|err| inflate_error(&mut self.reader, err))?;
+                }
+            }
+        }
+    }
+}
+
+impl<R: Read> Read for GzDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.read_decoded(buf).map_err(io::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HeaderError;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.txt");
+
+    #[test]
+    fn test_decodes_system_gzip_fixtures() {
+        assert_eq!(gunzip(include_bytes!("../tests/fixtures/sample.txt.gz")).unwrap(), SAMPLE);
+        assert_eq!(gunzip(include_bytes!("../tests/fixtures/sample-fast.txt.gz")).unwrap(), SAMPLE);
+        assert_eq!(gunzip(include_bytes!("../tests/fixtures/empty.txt.gz")).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_header_fields_from_system_gzip() {
+        let members = decode_members(include_bytes!("../tests/fixtures/hello.txt.gz")).unwrap();
+
+        assert_eq!(members.len(), 1);
+        let header = &members[0].header;
+        assert_eq!(header.filename.as_deref(), Some(&b"hello.txt"[..]));
+        assert_eq!(header.mtime, 1_735_689_600);
+        assert_eq!(header.os, OS_UNIX);
+        assert_eq!(members[0].data, b"Hello, World!\n");
+    }
+
+    #[test]
+    fn test_multi_member() {
+        let members = decode_members(include_bytes!("../tests/fixtures/multi.gz")).unwrap();
+
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].data, b"Hello, World!\n");
+        assert_eq!(members[1].data, SAMPLE);
+        assert_eq!(members[1].header.filename, None);
+    }
+
+    #[test]
+    fn test_round_trip_with_all_header_fields() {
+        let header = GzHeader {
+            text: true,
+            mtime: 1_234_567_890,
+            extra_flags: 2,
+            os: OS_UNIX,
+            extra: Some(b"AP\x04\x00test".to_vec()),
+            filename: Some(b"caf\xe9.txt".to_vec()),
+            comment: Some(b"a comment".to_vec()),
+            header_crc: true,
+        };
+        let encoded = encode_member(&header, SAMPLE, CompressionLevel::BEST).unwrap();
+        let members = decode_members(&encoded).unwrap();
+
+        assert_eq!(members, vec![GzMember { header, data: SAMPLE.to_vec() }]);
+    }
+
+    #[test]
+    fn test_rejects_unencodable_header() {
+        let header = GzHeader { extra: Some(vec![0; 65536]), ..GzHeader::default() };
+        let err = GzipError::UnencodableHeader("FEXTRA payload over 65535 bytes");
+        assert_eq!(header.to_bytes(), Err(err.clone()));
+        assert_eq!(encode_member(&header, SAMPLE, CompressionLevel::DEFAULT), Err(err.clone()));
+        assert_eq!(GzEncoder::with_header(Vec::new(), &header, CompressionLevel::DEFAULT).err(), Some(err));
+
+        let header = GzHeader { extra: Some(vec![0; 65535]), ..GzHeader::default() };
+        assert_eq!(header.to_bytes().unwrap().len(), 10 + 2 + 65535);
+
+        let header = GzHeader { filename: Some(b"a\0b".to_vec()), ..GzHeader::default() };
+        assert_eq!(header.to_bytes(), Err(GzipError::UnencodableHeader("zero byte in file name")));
+    }
+
+    #[test]
+    fn test_corrupt_trailer() {
+        let mut encoded = gzip(b"checksum me");
+        let crc_offset = encoded.len() - 8;
+        encoded[crc_offset] ^= 0xff;
+
+        assert!(matches!(
+            gunzip(&encoded),
+            Err(GzipError::ChecksumMismatch { offset, .. }) if offset == crc_offset
+        ));
+    }
+
+    #[test]
+    fn test_streaming_round_trip() {
+        let data = SAMPLE.repeat(30);
+        let mut encoder = GzEncoder::new(Vec::new(), CompressionLevel::DEFAULT);
+        for piece in data.chunks(777) {
+            encoder.write_all(piece).unwrap();
+        }
+        let encoded = encoder.finish().unwrap();
+
+        assert_eq!(gunzip(&encoded).unwrap(), data);
+
+        let mut decoder = GzDecoder::new(&encoded[..]);
+        let mut decoded = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            let n = decoder.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            decoded.extend_from_slice(&buf[..n]);
+        }
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_sync_flush_makes_prefix_decodable() {
+        let mut encoder = GzEncoder::new(Vec::new(), CompressionLevel::DEFAULT);
+        encoder.write_all(b"line one\n").unwrap();
+        encoder.flush().unwrap();
+        let partial = encoder.get_ref().clone();
+
+        // No trailer yet, but every byte written so far is recoverable
+        let mut decoder = GzDecoder::new(&partial[..]);
+        let mut buf = [0u8; 64];
+        let n = decoder.read(&mut buf).unwrap();
+        assert_eq!(&buf[..n], b"line one\n");
+    }
+
+    #[test]
+    fn test_streaming_decoder_multi_member() {
+        let mut decoder = GzDecoder::new(&include_bytes!("../tests/fixtures/multi.gz")[..]);
+        let mut decoded = Vec::new();
+        decoder.read_to_end(&mut decoded).unwrap();
+
+        let mut expected = b"Hello, World!\n".to_vec();
+        expected.extend_from_slice(SAMPLE);
+        assert_eq!(decoded, expected);
+        assert_eq!(decoder.header().unwrap().filename.as_deref(), Some(&b"hello.txt"[..]));
+    }
+
+    #[test]
+    fn test_streaming_decoder_reports_corruption() {
+        let mut encoded = gzip(SAMPLE);
+        let len = encoded.len();
+        encoded[len - 5] ^= 1;
+
+        let err = GzDecoder::new(&encoded[..]).read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_limits_stop_bombs() {
+        // 2 MB of zeros compresses to about 2 KB
+        let bomb = gzip(&vec![0u8; 2_000_000]);
+
+        let limits = Limits { max_output: Some(1_000_000), ..Limits::default() };
+        assert_eq!(
+            gunzip_with_limits(&bomb, &limits),
+            Err(GzipError::LimitExceeded(LimitError::OutputTooLarge { limit: 1_000_000 }))
+        );
+        let limits = Limits { max_ratio: Some(100), ..Limits::default() };
+        assert!(matches!(
+            gunzip_with_limits(&bomb, &limits),
+            Err(GzipError::LimitExceeded(LimitError::RatioTooHigh { limit: 100, .. }))
+        ));
+
+        // Ordinary data gets through the same limits
+        let limits = Limits { max_output: Some(1_000_000), max_ratio: Some(100), max_members: Some(1) };
+        assert_eq!(gunzip_with_limits(&gzip(SAMPLE), &limits).unwrap(), SAMPLE);
+    }
+
+    #[test]
+    fn test_member_limit() {
+        let multi = include_bytes!("../tests/fixtures/multi.gz");
+        let limits = Limits { max_members: Some(1), ..Limits::default() };
+        assert_eq!(
+            decode_members_with_limits(multi, &limits),
+            Err(GzipError::LimitExceeded(LimitError::TooManyMembers { limit: 1 }))
+        );
+        let limits = Limits { max_members: Some(2), ..Limits::default() };
+        assert_eq!(decode_members_with_limits(multi, &limits).unwrap().len(), 2);
+
+        let err = GzDecoder::with_limits(&multi[..], Limits { max_members: Some(1), ..Limits::default() })
+            .read_to_end(&mut Vec::new())
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_streaming_decoder_limits() {
+        let bomb = gzip(&vec![0u8; 2_000_000]);
+        let mut decoder = GzDecoder::with_limits(&bomb[..], Limits { max_output: Some(100_000), ..Limits::default() });
+        let mut decoded = Vec::new();
+        let err = decoder.read_to_end(&mut decoded).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decoded.len() <= 100_000, "{}", decoded.len());
+    }
+
+    #[test]
+    fn test_bad_headers() {
+        assert_eq!(gunzip(b""), Err(GzipError::TruncatedStream { offset: 0 }));
+        assert_eq!(gunzip(b"PK\x03\x04......"), Err(GzipError::InvalidHeader { offset: 0, reason: HeaderError::InvalidMagic }));
+
+        let mut encoded = gzip(b"x");
+        encoded[2] = 7;
+        assert_eq!(
+            gunzip(&encoded),
+            Err(GzipError::InvalidHeader { offset: 2, reason: HeaderError::UnsupportedMethod(7) })
+        );
+    }
+}
@@ -0,0 +1,840 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::io::Read;
+
+use crate::bits::{reverse_bits, BitReader, BitWriter};
+use crate::deflate::{distance_symbol, length_symbol, Symbol, END_OF_BLOCK, MAX_CODE_BITS};
+use crate::inflate::{Decoder, InflateError};
+use crate::lz77::Token;
+
+// Huffman tree node
+#[derive(Debug, Clone)]
+pub enum HuffmanNode {
+    Leaf { symbol: u16, freq: usize },
+    Internal { left: Box<HuffmanNode>, right: Box<HuffmanNode>, freq: usize },
+}
+
+impl HuffmanNode {
+    fn freq(&self) -> usize {
+        match self {
+            HuffmanNode::Leaf { freq, .. } => *freq,
+            HuffmanNode::Internal { freq, .. } => *freq,
+        }
+    }
+}
+
+/// Build Huffman tree from frequency map
+pub fn build_huffman_tree(frequencies: &HashMap<u16, usize>) -> Option<HuffmanNode> {
+    if frequencies.is_empty() {
+        return None;
+    }
+
+    // Create initial leaf nodes
+    let mut nodes: Vec<HuffmanNode> = frequencies
+        .iter()
+        .map(|(&symbol, &freq)| HuffmanNode::Leaf { symbol, freq })
+        .collect();
+
+    // HashMap order changes from run to run; start from symbol order so the
+    // (stable) sorts below always pair the same nodes
+    nodes.sort_by_key(|n| match n {
+        HuffmanNode::Leaf { symbol, .. } => *symbol,
+        HuffmanNode::Internal { .. } => unreachable!(),
+    });
+
+    // Build tree by repeatedly combining two lowest frequency nodes
+    while nodes.len() > 1 {
+        // Sort by frequency
+        nodes.sort_by_key(|n| std::cmp::Reverse(n.freq()));
+
+        // Take two lowest frequency nodes
+        let right = nodes.pop().unwrap();
+        let left = nodes.pop().unwrap();
+
+        // Create internal node
+        let internal = HuffmanNode::Internal {
+            freq: left.freq() + right.freq(),
+            left: Box::new(left),
+            right: Box::new(right),
+        };
+
+        nodes.push(internal);
+    }
+
+    nodes.pop()
+}
+
+/// Generate Huffman codes from tree
+pub fn generate_codes(node: &HuffmanNode, prefix: String, codes: &mut HashMap<u16, String>) {
+    match node {
+        HuffmanNode::Leaf { symbol, .. } => {
+            codes.insert(*symbol, if prefix.is_empty() { "0".to_string() } else { prefix });
+        }
+        HuffmanNode::Internal { left, right, .. } => {
+            generate_codes(left, format!("{}0", prefix), codes);
+            generate_codes(right, format!("{}1", prefix), codes);
+        }
+    }
+}
+
+/// Unrestricted Huffman code lengths, computed in place (Moffat & Katajainen).
+///
+/// `weights` must be sorted in increasing order and hold at least two
+/// entries; on return each holds the code length of its symbol.
+fn minimum_redundancy(weights: &mut [usize]) {
+    let n = weights.len();
+
+    // Phase 1: build the tree, internal nodes overwriting the weights with
+    // parent pointers as the leaves they replace are consumed
+    weights[0] += weights[1];
+    let (mut root, mut leaf) = (0, 2);
+    for next in 1..n - 1 {
+        if leaf >= n || weights[root] < weights[leaf] {
+            weights[next] = weights[root];
+            weights[root] = next;
+            root += 1;
+        } else {
+            weights[next] = weights[leaf];
+            leaf += 1;
+        }
+        if leaf >= n || (root < next && weights[root] < weights[leaf]) {
+            weights[next] += weights[root];
+            weights[root] = next;
+            root += 1;
+        } else {
+            weights[next] += weights[leaf];
+            leaf += 1;
+        }
+    }
+
+    // Phase 2: parent pointers become internal node depths
+    weights[n - 2] = 0;
+    for next in (0..n - 2).rev() {
+        weights[next] = weights[weights[next]] + 1;
+    }
+
+    // Phase 3: internal node depths become leaf depths
+    let (mut available, mut used, mut depth) = (1, 0, 0);
+    let mut root = n as isize - 2;
+    let mut next = n as isize - 1;
+    while available > 0 {
+        while root >= 0 && weights[root as usize] == depth {
+            used += 1;
+            root -= 1;
+        }
+        while available > used {
+            weights[next as usize] = depth;
+            next -= 1;
+            available -= 1;
+        }
+        available = 2 * used;
+        depth += 1;
+        used = 0;
+    }
+}
+
+/// An entry in a package-merge list: either a symbol or a package of two
+/// cheaper entries from the list below
+#[derive(Debug, Clone, Copy)]
+enum Coin {
+    Leaf(usize),
+    Package(usize, usize),
+}
+
+/// Package-merge over `leaves`, (frequency, symbol) pairs sorted by frequency,
+/// adding each symbol's code length to `lengths`
+fn package_merge(leaves: &[(usize, usize)], max_bits: u8, lengths: &mut [u8]) {
+    // Every list entry is (weight, index into `coins`)
+    let mut coins: Vec<Coin> = leaves.iter().map(|&(_, symbol)| Coin::Leaf(symbol)).collect();
+    let leaf_list: Vec<(usize, usize)> =
+        leaves.iter().enumerate().map(|(i, &(freq, _))| (freq, i)).collect();
+
+    let mut list = leaf_list.clone();
+    for _ in 1..max_bits {
+        // Package adjacent pairs, then merge the packages with a fresh set of leaves
+        let mut packages = Vec::with_capacity(list.len() / 2);
+        for pair in list.chunks_exact(2) {
+            coins.push(Coin::Package(pair[0].1, pair[1].1));
+            packages.push((pair[0].0 + pair[1].0, coins.len() - 1));
+        }
+
+        let mut merged = Vec::with_capacity(leaf_list.len() + packages.len());
+        let (mut i, mut j) = (0, 0);
+        while i < leaf_list.len() || j < packages.len() {
+            // Leaves go first on equal weight, keeping the result deterministic
+            if j == packages.len() || (i < leaf_list.len() && leaf_list[i].0 <= packages[j].0) {
+                merged.push(leaf_list[i]);
+                i += 1;
+            } else {
+                merged.push(packages[j]);
+                j += 1;
+            }
+        }
+        list = merged;
+    }
+
+    // The 2n - 2 cheapest entries form the code: each time a symbol appears
+    // among them (directly or inside a package) its code grows by one bit
+    let mut stack: Vec<usize> = list[..2 * leaves.len() - 2].iter().map(|&(_, coin)| coin).collect();
+    while let Some(coin) = stack.pop() {
+        match coins[coin] {
+            Coin::Leaf(symbol) => lengths[symbol] += 1,
+            Coin::Package(a, b) => {
+                stack.push(a);
+                stack.push(b);
+            }
+        }
+    }
+}
+
+/// Code lengths for symbols `0..frequencies.len()`, none longer than `max_bits`.
+///
+/// When the plain Huffman code already fits, that is the answer; otherwise the
+/// package-merge algorithm (Larmore & Hirschberg) finds the optimal lengths
+/// under the limit rather than patching an unlimited tree.
+/// Ties are broken by symbol value, so the same frequencies always give the
+/// same lengths. Symbols with zero frequency get length 0 (no code), and a
+/// lone symbol gets a 1-bit code.
+pub fn code_lengths(frequencies: &[usize], max_bits: u8) -> Vec<u8> {
+    let mut lengths = vec![0u8; frequencies.len()];
+
+    let mut leaves: Vec<(usize, usize)> = frequencies
+        .iter()
+        .enumerate()
+        .filter(|&(_, &freq)| freq > 0)
+        .map(|(symbol, &freq)| (freq, symbol))
+        .collect();
+    leaves.sort_unstable();
+
+    match leaves.len() {
+        0 => return lengths,
+        1 => {
+            lengths[leaves[0].1] = 1;
+            return lengths;
+        }
+        n => assert!(n <= 1 << max_bits, "{} symbols cannot fit in {}-bit codes", n, max_bits),
+    }
+
+    let mut depths: Vec<usize> = leaves.iter().map(|&(freq, _)| freq).collect();
+    minimum_redundancy(&mut depths);
+    // The rarest symbol has the longest code
+    if depths[0] <= max_bits as usize {
+        for (&(_, symbol), &depth) in leaves.iter().zip(&depths) {
+            lengths[symbol] = depth as u8;
+        }
+        return lengths;
+    }
+
+    package_merge(&leaves, max_bits, &mut lengths);
+    lengths
+}
+
+/// Canonical codes for the given code lengths (RFC 1951, section 3.2.2).
+///
+/// Codes are returned most-significant-bit first, exactly as the RFC writes
+/// them; a length of 0 yields an unused code of 0.
+pub fn canonical_codes(lengths: &[u8]) -> Vec<u16> {
+    let max_bits = lengths.iter().copied().max().unwrap_or(0) as usize;
+
+    // 1) Count the number of codes for each code length
+    let mut bl_count = vec![0u16; max_bits + 1];
+    for &len in lengths {
+        if len > 0 {
+            bl_count[len as usize] += 1;
+        }
+    }
+
+    // 2) Find the numerical value of the smallest code for each code length
+    let mut next_code = vec![0u16; max_bits + 1];
+    let mut code = 0u16;
+    for bits in 1..=max_bits {
+        code = (code + bl_count[bits - 1]) << 1;
+        next_code[bits] = code;
+    }
+
+    // 3) Assign consecutive values to all codes of the same length
+    lengths
+        .iter()
+        .map(|&len| {
+            if len == 0 {
+                return 0;
+            }
+            let code = next_code[len as usize];
+            next_code[len as usize] += 1;
+            code
+        })
+        .collect()
+}
+
+/// Code tables produced by `huffman_encode`, one per DEFLATE alphabet
+#[derive(Debug, Clone, Default)]
+pub struct HuffmanCodes {
+    /// Literals 0..=255, end-of-block 256 and length symbols 257..=285
+    pub litlen: HashMap<u16, String>,
+    /// Distance symbols 0..=29
+    pub distance: HashMap<u16, String>,
+}
+
+/// Canonical, length-limited codes for one alphabet, as strings of '0'/'1'
+fn codes_for(frequencies: &HashMap<u16, usize>) -> HashMap<u16, String> {
+    let size = frequencies.keys().max().map_or(0, |&max| max as usize + 1);
+    let mut dense = vec![0usize; size];
+    for (&symbol, &freq) in frequencies {
+        dense[symbol as usize] = freq;
+    }
+
+    let lengths = code_lengths(&dense, MAX_CODE_BITS);
+    let codes = canonical_codes(&lengths);
+
+    lengths
+        .iter()
+        .zip(&codes)
+        .enumerate()
+        .filter(|&(_, (&len, _))| len > 0)
+        .map(|(symbol, (&len, &code))| (symbol as u16, format!("{:0width$b}", code, width = len as usize)))
+        .collect()
+}
+
+/// Append extra bits in transmission order (least-significant bit first)
+fn push_extra_bits(encoded: &mut String, symbol: Symbol) {
+    for bit in 0..symbol.extra_bits {
+        encoded.push(if symbol.extra >> bit & 1 == 1 { '1' } else { '0' });
+    }
+}
+
+/// Encode tokens using Huffman coding, with lengths and distances mapped to
+/// their RFC 1951 symbols plus extra bits, and a closing end-of-block symbol
+pub fn huffman_encode(tokens: &[Token]) -> (String, HuffmanCodes) {
+    // Count frequencies
+    let mut litlen_freqs = HashMap::new();
+    let mut distance_freqs = HashMap::new();
+
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => {
+                *litlen_freqs.entry(*byte as u16).or_insert(0) += 1;
+            }
+            Token::Reference { length, distance } => {
+                *litlen_freqs.entry(length_symbol(*length).symbol).or_insert(0) += 1;
+                *distance_freqs.entry(distance_symbol(*distance).symbol).or_insert(0) += 1;
+            }
+        }
+    }
+    litlen_freqs.insert(END_OF_BLOCK as u16, 1);
+
+    // Build a Huffman tree per alphabet and generate codes
+    let codes = HuffmanCodes {
+        litlen: codes_for(&litlen_freqs),
+        distance: codes_for(&distance_freqs),
+    };
+
+    // Encode the data
+    let mut encoded = String::new();
+    for token in tokens {
+        match token {
+            Token::Literal(byte) => {
+                encoded.push_str(&codes.litlen[&(*byte as u16)]);
+            }
+            Token::Reference { length, distance } => {
+                let length = length_symbol(*length);
+                let distance = distance_symbol(*distance);
+                encoded.push_str(&codes.litlen[&length.symbol]);
+                push_extra_bits(&mut encoded, length);
+                encoded.push_str(&codes.distance[&distance.symbol]);
+                push_extra_bits(&mut encoded, distance);
+            }
+        }
+    }
+    encoded.push_str(&codes.litlen[&(END_OF_BLOCK as u16)]);
+
+    (encoded, codes)
+}
+
+/// A type usable as the alphabet of a `HuffmanCode`. The order decides how
+/// canonical codes are assigned; the integer form is what gets serialized.
+pub trait HuffmanSymbol: Copy + Ord {
+    fn to_u32(self) -> u32;
+    fn from_u32(value: u32) -> Option<Self>;
+}
+
+macro_rules! impl_huffman_symbol {
+    ($($ty:ty),*) => {$(
+        impl HuffmanSymbol for $ty {
+            fn to_u32(self) -> u32 {
+                self as u32
+            }
+
+            fn from_u32(value: u32) -> Option<Self> {
+                value.try_into().ok()
+            }
+        }
+    )*};
+}
+
+impl_huffman_symbol!(u8, u16, u32);
+
+impl HuffmanSymbol for char {
+    fn to_u32(self) -> u32 {
+        self as u32
+    }
+
+    fn from_u32(value: u32) -> Option<Self> {
+        char::from_u32(value)
+    }
+}
+
+/// Reasons a `HuffmanCode` cannot be built, used or read back
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HuffmanError {
+    /// More distinct symbols than codes of at most `max_bits` bits
+    TooManySymbols { count: usize, max_bits: u8 },
+    /// A length is 0 or over 15, or the lengths do not form a complete prefix code
+    InvalidLengths,
+    /// The same symbol was given two code lengths
+    DuplicateSymbol,
+    /// The symbol at this position of the input has no code
+    UnknownSymbol { index: usize },
+    /// A serialized table is malformed at this byte
+    InvalidTable { offset: usize },
+    /// The bits at this byte are not a code
+    InvalidCode { offset: usize },
+    /// The input ended at this byte, in the middle of a table or code
+    UnexpectedEof { offset: usize },
+}
+
+impl fmt::Display for HuffmanError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HuffmanError::TooManySymbols { count, max_bits } => {
+                write!(f, "{} symbols cannot fit in {}-bit codes", count, max_bits)
+            }
+            HuffmanError::InvalidLengths => f.write_str("code lengths do not form a prefix code"),
+            HuffmanError::DuplicateSymbol => f.write_str("symbol listed twice"),
+            HuffmanError::UnknownSymbol { index } => write!(f, "symbol {} has no code", index),
+            HuffmanError::InvalidTable { offset } => write!(f, "invalid code table at byte {}", offset),
+            HuffmanError::InvalidCode { offset } => write!(f, "invalid Huffman code at byte {}", offset),
+            HuffmanError::UnexpectedEof { offset } => write!(f, "unexpected end of input at byte {}", offset),
+        }
+    }
+}
+
+impl std::error::Error for HuffmanError {}
+
+impl From<InflateError> for HuffmanError {
+    fn from(err: InflateError) -> Self {
+        match err {
+            InflateError::UnexpectedEof { offset } => HuffmanError::UnexpectedEof { offset },
+            // A symbol decoder only reports the two above, but anything else
+            // is still bad data at `offset`
+            InflateError::InvalidSymbol { offset }
+            | InflateError::InvalidBlockType { offset }
+            | InflateError::StoredLengthMismatch { offset }
+            | InflateError::InvalidCodeLengths { offset }
+            | InflateError::DistanceTooFar { offset, .. } => HuffmanError::InvalidCode { offset },
+        }
+    }
+}
+
+/// A canonical, length-limited Huffman code over any `HuffmanSymbol`,
+/// independent of DEFLATE's alphabets. Bits are packed least-significant
+/// first with codes most-significant first, as DEFLATE does, and decoding
+/// goes through the same lookup table as `inflate`.
+#[derive(Debug, Clone)]
+pub struct HuffmanCode<S> {
+    /// Symbols with a code, in increasing order
+    symbols: Vec<S>,
+    /// Code length of each of `symbols`
+    lengths: Vec<u8>,
+    /// Code of each of `symbols`, bit-reversed to write least-significant first
+    codes: Vec<u16>,
+    /// Maps input bits to an index into `symbols`
+    decoder: Decoder,
+}
+
+impl<S: HuffmanSymbol> HuffmanCode<S> {
+    /// The optimal code for these symbol frequencies with no code longer
+    /// than `max_bits` (at most 15). Repeated symbols have their counts
+    /// added; symbols that never occur get no code.
+    pub fn from_frequencies(
+        frequencies: impl IntoIterator<Item = (S, usize)>,
+        max_bits: u8,
+    ) -> Result<Self, HuffmanError> {
+        let mut counts = BTreeMap::new();
+        for (symbol, freq) in frequencies {
+            if freq > 0 {
+                *counts.entry(symbol).or_insert(0) += freq;
+            }
+        }
+
+        let max_bits = max_bits.min(MAX_CODE_BITS);
+        if counts.len() > 1 << max_bits {
+            return Err(HuffmanError::TooManySymbols { count: counts.len(), max_bits });
+        }
+        let freqs: Vec<usize> = counts.values().copied().collect();
+        Self::from_lengths(counts.into_keys().zip(code_lengths(&freqs, max_bits)))
+    }
+
+    /// The optimal code for the symbols in `data`, by how often each occurs
+    pub fn from_data(data: &[S], max_bits: u8) -> Result<Self, HuffmanError> {
+        Self::from_frequencies(data.iter().map(|&symbol| (symbol, 1)), max_bits)
+    }
+
+    /// The canonical code with these code lengths, which must describe a
+    /// complete prefix code (or a single 1-bit code)
+    pub fn from_lengths(lengths: impl IntoIterator<Item = (S, u8)>) -> Result<Self, HuffmanError> {
+        let mut pairs: Vec<(S, u8)> = lengths.into_iter().collect();
+        pairs.sort_unstable_by_key(|&(symbol, _)| symbol);
+        if pairs.windows(2).any(|pair| pair[0].0 == pair[1].0) {
+            return Err(HuffmanError::DuplicateSymbol);
+        }
+        if pairs.len() > 1 << MAX_CODE_BITS || pairs.iter().any(|&(_, len)| len == 0 || len > MAX_CODE_BITS) {
+            return Err(HuffmanError::InvalidLengths);
+        }
+
+        let (symbols, lengths): (Vec<S>, Vec<u8>) = pairs.into_iter().unzip();
+        let decoder = Decoder::from_lengths(&lengths, true).ok_or(HuffmanError::InvalidLengths)?;
+        let codes = canonical_codes(&lengths)
+            .into_iter()
+            .zip(&lengths)
+            .map(|(code, &len)| reverse_bits(code, len))
+            .collect();
+        Ok(HuffmanCode { symbols, lengths, codes, decoder })
+    }
+
+    /// Number of symbols with a code
+    pub fn len(&self) -> usize {
+        self.symbols.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.symbols.is_empty()
+    }
+
+    /// Every symbol with its code length, in symbol order
+    pub fn lengths(&self) -> impl Iterator<Item = (S, u8)> + '_ {
+        self.symbols.iter().copied().zip(self.lengths.iter().copied())
+    }
+
+    /// The code for `symbol`, most-significant bit first, and its length
+    pub fn code(&self, symbol: S) -> Option<(u16, u8)> {
+        let i = self.symbols.binary_search(&symbol).ok()?;
+        Some((reverse_bits(self.codes[i], self.lengths[i]), self.lengths[i]))
+    }
+
+    /// Total bits needed to code `data`, or `None` if a symbol has no code
+    pub fn cost(&self, data: &[S]) -> Option<usize> {
+        data.iter().map(|&symbol| self.code(symbol).map(|(_, len)| len as usize)).sum()
+    }
+
+    /// Append the code for `symbol`, returning false if it has none
+    pub fn write(&self, writer: &mut BitWriter, symbol: S) -> bool {
+        match self.symbols.binary_search(&symbol) {
+            Ok(i) => {
+                writer.write_bits(self.codes[i] as u32, self.lengths[i] as u32);
+                true
+            }
+            Err(_) => false,
+        }
+    }
+
+    /// Decode the next symbol
+    pub fn read<R: Read>(&self, reader: &mut BitReader<R>) -> Result<S, HuffmanError> {
+        Ok(self.symbols[self.decoder.decode(reader)? as usize])
+    }
+
+    /// Pack the codes for `data` into bytes, the last one zero-padded
+    pub fn encode(&self, data: &[S]) -> Result<Vec<u8>, HuffmanError> {
+        let mut writer = BitWriter::new();
+        for (index, &symbol) in data.iter().enumerate() {
+            if !self.write(&mut writer, symbol) {
+                return Err(HuffmanError::UnknownSymbol { index });
+            }
+        }
+        Ok(writer.finish())
+    }
+
+    /// Decode `count` symbols from the start of `data`
+    pub fn decode(&self, data: &[u8], count: usize) -> Result<Vec<S>, HuffmanError> {
+        let mut reader = BitReader::new(data);
+        // Every code is at least one bit, which bounds a bogus count
+        let mut out = Vec::with_capacity(count.min(data.len() * 8));
+        for _ in 0..count {
+            out.push(self.read(&mut reader)?);
+        }
+        Ok(out)
+    }
+
+    /// The code table in a compact form: the number of symbols, then the
+    /// gaps between successive symbols, all as LEB128 varints, then the
+    /// code lengths packed two to a byte, low nibble first
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        write_varint(&mut out, self.symbols.len() as u32);
+        let mut next = 0;
+        for &symbol in &self.symbols {
+            write_varint(&mut out, symbol.to_u32() - next);
+            next = symbol.to_u32() + 1;
+        }
+        out.extend(self.lengths.chunks(2).map(|pair| pair[0] | pair.get(1).map_or(0, |len| len << 4)));
+        out
+    }
+
+    /// Read a table written by `serialize`, returning it and the number of
+    /// bytes it took up
+    pub fn deserialize(data: &[u8]) -> Result<(Self, usize), HuffmanError> {
+        let mut pos = 0;
+        let count = read_varint(data, &mut pos)? as usize;
+        // Each symbol takes at least one byte for its gap
+        if count > data.len() - pos {
+            return Err(HuffmanError::UnexpectedEof { offset: data.len() });
+        }
+
+        let mut symbols = Vec::with_capacity(count);
+        let mut next = 0u32;
+        for _ in 0..count {
+            let offset = pos;
+            let value = next.checked_add(read_varint(data, &mut pos)?);
+            let symbol = value.and_then(S::from_u32).ok_or(HuffmanError::InvalidTable { offset })?;
+            symbols.push(symbol);
+            next = value.unwrap().checked_add(1).ok_or(HuffmanError::InvalidTable { offset })?;
+        }
+
+        let packed = data
+            .get(pos..pos + count.div_ceil(2))
+            .ok_or(HuffmanError::UnexpectedEof { offset: data.len() })?;
+        let lengths = packed.iter().flat_map(|&byte| [byte & 0x0f, byte >> 4]).take(count);
+        let code = Self::from_lengths(symbols.into_iter().zip(lengths))
+            .map_err(|_| HuffmanError::InvalidTable { offset: pos })?;
+        Ok((code, pos + packed.len()))
+    }
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u32) {
+    while value >= 0x80 {
+        out.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], pos: &mut usize) -> Result<u32, HuffmanError> {
+    let start = *pos;
+    let mut value = 0u32;
+    for shift in (0..35).step_by(7) {
+        let byte = *data.get(*pos).ok_or(HuffmanError::UnexpectedEof { offset: *pos })?;
+        *pos += 1;
+        if shift == 28 && byte > 0x0f {
+            return Err(HuffmanError::InvalidTable { offset: start });
+        }
+        value |= ((byte & 0x7f) as u32) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+    }
+    unreachable!()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_data::values;
+
+    #[test]
+    fn test_huffman_tree() {
+        let mut freq = HashMap::new();
+        freq.insert(65, 3); // 'A' appears 3 times
+        freq.insert(66, 1); // 'B' appears 1 time
+
+        let tree = build_huffman_tree(&freq);
+        assert!(tree.is_some());
+    }
+
+    #[test]
+    fn test_code_lengths_respect_limit() {
+        // Fibonacci frequencies produce the deepest possible tree
+        let mut freqs = vec![1usize, 1];
+        while freqs.len() < 30 {
+            let n = freqs.len();
+            freqs.push(freqs[n - 1] + freqs[n - 2]);
+        }
+
+        let lengths = code_lengths(&freqs, 15);
+        assert!(lengths.iter().all(|&len| (1..=15).contains(&len)));
+
+        // Kraft sum must still describe a complete prefix code
+        let kraft: u64 = lengths.iter().map(|&len| 1u64 << (15 - len)).sum();
+        assert_eq!(kraft, 1 << 15);
+    }
+
+    #[test]
+    fn test_huffman_encode_uses_separate_alphabets() {
+        // Length 258 used to map to 514, clashing with the old distance range
+        let tokens = [
+            Token::Literal(b'a'),
+            Token::Reference { length: 258, distance: 1 },
+            Token::Reference { length: 3, distance: 40_000 - 32_768 },
+        ];
+        let (encoded, codes) = huffman_encode(&tokens);
+
+        let mut litlen: Vec<u16> = codes.litlen.keys().copied().collect();
+        litlen.sort();
+        let mut distance: Vec<u16> = codes.distance.keys().copied().collect();
+        distance.sort();
+        assert_eq!(litlen, vec![97, 256, 257, 285]);
+        assert_eq!(distance, vec![0, 25]);
+
+        // Codes plus 0 + 0 + 11 extra bits for distance 7232 (code 25, base 6145)
+        let code_bits = |map: &HashMap<u16, String>, symbol| map[&symbol].len();
+        let expected = code_bits(&codes.litlen, 97)
+            + code_bits(&codes.litlen, 285)
+            + code_bits(&codes.distance, 0)
+            + code_bits(&codes.litlen, 257)
+            + code_bits(&codes.distance, 25)
+            + 11
+            + code_bits(&codes.litlen, 256);
+        assert_eq!(encoded.len(), expected);
+    }
+
+    #[test]
+    fn test_package_merge_matches_huffman_when_unconstrained() {
+        // Classic example: optimal lengths are 1, 2, 3, 4, 4
+        assert_eq!(code_lengths(&[1, 1, 2, 4, 8], 15), vec![4, 4, 3, 2, 1]);
+        // With a 3-bit limit the two deepest codes must move up
+        assert_eq!(code_lengths(&[1, 1, 2, 4, 8], 3), vec![3, 3, 3, 3, 1]);
+        assert_eq!(code_lengths(&[0, 5, 0], 15), vec![0, 1, 0]);
+
+        // Away from the limit both algorithms find codes of the same total size
+        for chunk in values(20 * 286, 7).chunks(286) {
+            let freqs: Vec<usize> = chunk.iter().map(|&value| 1 + value as usize % 5000).collect();
+            let mut leaves: Vec<(usize, usize)> = freqs.iter().copied().zip(0..).collect();
+            leaves.sort_unstable();
+            let mut merged = vec![0u8; freqs.len()];
+            package_merge(&leaves, 15, &mut merged);
+
+            let cost = |lengths: &[u8]| -> usize {
+                freqs.iter().zip(lengths).map(|(&freq, &len)| freq * len as usize).sum()
+            };
+            assert_eq!(cost(&code_lengths(&freqs, 15)), cost(&merged));
+        }
+    }
+
+    #[test]
+    fn test_huffman_encode_is_deterministic() {
+        // Lots of equal frequencies: any tie-breaking by hash order would show up
+        let tokens: Vec<Token> = (0..=255u8).chain(0..=255u8).map(Token::Literal).collect();
+        let (first, first_codes) = huffman_encode(&tokens);
+
+        for _ in 0..10 {
+            let (encoded, codes) = huffman_encode(&tokens);
+            assert_eq!(encoded, first);
+            assert_eq!(codes.litlen, first_codes.litlen);
+        }
+        assert!(first_codes.litlen.values().all(|code| code.len() <= 15));
+    }
+
+    #[test]
+    fn test_huffman_code_round_trip() {
+        let text: Vec<char> = "abracadabra, the quick brown fox; ünïcödé too".chars().collect();
+        let code = HuffmanCode::from_data(&text, 15).unwrap();
+        let packed = code.encode(&text).unwrap();
+
+        assert_eq!(packed.len(), code.cost(&text).unwrap().div_ceil(8));
+        assert_eq!(code.decode(&packed, text.len()).unwrap(), text);
+        // The most frequent symbol has the shortest code
+        let shortest = code.lengths().map(|(_, len)| len).min().unwrap();
+        assert_eq!(code.code('a').unwrap().1, shortest);
+        assert_eq!(code.encode(&['a', 'Z']), Err(HuffmanError::UnknownSymbol { index: 1 }));
+    }
+
+    #[test]
+    fn test_huffman_code_from_lengths() {
+        // The RFC 1951 example again, over sparse u32 symbols
+        let symbols = [10u32, 20, 30, 40, 50, 60, 70, 80];
+        let code = HuffmanCode::from_lengths(symbols.into_iter().zip([3, 3, 3, 3, 3, 2, 4, 4])).unwrap();
+        assert_eq!(code.code(60), Some((0b00, 2)));
+        assert_eq!(code.code(80), Some((0b1111, 4)));
+        assert_eq!(code.code(15), None);
+
+        assert_eq!(HuffmanCode::from_lengths([(1u8, 1), (2, 1), (3, 1)]).unwrap_err(), HuffmanError::InvalidLengths);
+        assert_eq!(HuffmanCode::from_lengths([(1u8, 1), (2, 2)]).unwrap_err(), HuffmanError::InvalidLengths);
+        assert_eq!(HuffmanCode::from_lengths([(1u8, 1), (1, 1)]).unwrap_err(), HuffmanError::DuplicateSymbol);
+        assert_eq!(
+            HuffmanCode::from_frequencies((0..=255u8).map(|b| (b, 1)), 7).unwrap_err(),
+            HuffmanError::TooManySymbols { count: 256, max_bits: 7 }
+        );
+    }
+
+    #[test]
+    fn test_huffman_code_edge_cases() {
+        let single = HuffmanCode::from_data(&[7u16; 10], 15).unwrap();
+        assert_eq!(single.code(7), Some((0, 1)));
+        assert_eq!(single.decode(&single.encode(&[7; 10]).unwrap(), 10).unwrap(), [7; 10]);
+
+        let empty = HuffmanCode::<u8>::from_data(&[], 15).unwrap();
+        assert!(empty.is_empty());
+        assert_eq!(empty.encode(&[]).unwrap(), Vec::<u8>::new());
+        assert!(empty.decode(&[0], 1).is_err());
+
+        let code = HuffmanCode::from_data(b"aaaabbc", 15).unwrap();
+        let packed = code.encode(b"abc").unwrap();
+        assert_eq!(code.decode(&packed, 10), Err(HuffmanError::UnexpectedEof { offset: 1 }));
+    }
+
+    #[test]
+    fn test_huffman_code_serialization() {
+        let data: Vec<u16> = (0..2000u32).map(|i| ((i * i) % 613 + 1000) as u16).collect();
+        let code = HuffmanCode::from_data(&data, 12).unwrap();
+
+        let mut table = code.serialize();
+        let table_len = table.len();
+        table.extend(code.encode(&data).unwrap());
+
+        let (read, used) = HuffmanCode::<u16>::deserialize(&table).unwrap();
+        assert_eq!(used, table_len);
+        assert_eq!(read.lengths().collect::<Vec<_>>(), code.lengths().collect::<Vec<_>>());
+        assert_eq!(read.decode(&table[used..], data.len()).unwrap(), data);
+        // Dense symbols cost a byte each plus half a byte per length
+        assert!(table_len < code.len() * 2 + 4);
+
+        let empty = HuffmanCode::<u8>::from_data(&[], 15).unwrap();
+        assert_eq!(HuffmanCode::<u8>::deserialize(&empty.serialize()).unwrap().1, 1);
+    }
+
+    #[test]
+    fn test_huffman_code_rejects_bad_tables() {
+        let code = HuffmanCode::from_data(b"hello world", 15).unwrap();
+        let table = code.serialize();
+        for len in 0..table.len() {
+            assert!(HuffmanCode::<u8>::deserialize(&table[..len]).is_err(), "prefix of {} bytes", len);
+        }
+
+        // Symbol 300 does not fit in a u8
+        assert_eq!(
+            HuffmanCode::<u8>::deserialize(&[2, 0xac, 0x02, 0, 0x11]).unwrap_err(),
+            HuffmanError::InvalidTable { offset: 1 }
+        );
+        // Two 1-bit codes and a 2-bit code over-subscribe the code space
+        assert!(matches!(
+            HuffmanCode::<u8>::deserialize(&[3, 0, 0, 0, 0x11, 0x02]),
+            Err(HuffmanError::InvalidTable { .. })
+        ));
+        // A count larger than the input
+        assert!(HuffmanCode::<u8>::deserialize(&[0xff, 0xff, 0x03]).is_err());
+    }
+
+    #[test]
+    fn test_huffman_error_from_inflate_error() {
+        assert_eq!(HuffmanError::from(InflateError::UnexpectedEof { offset: 4 }), HuffmanError::UnexpectedEof { offset: 4 });
+        assert_eq!(
+            HuffmanError::from(InflateError::DistanceTooFar { offset: 9, distance: 5, available: 1 }),
+            HuffmanError::InvalidCode { offset: 9 }
+        );
+    }
+
+    #[test]
+    fn test_canonical_codes_rfc_example() {
+        // Example from RFC 1951, section 3.2.2: ABCDEFGH with lengths (3, 3, 3, 3, 3, 2, 4, 4)
+        let codes = canonical_codes(&[3, 3, 3, 3, 3, 2, 4, 4]);
+        assert_eq!(codes, vec![0b010, 0b011, 0b100, 0b101, 0b110, 0b00, 0b1110, 0b1111]);
+    }
+}
@@ -0,0 +1,506 @@
+//! Random access into gzip files, after zlib's `zran.c`. DEFLATE has no
+//! sync points, but between blocks the whole decoder state is the bit
+//! position plus the last 32 KiB of output. `GzIndex::build` decodes a file
+//! once and records such checkpoints every `span` bytes of output;
+//! `IndexedReader` then reaches any uncompressed offset by resuming from the
+//! nearest checkpoint before it, decoding at most about `span` bytes rather
+//! than everything from the start. Checkpoints can only fall between
+//! blocks, so a file written as a few huge blocks gets few of them.
+//!
+//! An index is usually kept next to the file it describes:
+//!
+//! ```no_run
+//! use std::fs::{self, File};
+//! use std::io::{Read, Seek, SeekFrom};
+//! use gzip::index::{GzIndex, IndexedReader, DEFAULT_SPAN};
+//!
+//! let index = GzIndex::build(File::open("big.log.gz")?, DEFAULT_SPAN)?;
+//! fs::write("big.log.gz.gzi", index.serialize())?;
+//!
+//! let index = GzIndex::deserialize(&fs::read("big.log.gz.gzi")?)?;
+//! let mut reader = IndexedReader::new(File::open("big.log.gz")?, index)?;
+//! reader.seek(SeekFrom::Start(5_000_000_000))?;
+//! let mut line = [0; 200];
+//! reader.read_exact(&mut line)?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::io::{self, Read, Seek, SeekFrom};
+
+use crate::bits::BitReader;
+use crate::crc32::{crc32, Crc32};
+use crate::deflate::{deflate_with_level, CompressionLevel};
+use crate::error::GzipError;
+use crate::gz::{inflate_error, read_trailer, GzDecoder, GzHeader};
+use crate::inflate::Inflater;
+use crate::lz77::WINDOW_SIZE;
+
+/// Output between checkpoints when the caller has no preference
+pub const DEFAULT_SPAN: u64 = 1 << 20;
+
+const MAGIC: &[u8; 8] = b"GZINDEX1";
+
+/// Serialized size of a checkpoint without its window
+const CHECKPOINT_SIZE: usize = 28;
+
+/// Everything needed to resume decoding at a block boundary
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Checkpoint {
+    /// Position of the block in the compressed file, in bits
+    pub bit_offset: u64,
+    /// Uncompressed bytes before the block, over all members
+    pub output_offset: u64,
+    /// CRC-32 of the member's output before the block
+    pub crc: u32,
+    /// Length of the member's output before the block, modulo 2^32
+    pub size: u32,
+    /// The output just before the block, up to 32 KiB
+    pub window: Vec<u8>,
+}
+
+/// Checkpoints into one gzip file, in order of position
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GzIndex {
+    checkpoints: Vec<Checkpoint>,
+    compressed_len: u64,
+    uncompressed_len: u64,
+}
+
+impl GzIndex {
+    /// Decode all of `inner`, checking every member's trailer, and record a
+    /// checkpoint at the first block boundary at least `span` bytes of
+    /// output past the previous one. Smaller spans make seeks cheaper and
+    /// the index bigger, by up to 32 KiB per checkpoint.
+    pub fn build<R: Read>(inner: R, span: u64) -> Result<GzIndex, GzipError> {
+        let mut reader = BitReader::new(inner);
+        let mut checkpoints: Vec<Checkpoint> = Vec::new();
+        let mut total = 0u64;
+        let mut members = 0;
+
+        while members == 0 || !reader.is_at_end() {
+            GzHeader::read_from(&mut reader)?;
+            let mut inflater = Inflater::new();
+            let mut crc = Crc32::new();
+            let mut size = 0u32;
+
+            while !inflater.is_done() {
+                if inflater.at_block_boundary()
+                    && checkpoints.last().is_none_or(|last| total - last.output_offset >= span)
+                {
+                    checkpoints.push(Checkpoint {
+                        bit_offset: reader.bit_offset() as u64,
+                        output_offset: total,
+                        crc: crc.value(),
+                        size,
+                        window: inflater.window().to_vec(),
+                    });
+                }
+                inflater.decode_block(&mut reader).map_err(|err| inflate_error(&mut reader, err))?;
+
+                let output = inflater.output();
+                crc.update(output);
+                size = size.wrapping_add(output.len() as u32);
+                total += output.len() as u64;
+                let n = output.len();
+                inflater.consume(n);
+            }
+            read_trailer(&mut reader, crc.value(), size)?;
+            members += 1;
+        }
+
+        Ok(GzIndex { checkpoints, compressed_len: reader.byte_offset() as u64, uncompressed_len: total })
+    }
+
+    pub fn checkpoints(&self) -> &[Checkpoint] {
+        &self.checkpoints
+    }
+
+    /// Length of the gzip file the index describes
+    pub fn compressed_len(&self) -> u64 {
+        self.compressed_len
+    }
+
+    /// Length of the file's contents, over all members
+    pub fn uncompressed_len(&self) -> u64 {
+        self.uncompressed_len
+    }
+
+    /// The last checkpoint at or before uncompressed `offset`
+    pub fn checkpoint_for(&self, offset: u64) -> &Checkpoint {
+        &self.checkpoints[self.nearest(offset)]
+    }
+
+    fn nearest(&self, offset: u64) -> usize {
+        // The first checkpoint is always at offset 0
+        self.checkpoints.partition_point(|checkpoint| checkpoint.output_offset <= offset) - 1
+    }
+
+    /// The index as bytes for a sidecar file: a magic number, the file's
+    /// lengths, each checkpoint with its window deflated, and a CRC-32 of
+    /// all of that. Integers are little-endian.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&self.compressed_len.to_le_bytes());
+        out.extend_from_slice(&self.uncompressed_len.to_le_bytes());
+        out.extend_from_slice(&(self.checkpoints.len() as u32).to_le_bytes());
+        for checkpoint in &self.checkpoints {
+            let window = deflate_with_level(&checkpoint.window, CompressionLevel::DEFAULT);
+            out.extend_from_slice(&checkpoint.bit_offset.to_le_bytes());
+            out.extend_from_slice(&checkpoint.output_offset.to_le_bytes());
+            out.extend_from_slice(&checkpoint.crc.to_le_bytes());
+            out.extend_from_slice(&checkpoint.size.to_le_bytes());
+            out.extend_from_slice(&(window.len() as u32).to_le_bytes());
+            out.extend_from_slice(&window);
+        }
+        let crc = crc32(&out);
+        out.extend_from_slice(&crc.to_le_bytes());
+        out
+    }
+
+    /// Read back an index written by `serialize`, rejecting any that is
+    /// damaged or inconsistent
+    pub fn deserialize(data: &[u8]) -> Result<GzIndex, GzipError> {
+        if !data.starts_with(MAGIC) || data.len() < MAGIC.len() + 24 {
+            return Err(GzipError::InvalidIndex { offset: 0 });
+        }
+        let body_len = data.len() - 4;
+        if crc32(&data[..body_len]).to_le_bytes() != data[body_len..] {
+            return Err(GzipError::InvalidIndex { offset: body_len });
+        }
+
+        let mut input = Input { data: &data[..body_len], pos: MAGIC.len() };
+        let compressed_len = input.u64()?;
+        let uncompressed_len = input.u64()?;
+        let count = input.u32()? as usize;
+        if count == 0 || count > (body_len - input.pos) / CHECKPOINT_SIZE {
+            return Err(GzipError::InvalidIndex { offset: input.pos - 4 });
+        }
+
+        let mut checkpoints: Vec<Checkpoint> = Vec::with_capacity(count);
+        for _ in 0..count {
+            let start = input.pos;
+            let bit_offset = input.u64()?;
+            let output_offset = input.u64()?;
+            let crc = input.u32()?;
+            let size = input.u32()?;
+            let window_len = input.u32()? as usize;
+            let window = decode_window(input.take(window_len)?)
+                .ok_or(GzipError::InvalidIndex { offset: start + CHECKPOINT_SIZE })?;
+
+            let in_order = match checkpoints.last() {
+                Some(last) => bit_offset > last.bit_offset && output_offset >= last.output_offset,
+                None => output_offset == 0,
+            };
+            if !in_order || bit_offset >= compressed_len * 8 || output_offset > uncompressed_len {
+                return Err(GzipError::InvalidIndex { offset: start });
+            }
+            checkpoints.push(Checkpoint { bit_offset, output_offset, crc, size, window });
+        }
+        if input.pos != body_len {
+            return Err(GzipError::InvalidIndex { offset: input.pos });
+        }
+
+        Ok(GzIndex { checkpoints, compressed_len, uncompressed_len })
+    }
+}
+
+/// Inflate a stored window, giving up as soon as it grows past 32 KiB so a
+/// crafted index cannot make it allocate without bound
+fn decode_window(data: &[u8]) -> Option<Vec<u8>> {
+    let mut reader = BitReader::new(data);
+    let mut inflater = Inflater::new();
+    while !inflater.is_done() {
+        inflater.decode_more(&mut reader).ok()?;
+        if inflater.output().len() > WINDOW_SIZE {
+            return None;
+        }
+    }
+    Some(inflater.into_output())
+}
+
+/// Cursor over a serialized index
+struct Input<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Input<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], GzipError> {
+        let bytes = self
+            .data
+            .get(self.pos..self.pos.saturating_add(len))
+            .ok_or(GzipError::InvalidIndex { offset: self.pos })?;
+        self.pos += len;
+        Ok(bytes)
+    }
+
+    fn u32(&mut self) -> Result<u32, GzipError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, GzipError> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+}
+
+/// Seekable reader over the contents of an indexed gzip file. Seeking only
+/// records the new position; the next read resumes from the nearest
+/// checkpoint, or keeps decoding forward when that is closer. Member
+/// trailers are still checked whenever decoding runs through them.
+pub struct IndexedReader<R: Read + Seek> {
+    index: GzIndex,
+    /// Decoding from the last checkpoint resumed, once there is one
+    decoder: Option<GzDecoder<R>>,
+    /// The file, while no decoder holds it
+    inner: Option<R>,
+    /// Uncompressed offset the decoder has reached
+    decoded: u64,
+    /// Uncompressed offset the next read starts at
+    position: u64,
+}
+
+impl<R: Read + Seek> IndexedReader<R> {
+    /// Read `inner` through `index`, which must have been built from it
+    pub fn new(mut inner: R, index: GzIndex) -> Result<Self, GzipError> {
+        let actual = inner.seek(SeekFrom::End(0))?;
+        if actual != index.compressed_len {
+            return Err(GzipError::IndexMismatch { expected: index.compressed_len, actual });
+        }
+        Ok(IndexedReader { index, decoder: None, inner: Some(inner), decoded: 0, position: 0 })
+    }
+
+    pub fn index(&self) -> &GzIndex {
+        &self.index
+    }
+
+    pub fn into_inner(self) -> R {
+        match self.decoder {
+            Some(decoder) => decoder.into_inner(),
+            None => self.inner.unwrap(),
+        }
+    }
+
+    /// Start decoding afresh at checkpoint `at`
+    fn resume(&mut self, at: usize) -> io::Result<()> {
+        let mut inner = match self.decoder.take() {
+            Some(decoder) => decoder.into_inner(),
+            None => self.inner.take().unwrap(),
+        };
+        let checkpoint = &self.index.checkpoints[at];
+        let byte = checkpoint.bit_offset / 8;
+        if let Err(err) = inner.seek(SeekFrom::Start(byte)) {
+            self.inner = Some(inner);
+            return Err(err);
+        }
+
+        let mut reader = BitReader::starting_at(inner, byte as usize);
+        if reader.read_bits((checkpoint.bit_offset % 8) as u32).is_none() {
+            self.inner = Some(reader.into_inner());
+            return Err(GzipError::TruncatedStream { offset: byte as usize }.into());
+        }
+        self.decoder = Some(GzDecoder::resume(reader, &checkpoint.window, checkpoint.crc, checkpoint.size));
+        self.decoded = checkpoint.output_offset;
+        Ok(())
+    }
+}
+
+impl<R: Read + Seek> Read for IndexedReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() || self.position > self.index.uncompressed_len {
+            return Ok(0);
+        }
+        // At the end, decode on only if that finishes checking a trailer
+        if self.position == self.index.uncompressed_len && self.decoded != self.position {
+            return Ok(0);
+        }
+
+        let nearest = self.index.nearest(self.position);
+        if self.decoder.is_none()
+            || self.position < self.decoded
+            || self.index.checkpoints[nearest].output_offset > self.decoded
+        {
+            self.resume(nearest)?;
+        }
+        let decoder = self.decoder.as_mut().unwrap();
+
+        let mut scratch = [0u8; 8192];
+        while self.decoded < self.position {
+            let want = (self.position - self.decoded).min(scratch.len() as u64) as usize;
+            let n = decoder.read(&mut scratch[..want])?;
+            if n == 0 {
+                return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file is shorter than its index"));
+            }
+            self.decoded += n as u64;
+        }
+
+        let n = decoder.read(buf)?;
+        if n == 0 && self.position < self.index.uncompressed_len {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file is shorter than its index"));
+        }
+        self.decoded += n as u64;
+        self.position += n as u64;
+        Ok(n)
+    }
+}
+
+impl<R: Read + Seek> Seek for IndexedReader<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let target = match pos {
+            SeekFrom::Start(offset) => Some(offset),
+            SeekFrom::End(delta) => self.index.uncompressed_len.checked_add_signed(delta),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+        };
+        self.position = target
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative or overflowing position"))?;
+        Ok(self.position)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+    use crate::deflate::write_huffman_blocks;
+    use crate::gz::{gzip_with_level, GzEncoder};
+    use crate::lz77::Token;
+    use crate::test_data::text;
+    use std::io::{Cursor, Write};
+
+    /// Three members compressed three ways, and their concatenated contents.
+    /// The first is flushed often, as a log writer would, so it has a block
+    /// boundary every 20 KB.
+    fn multi_member() -> (Vec<u8>, Vec<u8>) {
+        let parts = [text(300_000, 1), text(70_000, 2), text(200_000, 3)];
+        let mut encoder = GzEncoder::new(Vec::new(), CompressionLevel::DEFAULT);
+        for piece in parts[0].chunks(20_000) {
+            encoder.write_all(piece).unwrap();
+            encoder.flush().unwrap();
+        }
+        let mut compressed = encoder.finish().unwrap();
+        compressed.extend(gzip_with_level(&parts[1], CompressionLevel::NONE));
+        compressed.extend(gzip_with_level(&parts[2], CompressionLevel::FASTEST));
+        (compressed, parts.concat())
+    }
+
+    fn read_at<R: Read + Seek>(reader: &mut IndexedReader<R>, offset: u64, len: usize) -> Vec<u8> {
+        reader.seek(SeekFrom::Start(offset)).unwrap();
+        let mut out = Vec::new();
+        reader.take(len as u64).read_to_end(&mut out).unwrap();
+        out
+    }
+
+    #[test]
+    fn test_build() {
+        let (compressed, data) = multi_member();
+        let index = GzIndex::build(&compressed[..], 32 * 1024).unwrap();
+
+        assert_eq!(index.compressed_len(), compressed.len() as u64);
+        assert_eq!(index.uncompressed_len(), data.len() as u64);
+        assert!(index.checkpoints().len() >= 8, "{}", index.checkpoints().len());
+        for pair in index.checkpoints().windows(2) {
+            assert!(pair[1].output_offset - pair[0].output_offset >= 32 * 1024);
+        }
+        for checkpoint in index.checkpoints() {
+            let start = checkpoint.output_offset as usize;
+            assert_eq!(checkpoint.window, data[start.saturating_sub(WINDOW_SIZE)..start].to_vec());
+        }
+
+        // One checkpoint is always taken, at the start
+        let index = GzIndex::build(&compressed[..], u64::MAX).unwrap();
+        assert_eq!(index.checkpoints().len(), 1);
+    }
+
+    #[test]
+    fn test_seeks_anywhere() {
+        let (compressed, data) = multi_member();
+        let index = GzIndex::build(&compressed[..], 64 * 1024).unwrap();
+        let mut reader = IndexedReader::new(Cursor::new(&compressed), index).unwrap();
+
+        // Forwards, backwards, across member boundaries and up to the end
+        let mut state = 7u64;
+        for _ in 0..40 {
+            state = state.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+            let offset = (state >> 33) % data.len() as u64;
+            let len = (state & 0xffff) as usize;
+            let end = (offset as usize + len).min(data.len());
+            assert!(read_at(&mut reader, offset, len) == data[offset as usize..end], "at {}", offset);
+        }
+        let boundary = 300_000;
+        assert!(read_at(&mut reader, boundary - 10, 20) == data[boundary as usize - 10..][..20]);
+
+        reader.seek(SeekFrom::End(-5)).unwrap();
+        let mut tail = Vec::new();
+        reader.read_to_end(&mut tail).unwrap();
+        assert_eq!(tail, &data[data.len() - 5..]);
+        assert_eq!(reader.seek(SeekFrom::Current(-3)).unwrap(), data.len() as u64 - 3);
+        assert!(reader.seek(SeekFrom::Current(-(data.len() as i64))).is_err());
+    }
+
+    #[test]
+    fn test_serialize_round_trip() {
+        let (compressed, _) = multi_member();
+        let index = GzIndex::build(&compressed[..], 100_000).unwrap();
+        let bytes = index.serialize();
+
+        assert_eq!(GzIndex::deserialize(&bytes).unwrap(), index);
+        // Windows are stored compressed
+        let windows: usize = index.checkpoints().iter().map(|checkpoint| checkpoint.window.len()).sum();
+        assert!(bytes.len() < windows / 2, "{} bytes for {} of windows", bytes.len(), windows);
+    }
+
+    #[test]
+    fn test_rejects_bad_index() {
+        let (compressed, _) = multi_member();
+        let bytes = GzIndex::build(&compressed[..], 100_000).unwrap().serialize();
+
+        assert_eq!(GzIndex::deserialize(b"GZINDEX"), Err(GzipError::InvalidIndex { offset: 0 }));
+        assert_eq!(GzIndex::deserialize(&bytes[..bytes.len() - 1]), Err(GzipError::InvalidIndex { offset: bytes.len() - 5 }));
+        let mut damaged = bytes.clone();
+        damaged[40] ^= 1;
+        assert!(matches!(GzIndex::deserialize(&damaged), Err(GzipError::InvalidIndex { .. })));
+
+        // A window that inflates far past 32 KiB is refused without being
+        // decoded in full
+        let mut oversized = bytes[..MAGIC.len() + 20].to_vec();
+        oversized.extend_from_slice(&[0; 24]);
+        let mut tokens = vec![Token::Literal(0)];
+        tokens.resize(1 << 20, Token::Reference { length: 258, distance: 1 });
+        let mut writer = BitWriter::new();
+        write_huffman_blocks(&mut writer, &tokens, false);
+        let window = writer.finish();
+        oversized.extend_from_slice(&(window.len() as u32).to_le_bytes());
+        oversized.extend_from_slice(&window);
+        oversized[MAGIC.len() + 16..][..4].copy_from_slice(&1u32.to_le_bytes());
+        let crc = crc32(&oversized);
+        oversized.extend_from_slice(&crc.to_le_bytes());
+        assert_eq!(
+            GzIndex::deserialize(&oversized),
+            Err(GzipError::InvalidIndex { offset: MAGIC.len() + 20 + CHECKPOINT_SIZE })
+        );
+
+        // An index of some other file
+        let other = gzip_with_level(&text(1000, 4), CompressionLevel::DEFAULT);
+        let index = GzIndex::deserialize(&bytes).unwrap();
+        assert_eq!(
+            IndexedReader::new(Cursor::new(&other), index).err(),
+            Some(GzipError::IndexMismatch { expected: compressed.len() as u64, actual: other.len() as u64 })
+        );
+    }
+
+    #[test]
+    fn test_checks_trailers() {
+        let (mut compressed, data) = multi_member();
+        let index = GzIndex::build(&compressed[..], 64 * 1024).unwrap();
+        let trailer = compressed.len() - 8;
+        compressed[trailer] ^= 1;
+
+        // The CRC carried in the checkpoint still catches the damage
+        let mut reader = IndexedReader::new(Cursor::new(&compressed), index).unwrap();
+        assert!(read_at(&mut reader, 10_000, 1000) == data[10_000..11_000]);
+        reader.seek(SeekFrom::End(-1000)).unwrap();
+        let err = reader.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(GzipError::from(err), GzipError::ChecksumMismatch { offset, .. } if offset == trailer));
+
+        assert!(matches!(GzIndex::build(&compressed[..], DEFAULT_SPAN), Err(GzipError::ChecksumMismatch { .. })));
+    }
+}
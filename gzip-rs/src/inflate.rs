@@ -0,0 +1,549 @@
+use std::fmt;
+use std::io::Read;
+
+use crate::bits::{reverse_bits, BitReader};
+use crate::deflate::{
+    fixed_distance_lengths, fixed_litlen_lengths, CODE_LENGTH_ORDER, DISTANCE_BASE,
+    DISTANCE_EXTRA, END_OF_BLOCK, LENGTH_BASE, LENGTH_EXTRA, MAX_CODE_BITS,
+    NUM_CODE_LENGTH_SYMBOLS,
+};
+use crate::error::GzipError;
+use crate::lz77::WINDOW_SIZE;
+use crate::huffman::canonical_codes;
+use crate::limits::CHECK_INTERVAL;
+
+/// Reasons a DEFLATE stream can fail to decode. Offsets are byte positions
+/// in the compressed input where the problem was detected. Callers see
+/// these as `GzipError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum InflateError {
+    /// The input ended in the middle of a block
+    UnexpectedEof { offset: usize },
+    /// BTYPE 11 is reserved
+    InvalidBlockType { offset: usize },
+    /// NLEN of a stored block is not the complement of LEN
+    StoredLengthMismatch { offset: usize },
+    /// A dynamic block header describes an impossible Huffman code
+    InvalidCodeLengths { offset: usize },
+    /// The bits do not decode to a valid symbol
+    InvalidSymbol { offset: usize },
+    /// A back-reference points before the start of the output
+    DistanceTooFar { offset: usize, distance: usize, available: usize },
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InflateError::UnexpectedEof { offset } => {
+                write!(f, "unexpected end of stream at byte {}", offset)
+            }
+            InflateError::InvalidBlockType { offset } => {
+                write!(f, "invalid block type at byte {}", offset)
+            }
+            InflateError::StoredLengthMismatch { offset } => {
+                write!(f, "stored block length does not match its complement at byte {}", offset)
+            }
+            InflateError::InvalidCodeLengths { offset } => {
+                write!(f, "invalid Huffman code lengths at byte {}", offset)
+            }
+            InflateError::InvalidSymbol { offset } => {
+                write!(f, "invalid Huffman code at byte {}", offset)
+            }
+            InflateError::DistanceTooFar { offset, distance, available } => write!(
+                f,
+                "distance {} too far back at byte {} (only {} bytes available)",
+                distance, offset, available
+            ),
+        }
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+/// Table-driven Huffman decoder: every possible `max_length`-bit input maps
+/// straight to the symbol whose code it starts with
+#[derive(Debug, Clone)]
+pub(crate) struct Decoder {
+    /// (symbol, code length) indexed by the next `max_length` input bits;
+    /// a length of 0 marks bit patterns that are not a code
+    table: Vec<(u16, u8)>,
+    max_length: u8,
+}
+
+impl Decoder {
+    /// Build a decoder from code lengths, or `None` if they over-subscribe the
+    /// code space. Incomplete codes are only accepted when `allow_incomplete`
+    /// is set and there is a single one-bit code (the case zlib tolerates).
+    pub(crate) fn from_lengths(lengths: &[u8], allow_incomplete: bool) -> Option<Self> {
+        let max_length = lengths.iter().copied().max().unwrap_or(0);
+
+        // Measure the code space left over (the Kraft sum, scaled to integers)
+        let mut left: i64 = 1 << MAX_CODE_BITS;
+        for &len in lengths.iter().filter(|&&len| len > 0) {
+            left -= 1 << (MAX_CODE_BITS - len);
+        }
+        if left < 0 {
+            return None;
+        }
+        if left > 0 && max_length > 0 && !(allow_incomplete && max_length == 1) {
+            return None;
+        }
+
+        let codes = canonical_codes(lengths);
+        let mut table = vec![(0u16, 0u8); 1 << max_length];
+        for (symbol, (&len, &code)) in lengths.iter().zip(&codes).enumerate() {
+            if len == 0 {
+                continue;
+            }
+            // The code is read LSB first, so it occupies the low bits of the
+            // table index and every combination of the high bits maps to it
+            let reversed = reverse_bits(code, len) as usize;
+            for index in (reversed..table.len()).step_by(1 << len) {
+                table[index] = (symbol as u16, len);
+            }
+        }
+
+        Some(Decoder { table, max_length })
+    }
+
+    pub(crate) fn decode<R: Read>(&self, reader: &mut BitReader<R>) -> Result<u16, InflateError> {
+        let (symbol, len) = self.table[reader.peek_bits(self.max_length as u32) as usize];
+        let offset = reader.byte_offset();
+
+        if len == 0 {
+            // Either padding bits past the end of input, or a genuinely bad code
+            return Err(if reader.buffered_bits() < self.max_length as u32 {
+                InflateError::UnexpectedEof { offset }
+            } else {
+                InflateError::InvalidSymbol { offset }
+            });
+        }
+        if reader.buffered_bits() < len as u32 {
+            return Err(InflateError::UnexpectedEof { offset });
+        }
+
+        reader.consume(len as u32);
+        Ok(symbol)
+    }
+}
+
+pub(crate) fn read_bits<R: Read>(reader: &mut BitReader<R>, count: u32) -> Result<u32, InflateError> {
+    let offset = reader.byte_offset();
+    reader.read_bits(count).ok_or(InflateError::UnexpectedEof { offset })
+}
+
+/// Read the code length tables at the start of a dynamic block (RFC 1951,
+/// section 3.2.7), returning the literal/length and distance code lengths
+pub(crate) fn read_code_lengths<R: Read>(
+    reader: &mut BitReader<R>,
+) -> Result<(Vec<u8>, Vec<u8>), InflateError> {
+    let offset = reader.byte_offset();
+    let num_litlen = read_bits(reader, 5)? as usize + 257;
+    let num_distance = read_bits(reader, 5)? as usize + 1;
+    let num_code_length = read_bits(reader, 4)? as usize + 4;
+
+    if num_litlen > 286 || num_distance > 30 {
+        return Err(InflateError::InvalidCodeLengths { offset });
+    }
+
+    let mut cl_lengths = [0u8; NUM_CODE_LENGTH_SYMBOLS];
+    for &symbol in &CODE_LENGTH_ORDER[..num_code_length] {
+        cl_lengths[symbol] = read_bits(reader, 3)? as u8;
+    }
+    let cl_decoder = Decoder::from_lengths(&cl_lengths, false)
+        .ok_or(InflateError::InvalidCodeLengths { offset })?;
+
+    // Literal/length and distance lengths form one sequence; repeats may cross between them
+    let mut lengths = Vec::with_capacity(num_litlen + num_distance);
+    while lengths.len() < num_litlen + num_distance {
+        let offset = reader.byte_offset();
+        let (value, repeat) = match cl_decoder.decode(reader)? {
+            len @ 0..=15 => (len as u8, 1),
+            16 => {
+                let prev = *lengths.last().ok_or(InflateError::InvalidCodeLengths { offset })?;
+                (prev, 3 + read_bits(reader, 2)? as usize)
+            }
+            17 => (0, 3 + read_bits(reader, 3)? as usize),
+            _ => (0, 11 + read_bits(reader, 7)? as usize),
+        };
+
+        if lengths.len() + repeat > num_litlen + num_distance {
+            return Err(InflateError::InvalidCodeLengths { offset });
+        }
+        lengths.extend(std::iter::repeat_n(value, repeat));
+    }
+
+    // A block that cannot end is as broken as one that over-subscribes its code
+    if lengths[END_OF_BLOCK] == 0 {
+        return Err(InflateError::InvalidCodeLengths { offset });
+    }
+
+    let distance = lengths.split_off(num_litlen);
+    Ok((lengths, distance))
+}
+
+/// Read a dynamic block header and build its two decoders
+pub(crate) fn read_dynamic_tables<R: Read>(
+    reader: &mut BitReader<R>,
+) -> Result<(Decoder, Decoder), InflateError> {
+    let offset = reader.byte_offset();
+    let (litlen, distance) = read_code_lengths(reader)?;
+    let litlen = Decoder::from_lengths(&litlen, true)
+        .ok_or(InflateError::InvalidCodeLengths { offset })?;
+    let distance = Decoder::from_lengths(&distance, true)
+        .ok_or(InflateError::InvalidCodeLengths { offset })?;
+    Ok((litlen, distance))
+}
+
+/// Where the decoder is within the stream
+enum Block {
+    /// Next up is a 3-bit block header
+    Header,
+    /// Inside a stored block with this many bytes left to copy
+    Stored { remaining: usize },
+    /// Inside a Huffman-coded block
+    Huffman { litlen: Decoder, distance: Decoder },
+    /// The final block has ended
+    Done,
+}
+
+/// Output kept once drained, so back-references always have a full window
+/// to reach into. Compaction waits until twice that much has piled up.
+const KEEP: usize = WINDOW_SIZE;
+
+/// Resumable DEFLATE decoder. Input is pulled from a `BitReader` as needed;
+/// output collects in a window the caller drains with `output`/`consume`.
+/// Only the last 32 KiB of drained output is retained, so memory stays
+/// bounded however long the stream is.
+pub(crate) struct Inflater {
+    window: Vec<u8>,
+    /// Bytes at the front of `window` the caller has already taken
+    drained: usize,
+    block: Block,
+    final_block: bool,
+}
+
+impl Inflater {
+    pub(crate) fn new() -> Self {
+        Inflater { window: Vec::new(), drained: 0, block: Block::Header, final_block: false }
+    }
+
+    /// A decoder whose back-references can reach into `dictionary` as if it
+    /// had already been decoded (and consumed)
+    pub(crate) fn with_dictionary(dictionary: &[u8]) -> Self {
+        let window = dictionary[dictionary.len().saturating_sub(KEEP)..].to_vec();
+        Inflater { drained: window.len(), window, ..Self::new() }
+    }
+
+    /// Decoded bytes not yet consumed
+    pub(crate) fn output(&self) -> &[u8] {
+        &self.window[self.drained..]
+    }
+
+    /// Mark `n` bytes of `output()` as taken
+    pub(crate) fn consume(&mut self, n: usize) {
+        debug_assert!(self.drained + n <= self.window.len());
+        self.drained += n;
+    }
+
+    /// Hand over everything decoded, for one-shot decoding
+    pub(crate) fn into_output(mut self) -> Vec<u8> {
+        self.window.drain(..self.drained);
+        self.window
+    }
+
+    /// True once the final block has been fully decoded
+    pub(crate) fn is_done(&self) -> bool {
+        matches!(self.block, Block::Done)
+    }
+
+    /// True between blocks, where the bit position and the last 32 KiB of
+    /// output are all the state there is: a decoder given that window can
+    /// pick up from here
+    pub(crate) fn at_block_boundary(&self) -> bool {
+        matches!(self.block, Block::Header)
+    }
+
+    /// The most recent output, up to the 32 KiB back-references can reach
+    pub(crate) fn window(&self) -> &[u8] {
+        &self.window[self.window.len().saturating_sub(KEEP)..]
+    }
+
+    /// Drop drained output that is no longer reachable by back-references
+    fn compact(&mut self) {
+        if self.drained > 2 * KEEP {
+            let cut = self.drained.min(self.window.len() - KEEP);
+            self.window.drain(..cut);
+            self.drained -= cut;
+        }
+    }
+
+    /// Decode until at least `want` bytes of output are waiting or the stream
+    /// ends. Also stops between blocks when some output is ready and no more
+    /// input is buffered, so data up to a sync flush can be handed out
+    /// before waiting on input that may not have been written yet.
+    pub(crate) fn fill<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+        want: usize,
+    ) -> Result<(), InflateError> {
+        self.run(reader, want, true, false)
+    }
+
+    /// Decode the rest of the stream in one go
+    pub(crate) fn decode_to_end<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+    ) -> Result<(), InflateError> {
+        self.run(reader, usize::MAX, false, false)
+    }
+
+    /// Decode about `CHECK_INTERVAL` more bytes without consuming any, for
+    /// one-shot decoding that checks limits as the output grows
+    pub(crate) fn decode_more<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+    ) -> Result<(), InflateError> {
+        let want = self.output().len() + CHECK_INTERVAL;
+        self.run(reader, want, false, false)
+    }
+
+    /// Decode to the end of the current block, or the next one when between
+    /// blocks, stopping early once `CHECK_INTERVAL` bytes are waiting
+    pub(crate) fn decode_block<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+    ) -> Result<(), InflateError> {
+        self.run(reader, CHECK_INTERVAL, false, true)
+    }
+
+    fn run<R: Read>(
+        &mut self,
+        reader: &mut BitReader<R>,
+        want: usize,
+        stop_when_starved: bool,
+        stop_at_block_end: bool,
+    ) -> Result<(), InflateError> {
+        self.compact();
+        // Inside a block already, the next header is where it ends
+        let mut in_block = !self.at_block_boundary();
+
+        while self.window.len() - self.drained < want {
+            match &self.block {
+                Block::Done => return Ok(()),
+                Block::Header if stop_at_block_end && in_block => return Ok(()),
+                Block::Header
+                    if stop_when_starved
+                        && self.window.len() > self.drained
+                        && !reader.has_buffered_input() =>
+                {
+                    return Ok(());
+                }
+                Block::Header => {
+                    in_block = true;
+                    self.read_block_header(reader)?;
+                }
+                Block::Stored { remaining } => {
+                    let remaining = *remaining;
+                    let wanted = remaining.min(want - (self.window.len() - self.drained));
+                    let copied = reader.read_aligned(&mut self.window, wanted);
+                    if copied == 0 {
+                        return Err(InflateError::UnexpectedEof { offset: reader.byte_offset() });
+                    }
+                    self.block = Block::Stored { remaining: remaining - copied };
+                    if remaining == copied {
+                        self.end_block();
+                    }
+                }
+                Block::Huffman { litlen, distance } => {
+                    if !decode_symbol(reader, &mut self.window, litlen, distance)? {
+                        self.end_block();
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    fn end_block(&mut self) {
+        self.block = if self.final_block { Block::Done } else { Block::Header };
+    }
+
+    fn read_block_header<R: Read>(&mut self, reader: &mut BitReader<R>) -> Result<(), InflateError> {
+        let offset = reader.byte_offset();
+        self.final_block = read_bits(reader, 1)? == 1;
+
+        self.block = match read_bits(reader, 2)? {
+            0b00 => {
+                reader.align_to_byte();
+                let offset = reader.byte_offset();
+                let len = read_bits(reader, 16)?;
+                let nlen = read_bits(reader, 16)?;
+                if len != !nlen & 0xffff {
+                    return Err(InflateError::StoredLengthMismatch { offset });
+                }
+                if len == 0 {
+                    self.end_block();
+                    return Ok(());
+                }
+                Block::Stored { remaining: len as usize }
+            }
+            0b01 => Block::Huffman {
+                litlen: Decoder::from_lengths(&fixed_litlen_lengths(), false).unwrap(),
+                distance: Decoder::from_lengths(&fixed_distance_lengths(), false).unwrap(),
+            },
+            0b10 => {
+                let (litlen, distance) = read_dynamic_tables(reader)?;
+                Block::Huffman { litlen, distance }
+            }
+            _ => return Err(InflateError::InvalidBlockType { offset }),
+        };
+
+        Ok(())
+    }
+}
+
+/// Decode one literal/length symbol (and its match, if any) into `window`.
+/// Returns false at the end-of-block symbol.
+fn decode_symbol<R: Read>(
+    reader: &mut BitReader<R>,
+    window: &mut Vec<u8>,
+    litlen: &Decoder,
+    distance: &Decoder,
+) -> Result<bool, InflateError> {
+    let offset = reader.byte_offset();
+    let symbol = litlen.decode(reader)? as usize;
+
+    if symbol < END_OF_BLOCK {
+        window.push(symbol as u8);
+        return Ok(true);
+    }
+    if symbol == END_OF_BLOCK {
+        return Ok(false);
+    }
+
+    let len_idx = symbol - 257;
+    if len_idx >= LENGTH_BASE.len() {
+        return Err(InflateError::InvalidSymbol { offset });
+    }
+    let length = LENGTH_BASE[len_idx] as usize
+        + read_bits(reader, LENGTH_EXTRA[len_idx] as u32)? as usize;
+
+    let offset = reader.byte_offset();
+    let dist_idx = distance.decode(reader)? as usize;
+    if dist_idx >= DISTANCE_BASE.len() {
+        return Err(InflateError::InvalidSymbol { offset });
+    }
+    let dist = DISTANCE_BASE[dist_idx] as usize
+        + read_bits(reader, DISTANCE_EXTRA[dist_idx] as u32)? as usize;
+
+    // The window always holds the last 32 KiB, so only a reference before
+    // the start of the stream can fail here
+    if dist > window.len() {
+        return Err(InflateError::DistanceTooFar { offset, distance: dist, available: window.len() });
+    }
+
+    // Copy byte by byte: the source may overlap the bytes being written
+    let start = window.len() - dist;
+    for i in 0..length {
+        window.push(window[start + i]);
+    }
+
+    Ok(true)
+}
+
+/// Decompress a raw DEFLATE stream (RFC 1951)
+pub fn inflate(data: &[u8]) -> Result<Vec<u8>, GzipError> {
+    let mut reader = BitReader::new(data);
+    let mut inflater = Inflater::new();
+    inflater.decode_to_end(&mut reader)?;
+    Ok(inflater.into_output())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::bits::BitWriter;
+    use crate::deflate::{deflate, deflate_with_level, CompressionLevel};
+
+    #[test]
+    fn test_round_trip() {
+        let inputs: Vec<Vec<u8>> = vec![
+            b"".to_vec(),
+            b"a".to_vec(),
+            b"Hello, World! Hello, World! This is a test. Hello, World!".to_vec(),
+            b"abc".repeat(30_000),
+            (0..70_000u32).map(|i| (i.wrapping_mul(2_654_435_761) >> 24) as u8).collect(),
+        ];
+
+        for data in inputs {
+            for level in 0..=9 {
+                let level = CompressionLevel::new(level).unwrap();
+                assert_eq!(inflate(&deflate_with_level(&data, level)).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_decodes_zlib_output() {
+        // zlib.compressobj(9, zlib.DEFLATED, -15) over "hello hello hello hello\n"
+        let compressed = [0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00];
+        assert_eq!(inflate(&compressed).unwrap(), b"hello hello hello hello\n");
+    }
+
+    #[test]
+    fn test_streaming_output_stays_bounded() {
+        let data: Vec<u8> = (0..2_000_000u32).map(|i| (i % 251) as u8 ^ (i / 4096) as u8).collect();
+        let compressed = deflate(&data);
+
+        let mut reader = BitReader::new(&compressed[..]);
+        let mut inflater = Inflater::new();
+        let mut decoded = Vec::new();
+        while !inflater.is_done() || !inflater.output().is_empty() {
+            inflater.fill(&mut reader, 4096).unwrap();
+            decoded.extend_from_slice(inflater.output());
+            inflater.consume(inflater.output().len());
+            assert!(inflater.window.len() <= 2 * KEEP + 4096 + 258);
+        }
+
+        assert_eq!(decoded, data);
+    }
+
+    #[test]
+    fn test_invalid_block_type() {
+        assert_eq!(inflate(&[0b111]), Err(GzipError::BadBlockType { offset: 0 }));
+    }
+
+    #[test]
+    fn test_stored_length_mismatch() {
+        assert_eq!(
+            inflate(&[0x01, 0x05, 0x00, 0x00, 0x00]),
+            Err(GzipError::StoredLengthMismatch { offset: 1 })
+        );
+    }
+
+    #[test]
+    fn test_truncated_stream() {
+        let compressed = deflate(&b"some text that gets cut short".repeat(10));
+        let truncated = &compressed[..compressed.len() - 2];
+
+        assert!(matches!(inflate(truncated), Err(GzipError::TruncatedStream { .. })));
+    }
+
+    #[test]
+    fn test_distance_too_far() {
+        // Fixed block whose first symbol is a length-3 match at distance 1
+        let mut writer = BitWriter::new();
+        writer.write_bits(1, 1);
+        writer.write_bits(0b01, 2);
+        writer.write_code(0b000_0001, 7);
+        writer.write_code(0b00000, 5);
+        let compressed = writer.finish();
+
+        assert_eq!(
+            inflate(&compressed),
+            Err(GzipError::DistanceTooFar { offset: 1, distance: 1, available: 0 })
+        );
+    }
+}
@@ -0,0 +1,47 @@
+//! LZ77 + Huffman coding, the two halves of DEFLATE (RFC 1951), and the
+//! zlib (RFC 1950) and gzip (RFC 1952) formats built on top of it. Every
+//! decoder reports bad data as a `GzipError`; see `error`.
+
+pub mod adler32;
+pub mod analyze;
+pub mod bits;
+pub mod crc32;
+pub mod deflate;
+pub mod entropy;
+pub mod error;
+pub mod gz;
+pub mod huffman;
+pub mod index;
+pub mod inflate;
+pub mod limits;
+pub mod lz77;
+pub mod optimal;
+pub mod parallel;
+pub mod range_coder;
+mod split;
+pub mod tar;
+#[cfg(test)]
+mod test_data;
+pub mod zlib;
+
+use huffman::{huffman_encode, HuffmanCodes};
+use lz77::{lz77_compress, Token, LOOKAHEAD_SIZE, WINDOW_SIZE};
+
+pub use deflate::{deflate, deflate_ultra, deflate_with_dictionary, deflate_with_level, CompressionLevel, Deflater};
+pub use error::{GzipError, HeaderError};
+pub use gz::{gunzip, gunzip_with_limits, gzip, gzip_ultra, gzip_with_level, GzDecoder, GzEncoder};
+pub use huffman::{HuffmanCode, HuffmanError};
+pub use inflate::inflate;
+pub use limits::{LimitError, Limits};
+pub use parallel::{gzip_parallel, ParallelGzEncoder};
+pub use zlib::{zlib_compress, zlib_decompress, zlib_decompress_with_limits, ZlibDecoder, ZlibEncoder};
+
+// Compress data using LZ77 + Huffman (simplified DEFLATE)
+pub fn compress(data: &[u8]) -> Result<(String, HuffmanCodes, Vec<Token>), GzipError> {
+    if data.is_empty() {
+        return Err(GzipError::EmptyInput);
+    }
+    let tokens = lz77_compress(data, WINDOW_SIZE, LOOKAHEAD_SIZE);
+    let (encoded, codes) = huffman_encode(&tokens);
+    Ok((encoded, codes, tokens))
+}
@@ -0,0 +1,102 @@
+//! Guardrails for decompressing untrusted input. A few hundred bytes of
+//! DEFLATE can expand to gigabytes, so decoders given `Limits` stop with a
+//! `LimitError` as soon as a limit is crossed instead of allocating on.
+
+use std::fmt;
+
+/// Caps on what a decoder will produce. Every limit is off by default.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Most bytes of decompressed output, over all members
+    pub max_output: Option<u64>,
+    /// Most bytes of output per byte of input read so far. DEFLATE cannot
+    /// exceed about 1032:1, but long runs of one byte get close to that
+    /// legitimately, so set this well above the ratios expected in practice.
+    pub max_ratio: Option<u64>,
+    /// Most gzip members in one file; zlib streams always have one
+    pub max_members: Option<usize>,
+}
+
+/// Which limit a decoder ran into
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LimitError {
+    /// The output grew past `Limits::max_output`
+    OutputTooLarge { limit: u64 },
+    /// `output` bytes were produced from only `input` bytes
+    RatioTooHigh { limit: u64, input: u64, output: u64 },
+    /// The file holds more than `Limits::max_members` members
+    TooManyMembers { limit: usize },
+}
+
+impl fmt::Display for LimitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LimitError::OutputTooLarge { limit } => {
+                write!(f, "decompressed size exceeds the limit of {} bytes", limit)
+            }
+            LimitError::RatioTooHigh { limit, input, output } => write!(
+                f,
+                "expansion ratio exceeds the limit of {}:1 ({} bytes from {})",
+                limit, output, input
+            ),
+            LimitError::TooManyMembers { limit } => {
+                write!(f, "more than {} members", limit)
+            }
+        }
+    }
+}
+
+impl std::error::Error for LimitError {}
+
+/// Output decoded between limit checks by one-shot decoders; the most a
+/// decoder can hold beyond `max_output` before noticing
+pub(crate) const CHECK_INTERVAL: usize = 64 * 1024;
+
+impl Limits {
+    /// No limits at all
+    pub const NONE: Limits = Limits { max_output: None, max_ratio: None, max_members: None };
+
+    /// Check that `output` bytes decoded from `input` bytes are allowed
+    pub(crate) fn check_output(&self, input: u64, output: u64) -> Result<(), LimitError> {
+        match (self.max_output, self.max_ratio) {
+            (Some(limit), _) if output > limit => Err(LimitError::OutputTooLarge { limit }),
+            (_, Some(limit)) if output > input.saturating_mul(limit) => {
+                Err(LimitError::RatioTooHigh { limit, input, output })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Check that another member may follow the `members` already decoded
+    pub(crate) fn check_members(&self, members: usize) -> Result<(), LimitError> {
+        match self.max_members {
+            Some(limit) if members >= limit => Err(LimitError::TooManyMembers { limit }),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_is_unlimited() {
+        assert_eq!(Limits::default(), Limits::NONE);
+        assert_eq!(Limits::NONE.check_output(1, u64::MAX), Ok(()));
+        assert_eq!(Limits::NONE.check_members(usize::MAX), Ok(()));
+    }
+
+    #[test]
+    fn test_checks() {
+        let limits = Limits { max_output: Some(1000), max_ratio: Some(10), max_members: Some(2) };
+        assert_eq!(limits.check_output(100, 1000), Ok(()));
+        assert_eq!(limits.check_output(200, 1001), Err(LimitError::OutputTooLarge { limit: 1000 }));
+        assert_eq!(
+            limits.check_output(50, 501),
+            Err(LimitError::RatioTooHigh { limit: 10, input: 50, output: 501 })
+        );
+        assert_eq!(limits.check_members(1), Ok(()));
+        assert_eq!(limits.check_members(2), Err(LimitError::TooManyMembers { limit: 2 }));
+    }
+}
@@ -0,0 +1,449 @@
+use crate::error::GzipError;
+
+/// 32KiB, specified in RFC 1951
+/// Powers of 2 are efficient for bitwise operations and memory
+/// addressing. 32KB was chosen as a good balance in the 1990s
+/// when DEFLATE was designed.
+///
+/// To illustrate:
+/// - Larger window = better compression (can find matches farther back)
+/// - Smaller window = less memory usage and faster compression
+pub const WINDOW_SIZE: usize = 32_768;
+
+/// How far ahead you look when trying to find matches
+/// - Standard DEFLATE: 258 bytes lookahead (RFC 1951)
+/// - Smaller lookahead = faster but finds shorter matches
+/// - Larger lookahead = slower but can find longer matches
+///
+/// Examples:
+///
+/// ```
+/// # use gzip::lz77::lz77_compress;
+/// # let data = b"Hello, World! Hello, World!";
+/// // Very fast, minimal compression (small window + short matches)
+/// lz77_compress(data, 4096, 32);
+///
+/// // Balanced (medium window)
+/// lz77_compress(data, 8192, 128);
+///
+/// // Standard DEFLATE (what you should normally use)
+/// lz77_compress(data, 32768, 258);
+/// ```
+pub const LOOKAHEAD_SIZE: usize = 258;
+
+// LZ77 Token: either a literal byte or a (length, distance) pair
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Token {
+    Literal(u8),
+    Reference { length: usize, distance: usize },
+}
+
+/// Shortest match worth encoding as a reference
+pub const MIN_MATCH: usize = 3;
+
+/// Default number of chain links followed per position (zlib's maximum)
+pub const DEFAULT_MAX_CHAIN: usize = 4096;
+
+/// Number of bits in the 3-byte hash that indexes the chain heads
+const HASH_BITS: u32 = 15;
+
+/// Marks an empty hash bucket or the end of a chain
+const NIL: usize = usize::MAX;
+
+/// Lazy matching drops 3-byte matches farther back than this: their
+/// distance code usually costs more than the three literals would
+const TOO_FAR: usize = 4096;
+
+/// Tuning knobs for the match finder
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MatchConfig {
+    /// How far back matches may start
+    pub window_size: usize,
+    /// Longest match that will be reported
+    pub lookahead_size: usize,
+    /// Stop searching after following this many chain links
+    pub max_chain: usize,
+    /// Stop searching as soon as a match at least this long is found
+    pub nice_length: usize,
+    /// Defer each match by one byte in case the next position has a longer one
+    pub lazy: bool,
+    /// Lazy: skip the deferred search when the current match is already this long.
+    /// Greedy: only matches up to this length have their positions hashed.
+    pub max_lazy: usize,
+    /// Lazy: search only a quarter of the chain when the current match is this long
+    pub good_length: usize,
+}
+
+impl Default for MatchConfig {
+    fn default() -> Self {
+        MatchConfig {
+            window_size: WINDOW_SIZE,
+            lookahead_size: LOOKAHEAD_SIZE,
+            max_chain: DEFAULT_MAX_CHAIN,
+            nice_length: LOOKAHEAD_SIZE,
+            lazy: false,
+            max_lazy: LOOKAHEAD_SIZE,
+            good_length: LOOKAHEAD_SIZE,
+        }
+    }
+}
+
+/// zlib-style hash chains: `head` holds the most recent position for every
+/// 3-byte hash and `prev` links each position to the previous one with the
+/// same hash, so only plausible candidates are compared
+#[derive(Debug, Clone)]
+pub struct MatchFinder {
+    config: MatchConfig,
+    head: Vec<usize>,
+    prev: Vec<usize>,
+    window_mask: usize,
+}
+
+impl MatchFinder {
+    pub fn new(config: MatchConfig) -> Self {
+        let window = config.window_size.max(1).next_power_of_two();
+        MatchFinder {
+            config,
+            head: vec![NIL; 1 << HASH_BITS],
+            prev: vec![NIL; window],
+            window_mask: window - 1,
+        }
+    }
+
+    pub fn config(&self) -> &MatchConfig {
+        &self.config
+    }
+
+    fn hash(data: &[u8], pos: usize) -> usize {
+        let key = (data[pos] as u32) | (data[pos + 1] as u32) << 8 | (data[pos + 2] as u32) << 16;
+        (key.wrapping_mul(0x9e37_79b1) >> (32 - HASH_BITS)) as usize
+    }
+
+    /// Add `pos` to its hash chain so later positions can match against it
+    pub fn insert(&mut self, data: &[u8], pos: usize) {
+        if pos + MIN_MATCH > data.len() {
+            return;
+        }
+        let hash = Self::hash(data, pos);
+        self.prev[pos & self.window_mask] = self.head[hash];
+        self.head[hash] = pos;
+    }
+
+    /// Longest earlier match for the bytes at `pos` as (length, distance),
+    /// or (0, 0) when there is none. Nearer matches win ties.
+    pub fn longest_match(&self, data: &[u8], pos: usize) -> (usize, usize) {
+        self.search(data, pos, self.config.max_chain)
+    }
+
+    fn search(&self, data: &[u8], pos: usize, max_chain: usize) -> (usize, usize) {
+        let max_length = self.config.lookahead_size.min(data.len() - pos);
+        if max_length < MIN_MATCH {
+            return (0, 0);
+        }
+
+        let limit = pos.saturating_sub(self.config.window_size);
+        let mut best_length = 0;
+        let mut best_distance = 0;
+        let mut candidate = self.head[Self::hash(data, pos)];
+        let mut chain = max_chain;
+
+        while candidate != NIL && candidate >= limit && candidate < pos && chain > 0 {
+            // Cheap rejection: a longer match must also agree at the current best length
+            if best_length == 0 || data[candidate + best_length] == data[pos + best_length] {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best_length {
+                    best_length = length;
+                    best_distance = pos - candidate;
+                    if length >= self.config.nice_length || length == max_length {
+                        break;
+                    }
+                }
+            }
+
+            candidate = self.prev[candidate & self.window_mask];
+            chain -= 1;
+        }
+
+        (best_length, best_distance)
+    }
+
+    /// Every match length worth considering at `pos`, for optimal parsing.
+    /// Pushes (length, distance) pairs in increasing length, each the
+    /// nearest match at least that long: any length between one pair and
+    /// the next can be had at the next pair's distance.
+    pub fn all_matches(&self, data: &[u8], pos: usize, out: &mut Vec<(usize, usize)>) {
+        let max_length = self.config.lookahead_size.min(data.len() - pos);
+        if max_length < MIN_MATCH {
+            return;
+        }
+
+        let limit = pos.saturating_sub(self.config.window_size);
+        let mut best_length = MIN_MATCH - 1;
+        let mut candidate = self.head[Self::hash(data, pos)];
+        let mut chain = self.config.max_chain;
+
+        while candidate != NIL && candidate >= limit && candidate < pos && chain > 0 {
+            if data[candidate + best_length] == data[pos + best_length] {
+                let length = data[candidate..]
+                    .iter()
+                    .zip(&data[pos..pos + max_length])
+                    .take_while(|(a, b)| a == b)
+                    .count();
+
+                if length > best_length {
+                    best_length = length;
+                    out.push((length, pos - candidate));
+                    if length == max_length {
+                        break;
+                    }
+                }
+            }
+
+            candidate = self.prev[candidate & self.window_mask];
+            chain -= 1;
+        }
+    }
+}
+
+/// LZ77 Compression - finds repeated sequences
+pub fn lz77_compress(data: &[u8], window_size: usize, lookahead_size: usize) -> Vec<Token> {
+    lz77_compress_with(data, MatchConfig { window_size, lookahead_size, ..MatchConfig::default() })
+}
+
+/// LZ77 with explicit match finder settings
+pub fn lz77_compress_with(data: &[u8], config: MatchConfig) -> Vec<Token> {
+    lz77_compress_from(data, 0, config)
+}
+
+/// Parse `data[start..]`, letting matches reach back into `data[..start]`.
+/// The earlier bytes act as a dictionary: they are searched but not emitted.
+pub fn lz77_compress_from(data: &[u8], start: usize, config: MatchConfig) -> Vec<Token> {
+    let mut finder = MatchFinder::new(config);
+    for pos in start.saturating_sub(config.window_size)..start {
+        finder.insert(data, pos);
+    }
+
+    if config.lazy {
+        lazy_parse(data, start, finder)
+    } else {
+        greedy_parse(data, start, finder)
+    }
+}
+
+/// Expand tokens back into the bytes they describe; a reference reaching
+/// before the start of the output fails with `InvalidToken`
+pub fn lz77_decompress(tokens: &[Token]) -> Result<Vec<u8>, GzipError> {
+    let mut out: Vec<u8> = Vec::with_capacity(tokens.len());
+    for (index, token) in tokens.iter().enumerate() {
+        match *token {
+            Token::Literal(byte) => out.push(byte),
+            Token::Reference { length, distance } => {
+                if distance == 0 || distance > out.len() {
+                    return Err(GzipError::InvalidToken { index, distance, available: out.len() });
+                }
+                let start = out.len() - distance;
+                for i in 0..length {
+                    out.push(out[start + i]);
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Take the longest match found at each position
+fn greedy_parse(data: &[u8], start: usize, mut finder: MatchFinder) -> Vec<Token> {
+    let config = finder.config;
+    let mut tokens = Vec::new();
+    let mut pos = start;
+
+    while pos < data.len() {
+        let (length, distance) = finder.longest_match(data, pos);
+
+        // Only use reference if it's at least 3 bytes (worthwhile)
+        if length >= MIN_MATCH {
+            tokens.push(Token::Reference { length, distance });
+            // Hashing every position of a long match costs more than it finds
+            if length <= config.max_lazy {
+                for p in pos..pos + length {
+                    finder.insert(data, p);
+                }
+            } else {
+                finder.insert(data, pos);
+            }
+            pos += length;
+        } else {
+            tokens.push(Token::Literal(data[pos]));
+            finder.insert(data, pos);
+            pos += 1;
+        }
+    }
+
+    tokens
+}
+
+/// Lazy evaluation as in zlib's deflate_slow: a match found at one position
+/// is only emitted if the next position does not start a longer one;
+/// otherwise the first byte goes out as a literal and the later match wins
+fn lazy_parse(data: &[u8], start: usize, mut finder: MatchFinder) -> Vec<Token> {
+    let config = finder.config;
+    let mut tokens = Vec::new();
+    // Best match starting at `pos - 1`, still waiting to be emitted
+    let mut deferred: Option<(usize, usize)> = None;
+    let mut pos = start;
+
+    while pos < data.len() {
+        let prev_length = deferred.map_or(0, |(length, _)| length);
+        let mut current = (0, 0);
+        if prev_length < config.max_lazy {
+            let chain = if prev_length >= config.good_length {
+                config.max_chain / 4
+            } else {
+                config.max_chain
+            };
+            current = finder.search(data, pos, chain);
+            if current.0 == MIN_MATCH && current.1 > TOO_FAR {
+                current = (0, 0);
+            }
+        }
+        finder.insert(data, pos);
+
+        match deferred {
+            Some((length, distance)) if length >= MIN_MATCH && current.0 <= length => {
+                tokens.push(Token::Reference { length, distance });
+                // The match started at pos - 1; both of those positions are hashed already
+                for p in pos + 1..pos - 1 + length {
+                    finder.insert(data, p);
+                }
+                pos += length - 1;
+                deferred = None;
+            }
+            Some(_) => {
+                tokens.push(Token::Literal(data[pos - 1]));
+                deferred = Some(current);
+                pos += 1;
+            }
+            None => {
+                deferred = Some(current);
+                pos += 1;
+            }
+        }
+    }
+
+    if deferred.is_some() {
+        tokens.push(Token::Literal(data[pos - 1]));
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lz77_compression() {
+        let data = b"abcabc";
+        let tokens = lz77_compress(data, 100, 100);
+
+        // Should find the repeated "abc"
+        assert!(tokens.iter().any(|t| matches!(t, Token::Reference { .. })));
+    }
+
+    fn expand(tokens: &[Token]) -> Vec<u8> {
+        lz77_decompress(tokens).unwrap()
+    }
+
+    #[test]
+    fn test_decompress_rejects_bad_distance() {
+        let tokens = [Token::Literal(b'a'), Token::Reference { length: 3, distance: 2 }];
+        assert_eq!(
+            lz77_decompress(&tokens),
+            Err(GzipError::InvalidToken { index: 1, distance: 2, available: 1 })
+        );
+        assert!(lz77_decompress(&[Token::Reference { length: 3, distance: 0 }]).is_err());
+        assert_eq!(lz77_decompress(&tokens[..1]).unwrap(), b"a");
+    }
+
+    #[test]
+    fn test_tokens_reproduce_input() {
+        let data = b"Hello, World! Hello, World! This is a test. Hello, World!".repeat(50);
+        let tokens = lz77_compress(&data, WINDOW_SIZE, LOOKAHEAD_SIZE);
+
+        assert_eq!(expand(&tokens), data);
+        assert!(tokens.iter().all(|t| match *t {
+            Token::Reference { length, distance } => length <= LOOKAHEAD_SIZE && distance <= WINDOW_SIZE,
+            Token::Literal(_) => true,
+        }));
+    }
+
+    #[test]
+    fn test_matches_respect_window() {
+        // The only repeat is 100 bytes back, outside a 64-byte window
+        let mut data: Vec<u8> = (0..100u8).collect();
+        data.extend(0..10u8);
+        let tokens = lz77_compress(&data, 64, LOOKAHEAD_SIZE);
+
+        assert!(tokens.iter().all(|t| matches!(t, Token::Literal(_))));
+        assert_eq!(expand(&tokens), data);
+    }
+
+    #[test]
+    fn test_nice_length_stops_search() {
+        // The nearest candidate matches 3 bytes, the farther one all 7
+        let data = b"abcdefg-abcX-abcdefg";
+        let search = |nice_length| {
+            let mut finder = MatchFinder::new(MatchConfig { nice_length, ..MatchConfig::default() });
+            for pos in 0..13 {
+                finder.insert(data, pos);
+            }
+            finder.longest_match(data, 13)
+        };
+
+        assert_eq!(search(3), (3, 5));
+        assert_eq!(search(LOOKAHEAD_SIZE), (7, 13));
+    }
+
+    #[test]
+    fn test_lazy_matching_prefers_longer_match() {
+        // At "abcdef" greedy grabs "abc" (distance 10) and then "def";
+        // lazy emits 'a' as a literal and takes "bcdef" from distance 7
+        let data = b"abcXbcdefYabcdef";
+        let greedy = lz77_compress_with(data, MatchConfig::default());
+        let lazy = lz77_compress_with(data, MatchConfig { lazy: true, ..MatchConfig::default() });
+
+        assert_eq!(greedy[10..], [
+            Token::Reference { length: 3, distance: 10 },
+            Token::Reference { length: 3, distance: 7 },
+        ]);
+        assert_eq!(lazy[10..], [
+            Token::Literal(b'a'),
+            Token::Reference { length: 5, distance: 7 },
+        ]);
+        assert_eq!(expand(&lazy), data);
+    }
+
+    #[test]
+    fn test_dictionary_prefix() {
+        let data = b"a dictionary phrase|a dictionary phrase";
+        let tokens = lz77_compress_from(data, 20, MatchConfig::default());
+
+        assert_eq!(tokens, vec![Token::Reference { length: 19, distance: 20 }]);
+    }
+
+    #[test]
+    fn test_long_input_is_fast() {
+        // Quadratic search would take minutes on this in a debug build
+        let data: Vec<u8> = (0..2_000_000u32).map(|i| (i % 251) as u8 ^ (i / 4096) as u8).collect();
+        let tokens = lz77_compress(&data, WINDOW_SIZE, LOOKAHEAD_SIZE);
+        assert_eq!(expand(&tokens), data);
+
+        let lazy = lz77_compress_with(&data, MatchConfig { lazy: true, ..MatchConfig::default() });
+        assert_eq!(expand(&lazy), data);
+    }
+}
@@ -1,266 +1,837 @@
-use std::collections::HashMap;
-
-/// 32KiB, specified in RFC 1951
-/// Powers of 2 are efficient for bitwise operations and memory
-/// addressing. 32KB was chosen as a good balance in the 1990s
-/// when DEFLATE was designed.
-///
-/// To illustrate:
-/// - Larger window = better compression (can find matches farther back)
-/// - Smaller window = less memory usage and faster compression
-const WINDOW_SIZE: usize = 32_768;
-
-/// How far ahead you look when trying to find matches
-/// - Standard DEFLATE: 258 bytes lookahead (RFC 1951)
-/// - Smaller lookahead = faster but finds shorter matches
-/// - Larger lookahead = slower but can find longer matches
-///
-/// Examples:
-///
-/// ```
-/// // Very fast, minimal compression (small window + short matches)
-/// lz77_compress(data, 4096, 32);
-///
-/// // Balanced (medium window)
-/// lz77_compress(data, 8192, 128);
-///
-/// // Standard DEFLATE (what you should normally use)
-/// lz77_compress(data, 32768, 258);
-/// ```
-const LOOKAHEAD_SIZE: usize = 258;
-
-// LZ77 Token: either a literal byte or a (length, distance) pair
-#[derive(Debug, Clone)]
-enum Token {
-    Literal(u8),
-    Reference { length: usize, distance: usize },
-}
-
-// Huffman tree node
-#[derive(Debug, Clone)]
-enum HuffmanNode {
-    Leaf { symbol: u16, freq: usize },
-    Internal { left: Box<HuffmanNode>, right: Box<HuffmanNode>, freq: usize },
-}
-
-impl HuffmanNode {
-    fn freq(&self) -> usize {
+use std::env;
+use std::fs::{self, File};
+use std::io::{self, BufReader, BufWriter, IsTerminal, Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+use std::process::ExitCode;
+use std::time::UNIX_EPOCH;
+
+use gzip::gz::{encode_member_ultra, extra_flags_for, GzHeader, OS_UNIX};
+use gzip::analyze::{analyze, BlockReport, MemberReport};
+use gzip::lz77::Token;
+use gzip::optimal::DEFAULT_ITERATIONS;
+use gzip::tar::{path_to_bytes, EntryType, TarHeader, TarReader, TarWriter};
+use gzip::{CompressionLevel, GzDecoder, GzEncoder, ParallelGzEncoder};
+
+const USAGE: &str = "\
+Usage: gzip [OPTION]... [FILE]...
+Compress or decompress FILEs (by default, compress in place).
+With no FILE, or when FILE is -, read standard input.
+
+  -c, --stdout      write on standard output, keep original files
+  -d, --decompress  decompress
+  -f, --force       overwrite existing output files, compress to a terminal
+  -h, --help        give this help
+  -k, --keep        keep (don't delete) input files
+  -l, --list        list compressed file contents
+  -p, --processes N compress with N threads (pigz-style chunks)
+  -t, --test        test compressed file integrity
+  -v, --verbose     verbose mode
+  -1, --fast        compress faster
+  -9, --best        compress better
+      --ultra       compress best of all with optimal parsing (slow; reads
+                    the whole input into memory)
+      --analyze     dump the blocks, code tables and bit costs of gzip or
+                    raw DEFLATE FILEs (with -v, every token too)
+      --json        print --analyze output as JSON
+      --tar ARCHIVE create a .tar.gz ARCHIVE of the FILEs and directories;
+                    with -d, extract ARCHIVE into directory FILE (default .)";
+
+/// Suffix appended on compression and expected on decompression
+const SUFFIX: &str = ".gz";
+
+/// What a single file ended in, ordered by severity so the exit code is the maximum
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Status {
+    Ok,
+    /// Skipped with a warning (exit code 2, as gzip does)
+    Warning,
+    Error,
+}
+
+impl Status {
+    fn exit_code(self) -> ExitCode {
         match self {
-            HuffmanNode::Leaf { freq, .. } => *freq,
-            HuffmanNode::Internal { freq, .. } => *freq,
+            Status::Ok => ExitCode::SUCCESS,
+            Status::Error => ExitCode::from(1),
+            Status::Warning => ExitCode::from(2),
         }
     }
 }
 
-/// LZ77 Compression - finds repeated sequences
-fn lz77_compress(data: &[u8], window_size: usize, lookahead_size: usize) -> Vec<Token> {
-    let mut tokens = Vec::new();
-    let mut pos = 0;
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mode {
+    Compress,
+    Decompress,
+    Test,
+    List,
+    Analyze,
+}
 
-    while pos < data.len() {
-        let mut best_length = 0;
-        let mut best_distance = 0;
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Options {
+    mode: Mode,
+    stdout: bool,
+    force: bool,
+    keep: bool,
+    verbose: bool,
+    level: CompressionLevel,
+    threads: usize,
+    /// Zopfli-style optimal parsing instead of `level`
+    ultra: bool,
+    /// Machine-readable output for `--analyze`
+    json: bool,
+    /// Archive to create from the FILEs, or with `-d` to extract
+    tar: Option<String>,
+}
 
-        // Search window starts
-        let search_start = pos.saturating_sub(window_size);
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            mode: Mode::Compress,
+            stdout: false,
+            force: false,
+            keep: false,
+            verbose: false,
+            level: CompressionLevel::DEFAULT,
+            threads: 1,
+            ultra: false,
+            json: false,
+            tar: None,
+        }
+    }
+}
 
-        // Look for matches in the search window
-        for i in search_start..pos {
-            let mut length = 0;
+#[derive(Debug, PartialEq, Eq)]
+enum Command {
+    Run(Options, Vec<String>),
+    Help,
+}
 
-            // Count matching bytes
-            while length < lookahead_size
-                && pos + length < data.len()
-                && data[i + length] == data[pos + length] {
-                length += 1;
-            }
+/// Thread count given to `-p`
+fn parse_threads(value: Option<String>) -> Result<usize, String> {
+    let value = value.ok_or("option requires an argument -- 'p'")?;
+    match value.parse() {
+        Ok(threads) if threads > 0 => Ok(threads),
+        _ => Err(format!("invalid number of processes '{}'", value)),
+    }
+}
 
-            // Keep track of best match
-            if length > best_length {
-                best_length = length;
-                best_distance = pos - i;
+/// Parse the command line (without the program name). Short flags can be
+/// combined as in `-dc`, with `-p` taking the rest of its group or the next
+/// argument; `--` ends option processing and `-` is standard input.
+fn parse_args<I: IntoIterator<Item = String>>(args: I) -> Result<Command, String> {
+    let mut options = Options::default();
+    let mut files = Vec::new();
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            files.extend(args.by_ref());
+            break;
+        }
+        if let Some(long) = arg.strip_prefix("--") {
+            if let Some(value) = long.strip_prefix("processes=") {
+                options.threads = parse_threads(Some(value.to_string()))?;
+                continue;
+            }
+            if let Some(value) = long.strip_prefix("tar=") {
+                options.tar = Some(value.to_string());
+                continue;
             }
+            match long {
+                "stdout" | "to-stdout" => options.stdout = true,
+                "decompress" | "uncompress" => options.mode = Mode::Decompress,
+                "force" => options.force = true,
+                "help" => return Ok(Command::Help),
+                "keep" => options.keep = true,
+                "list" => options.mode = Mode::List,
+                "test" => options.mode = Mode::Test,
+                "verbose" => options.verbose = true,
+                "fast" => options.level = CompressionLevel::FASTEST,
+                "best" => options.level = CompressionLevel::BEST,
+                "ultra" => options.ultra = true,
+                "processes" => options.threads = parse_threads(args.next())?,
+                "analyze" => options.mode = Mode::Analyze,
+                "json" => options.json = true,
+                "tar" => options.tar = Some(args.next().ok_or("option '--tar' requires an argument")?),
+                _ => return Err(format!("unrecognized option '{}'", arg)),
+            }
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            for (i, flag) in arg.char_indices().skip(1) {
+                match flag {
+                    'c' => options.stdout = true,
+                    'd' => options.mode = Mode::Decompress,
+                    'f' => options.force = true,
+                    'h' => return Ok(Command::Help),
+                    'k' => options.keep = true,
+                    'l' => options.mode = Mode::List,
+                    't' => options.mode = Mode::Test,
+                    'v' => options.verbose = true,
+                    'p' => {
+                        let rest = &arg[i + 1..];
+                        let value = if rest.is_empty() { args.next() } else { Some(rest.to_string()) };
+                        options.threads = parse_threads(value)?;
+                        break;
+                    }
+                    '0'..='9' => {
+                        options.level = CompressionLevel::new(flag as u8 - b'0').unwrap();
+                    }
+                    _ => return Err(format!("invalid option -- '{}'", flag)),
+                }
+            }
+        } else {
+            files.push(arg);
         }
+    }
 
-        // Only use reference if it's at least 3 bytes (worthwhile)
-        if best_length >= 3 {
-            tokens.push(Token::Reference {
-                length: best_length,
-                distance: best_distance,
-            });
-            pos += best_length;
-        } else {
-            tokens.push(Token::Literal(data[pos]));
-            pos += 1;
+    if options.tar.is_some() {
+        match options.mode {
+            Mode::Compress if files.is_empty() => return Err("--tar needs files to archive".to_string()),
+            Mode::Decompress if files.len() > 1 => return Err("--tar -d extracts into one directory".to_string()),
+            Mode::Compress | Mode::Decompress => {}
+            _ => return Err("--tar only works when compressing or with -d".to_string()),
         }
+    } else if files.is_empty() {
+        files.push("-".to_string());
     }
+    Ok(Command::Run(options, files))
+}
 
-    tokens
+/// Name of the decompressed file, or `None` if `name` lacks a known suffix
+fn decompressed_name(name: &str) -> Option<String> {
+    if let Some(stem) = name.strip_suffix(".tgz") {
+        return Some(format!("{}.tar", stem));
+    }
+    [SUFFIX, "-gz", ".z"]
+        .iter()
+        .find_map(|suffix| name.strip_suffix(suffix))
+        .filter(|stem| !stem.is_empty() && !stem.ends_with('/'))
+        .map(str::to_string)
 }
 
-/// Build Huffman tree from frequency map
-fn build_huffman_tree(frequencies: &HashMap<u16, usize>) -> Option<HuffmanNode> {
-    if frequencies.is_empty() {
-        return None;
+/// Space saved by compression, as gzip reports it
+fn ratio(uncompressed: u64, compressed: u64) -> f64 {
+    if uncompressed == 0 {
+        0.0
+    } else {
+        (1.0 - compressed as f64 / uncompressed as f64) * 100.0
     }
+}
 
-    // Create initial leaf nodes
-    let mut nodes: Vec<HuffmanNode> = frequencies
-        .iter()
-        .map(|(&symbol, &freq)| HuffmanNode::Leaf { symbol, freq })
-        .collect();
-
-    // Build tree by repeatedly combining two lowest frequency nodes
-    while nodes.len() > 1 {
-        // Sort by frequency
-        nodes.sort_by_key(|n| std::cmp::Reverse(n.freq()));
-
-        // Take two lowest frequency nodes
-        let right = nodes.pop().unwrap();
-        let left = nodes.pop().unwrap();
-
-        // Create internal node
-        let internal = HuffmanNode::Internal {
-            freq: left.freq() + right.freq(),
-            left: Box::new(left),
-            right: Box::new(right),
-        };
+/// Create `path` for writing, refusing to replace an existing file unless forced
+fn create_output(path: &Path, force: bool) -> io::Result<Option<File>> {
+    if path.exists() {
+        if !force {
+            eprintln!("gzip: {} already exists; not overwritten", path.display());
+            return Ok(None);
+        }
+        fs::remove_file(path)?;
+    }
+    File::create(path).map(Some)
+}
+
+/// Give the output file the mode and modification time of its input
+fn copy_metadata(output: &File, metadata: &fs::Metadata) -> io::Result<()> {
+    output.set_permissions(metadata.permissions())?;
+    output.set_modified(metadata.modified()?)
+}
 
-        nodes.push(internal);
+fn compress_stream<R: Read, W: Write>(mut input: R, mut output: W, header: &GzHeader, options: &Options) -> io::Result<(u64, W)> {
+    if options.ultra {
+        // Optimal parsing needs the whole input up front
+        let mut data = Vec::new();
+        input.read_to_end(&mut data)?;
+        output.write_all(&encode_member_ultra(header, &data, DEFAULT_ITERATIONS)?)?;
+        output.flush()?;
+        return Ok((data.len() as u64, output));
+    }
+    if options.threads > 1 {
+        let mut encoder = ParallelGzEncoder::with_header(output, header, options.level, options.threads)?;
+        let read = io::copy(&mut input, &mut encoder)?;
+        return Ok((read, encoder.finish()?));
     }
+    let mut encoder = GzEncoder::with_header(output, header, options.level)?;
+    let read = io::copy(&mut input, &mut encoder)?;
+    Ok((read, encoder.finish()?))
+}
 
-    nodes.pop()
+fn decompress_stream<R: Read, W: Write>(input: R, mut output: W) -> io::Result<u64> {
+    let mut decoder = GzDecoder::new(input);
+    let written = io::copy(&mut decoder, &mut output)?;
+    output.flush()?;
+    Ok(written)
 }
 
-/// Generate Huffman codes from tree
-fn generate_codes(node: &HuffmanNode, prefix: String, codes: &mut HashMap<u16, String>) {
-    match node {
-        HuffmanNode::Leaf { symbol, .. } => {
-            codes.insert(*symbol, if prefix.is_empty() { "0".to_string() } else { prefix });
-        }
-        HuffmanNode::Internal { left, right, .. } => {
-            generate_codes(left, format!("{}0", prefix), codes);
-            generate_codes(right, format!("{}1", prefix), codes);
-        }
+/// Compress whatever `write` produces into one member, with the encoder
+/// `options` ask for; the push-driven counterpart of `compress_stream`
+fn compress_with<W: Write>(
+    mut output: W,
+    header: &GzHeader,
+    options: &Options,
+    write: impl FnOnce(&mut dyn Write) -> io::Result<()>,
+) -> io::Result<W> {
+    if options.ultra {
+        let mut data = Vec::new();
+        write(&mut data)?;
+        output.write_all(&encode_member_ultra(header, &data, DEFAULT_ITERATIONS)?)?;
+        output.flush()?;
+        return Ok(output);
+    }
+    if options.threads > 1 {
+        let mut encoder = ParallelGzEncoder::with_header(output, header, options.level, options.threads)?;
+        write(&mut encoder)?;
+        return encoder.finish();
     }
+    let mut encoder = GzEncoder::with_header(output, header, options.level)?;
+    write(&mut encoder)?;
+    encoder.finish()
 }
 
-/// Encode tokens using Huffman coding
-fn huffman_encode(tokens: &[Token]) -> (String, HashMap<u16, String>) {
-    // Count frequencies
-    let mut frequencies = HashMap::new();
+fn member_header(options: &Options) -> GzHeader {
+    let level = if options.ultra { CompressionLevel::BEST } else { options.level };
+    GzHeader { extra_flags: extra_flags_for(level), os: OS_UNIX, ..GzHeader::default() }
+}
 
-    for token in tokens {
-        match token {
-            Token::Literal(byte) => {
-                *frequencies.entry(*byte as u16).or_insert(0) += 1;
-            }
-            Token::Reference { length, distance } => {
-                // Encode length and distance as special symbols
-                // In real DEFLATE these use special code ranges
-                let length_code = 256 + (*length as u16);
-                let distance_code = 512 + (*distance as u16);
-                *frequencies.entry(length_code).or_insert(0) += 1;
-                *frequencies.entry(distance_code).or_insert(0) += 1;
-            }
+fn compress_file(options: &Options, name: &str) -> io::Result<Status> {
+    let mut header = member_header(options);
+
+    if name == "-" {
+        let stdout = io::stdout();
+        if stdout.is_terminal() && !options.force {
+            eprintln!("gzip: compressed data not written to a terminal. Use -f to force compression.");
+            return Ok(Status::Error);
         }
+        compress_stream(io::stdin().lock(), BufWriter::new(stdout.lock()), &header, options)?;
+        return Ok(Status::Ok);
     }
 
-    // Build Huffman tree and generate codes
-    let tree = build_huffman_tree(&frequencies).unwrap();
-    let mut codes = HashMap::new();
-    generate_codes(&tree, String::new(), &mut codes);
+    let path = Path::new(name);
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() {
+        eprintln!("gzip: {} is not a regular file -- ignored", name);
+        return Ok(Status::Warning);
+    }
+    if name.ends_with(SUFFIX) && !options.stdout {
+        eprintln!("gzip: {} already has {} suffix -- unchanged", name, SUFFIX);
+        return Ok(Status::Warning);
+    }
+
+    header.filename = path.file_name().map(|file_name| file_name.as_encoded_bytes().to_vec());
+    header.mtime = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |since| since.as_secs().min(u32::MAX as u64) as u32);
+    let input = BufReader::new(File::open(path)?);
+
+    if options.stdout {
+        compress_stream(input, BufWriter::new(io::stdout().lock()), &header, options)?;
+        return Ok(Status::Ok);
+    }
 
-    // Encode the data
-    let mut encoded = String::new();
-    for token in tokens {
-        match token {
-            Token::Literal(byte) => {
-                encoded.push_str(&codes[&(*byte as u16)]);
+    let out_path = PathBuf::from(format!("{}{}", name, SUFFIX));
+    let Some(output) = create_output(&out_path, options.force)? else {
+        return Ok(Status::Warning);
+    };
+    let result = compress_stream(input, BufWriter::new(output), &header, options)
+        .and_then(|(read, output)| {
+            let output = output.into_inner().map_err(io::IntoInnerError::into_error)?;
+            copy_metadata(&output, &metadata)?;
+            Ok((read, output.metadata()?.len()))
+        });
+    let (read, written) = match result {
+        Ok(sizes) => sizes,
+        Err(err) => {
+            let _ = fs::remove_file(&out_path);
+            return Err(err);
+        }
+    };
+
+    finish_file(options, name, &out_path, ratio(read, written))
+}
+
+fn decompress_file(options: &Options, name: &str) -> io::Result<Status> {
+    if name == "-" {
+        match options.mode {
+            Mode::Test => {
+                decompress_stream(io::stdin().lock(), io::sink())?;
             }
-            Token::Reference { length, distance } => {
-                let length_code = 256 + (*length as u16);
-                let distance_code = 512 + (*distance as u16);
-                encoded.push_str(&codes[&length_code]);
-                encoded.push_str(&codes[&distance_code]);
+            _ => {
+                decompress_stream(io::stdin().lock(), BufWriter::new(io::stdout().lock()))?;
             }
         }
+        return Ok(Status::Ok);
+    }
+
+    let path = Path::new(name);
+    let metadata = fs::metadata(path)?;
+    if !metadata.is_file() {
+        eprintln!("gzip: {} is not a regular file -- ignored", name);
+        return Ok(Status::Warning);
     }
+    let input = BufReader::new(File::open(path)?);
 
-    (encoded, codes)
+    if options.mode == Mode::Test {
+        decompress_stream(input, io::sink())?;
+        if options.verbose {
+            eprintln!("{}:\t OK", name);
+        }
+        return Ok(Status::Ok);
+    }
+    if options.stdout {
+        decompress_stream(input, BufWriter::new(io::stdout().lock()))?;
+        return Ok(Status::Ok);
+    }
+
+    let Some(out_name) = decompressed_name(name) else {
+        eprintln!("gzip: {}: unknown suffix -- ignored", name);
+        return Ok(Status::Warning);
+    };
+    let out_path = PathBuf::from(out_name);
+    let Some(output) = create_output(&out_path, options.force)? else {
+        return Ok(Status::Warning);
+    };
+    let mut output = BufWriter::new(output);
+    let result = decompress_stream(input, &mut output).and_then(|written| {
+        let output = output.into_inner().map_err(io::IntoInnerError::into_error)?;
+        copy_metadata(&output, &metadata)?;
+        Ok(written)
+    });
+    let written = match result {
+        Ok(written) => written,
+        Err(err) => {
+            let _ = fs::remove_file(&out_path);
+            return Err(err);
+        }
+    };
+
+    finish_file(options, name, &out_path, ratio(written, metadata.len()))
 }
 
-// Compress data using LZ77 + Huffman (simplified DEFLATE)
-fn compress(data: &[u8]) -> (String, HashMap<u16, String>, Vec<Token>) {
-    let tokens = lz77_compress(data, WINDOW_SIZE, LOOKAHEAD_SIZE);
-    let (encoded, codes) = huffman_encode(&tokens);
-    (encoded, codes, tokens)
+/// Remove the input unless asked to keep it, and report what happened
+fn finish_file(options: &Options, name: &str, out_path: &Path, ratio: f64) -> io::Result<Status> {
+    if !options.keep {
+        fs::remove_file(name)?;
+    }
+    if options.verbose {
+        let action = if options.keep { "created" } else { "replaced with" };
+        eprintln!("{}:\t{:5.1}% -- {} {}", name, ratio, action, out_path.display());
+    }
+    Ok(Status::Ok)
 }
 
-fn main() {
-    let data = b"Hello, World! Hello, World! This is a test. Hello, World!";
+/// Sizes for `-l`: the compressed size and the ISIZE field of the last member
+fn list_sizes(name: &str) -> io::Result<(u64, u64)> {
+    let mut file = File::open(name)?;
+    let compressed = file.metadata()?.len();
+    if compressed < 18 {
+        return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file too short to be gzip"));
+    }
+    let mut magic = [0; 2];
+    file.read_exact(&mut magic)?;
+    if magic != gzip::gz::MAGIC {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "not in gzip format"));
+    }
+    let mut isize = [0; 4];
+    file.seek(SeekFrom::End(-4))?;
+    file.read_exact(&mut isize)?;
+    Ok((compressed, u32::from_le_bytes(isize) as u64))
+}
 
-    println!("Original data: {}", String::from_utf8_lossy(data));
-    println!("Original size: {} bytes ({} bits)\n", data.len(), data.len() * 8);
+fn list_file(name: &str, totals: &mut (u64, u64)) -> io::Result<Status> {
+    if name == "-" {
+        eprintln!("gzip: cannot list standard input");
+        return Ok(Status::Warning);
+    }
+    let (compressed, uncompressed) = list_sizes(name)?;
+    let out_name = decompressed_name(name).unwrap_or_else(|| name.to_string());
+    println!("{:>19} {:>19} {:5.1}% {}", compressed, uncompressed, ratio(uncompressed, compressed), out_name);
+    totals.0 += compressed;
+    totals.1 += uncompressed;
+    Ok(Status::Ok)
+}
 
-    let (encoded, codes, tokens) = compress(data);
+/// Name of `path` inside an archive: its components joined by '/', without
+/// a leading '/' or `.`; `None` if it climbs with `..`
+fn archive_name(path: &Path) -> Option<Vec<u8>> {
+    let mut parts = Vec::new();
+    for component in path.components() {
+        match component {
+            Component::Normal(part) => parts.push(path_to_bytes(Path::new(part))),
+            Component::ParentDir => return None,
+            Component::CurDir | Component::RootDir | Component::Prefix(_) => {}
+        }
+    }
+    if parts.is_empty() {
+        return Some(b".".to_vec());
+    }
+    Some(parts.join(&b'/'))
+}
 
-    println!("=== LZ77 Tokens ===");
-    for (i, token) in tokens.iter().enumerate().take(20) {
-        match token {
-            Token::Literal(byte) => println!("{}: Literal '{}'", i, *byte as char),
-            Token::Reference { length, distance } => {
-                println!("{}: Reference (length: {}, distance: {})", i, length, distance);
-            }
+fn print_entry(header: &TarHeader) {
+    let suffix = if header.entry_type == EntryType::Directory { "/" } else { "" };
+    eprintln!("{}{}", String::from_utf8_lossy(&header.path), suffix);
+}
+
+/// `--tar`: archive `files` into `archive`, streaming through the encoder
+fn create_archive(options: &Options, archive: &str, files: &[String]) -> io::Result<Status> {
+    let mut status = Status::Ok;
+    let write = |output: &mut dyn Write| {
+        let mut tar = TarWriter::new(output);
+        for file in files {
+            let Some(name) = archive_name(Path::new(file)) else {
+                eprintln!("gzip: {}: member names may not contain '..' -- ignored", file);
+                status = Status::Warning;
+                continue;
+            };
+            tar.append_path(Path::new(file), &name, &mut |header| {
+                if options.verbose {
+                    print_entry(header);
+                }
+            })?;
+        }
+        tar.finish().map(drop)
+    };
+
+    if archive == "-" {
+        let stdout = io::stdout();
+        if stdout.is_terminal() && !options.force {
+            eprintln!("gzip: compressed data not written to a terminal. Use -f to force compression.");
+            return Ok(Status::Error);
         }
+        compress_with(BufWriter::new(stdout.lock()), &member_header(options), options, write)?.flush()?;
+        return Ok(status);
     }
-    if tokens.len() > 20 {
-        println!("... and {} more tokens", tokens.len() - 20);
+
+    let path = Path::new(archive);
+    let Some(output) = create_output(path, options.force)? else {
+        return Ok(Status::Warning);
+    };
+    let result = compress_with(BufWriter::new(output), &member_header(options), options, write)
+        .and_then(|output| output.into_inner().map_err(io::IntoInnerError::into_error).map(drop));
+    if let Err(err) = result {
+        let _ = fs::remove_file(path);
+        return Err(err);
     }
+    Ok(status)
+}
 
-    println!("\n=== Huffman Codes (sample) ===");
-    let mut code_vec: Vec<_> = codes.iter().collect();
-    code_vec.sort_by_key(|(symbol, _)| *symbol);
-    for (symbol, code) in code_vec.iter().take(10) {
-        if **symbol < 256 {
-            println!("'{}' ({}): {}", **symbol as u8 as char, symbol, code);
-        } else if **symbol < 512 {
-            println!("Length {}: {}", **symbol - 256, code);
-        } else {
-            println!("Distance {}: {}", **symbol - 512, code);
+/// `--tar -d`: extract `archive` into `dest`, refusing entries that would
+/// land outside it
+fn extract_archive(options: &Options, archive: &str, dest: &str) -> io::Result<Status> {
+    fs::create_dir_all(dest)?;
+    let mut status = Status::Ok;
+    let each = |header: &TarHeader| {
+        if let EntryType::Other(flag) = header.entry_type {
+            eprintln!("gzip: {}: unsupported entry type '{}' -- skipped", String::from_utf8_lossy(&header.path), flag as char);
+            status = Status::Warning;
+        } else if options.verbose {
+            print_entry(header);
+        }
+    };
+    let input: Box<dyn Read> = if archive == "-" {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(BufReader::new(File::open(archive)?))
+    };
+    let mut tar = TarReader::new(GzDecoder::new(input));
+    tar.unpack(Path::new(dest), each)?;
+    // The archive ends before the gzip stream does: decode the rest, the
+    // second zero block and any further members, so every trailer is checked
+    io::copy(&mut tar.into_inner(), &mut io::sink())?;
+    Ok(status)
+}
+
+fn run(options: &Options, files: &[String]) -> Status {
+    if let Some(archive) = &options.tar {
+        let result = match options.mode {
+            Mode::Decompress => extract_archive(options, archive, files.first().map_or(".", String::as_str)),
+            _ => create_archive(options, archive, files),
+        };
+        return result.unwrap_or_else(|err| {
+            eprintln!("gzip: {}: {}", archive, err);
+            Status::Error
+        });
+    }
+
+    let mut status = Status::Ok;
+    let mut totals = (0, 0);
+
+    if options.mode == Mode::List {
+        println!("{:>19} {:>19}  ratio uncompressed_name", "compressed", "uncompressed");
+    }
+    for name in files {
+        let result = match options.mode {
+            Mode::Compress => compress_file(options, name),
+            Mode::Decompress | Mode::Test => decompress_file(options, name),
+            Mode::List => list_file(name, &mut totals),
+            Mode::Analyze => analyze_file(options, name),
+        };
+        let file_status = result.unwrap_or_else(|err| {
+            eprintln!("gzip: {}: {}", name, err);
+            Status::Error
+        });
+        status = status.max(file_status);
+    }
+    if options.mode == Mode::List && files.len() > 1 {
+        println!("{:>19} {:>19} {:5.1}% (totals)", totals.0, totals.1, ratio(totals.1, totals.0));
+    }
+    status
+}
+
+fn main() -> ExitCode {
+    match parse_args(env::args().skip(1)) {
+        Ok(Command::Run(options, files)) => run(&options, &files).exit_code(),
+        Ok(Command::Help) => {
+            println!("{}", USAGE);
+            ExitCode::SUCCESS
+        }
+        Err(message) => {
+            eprintln!("gzip: {}\n{}", message, USAGE);
+            Status::Error.exit_code()
         }
     }
+}
 
-    println!("\n=== Compression Results ===");
-    println!("Encoded size: {} bits ({:.2} bytes)",
-             encoded.len(),
-             encoded.len() as f64 / 8.0);
-    println!("Compression ratio: {:.2}%",
-             (1.0 - (encoded.len() as f64 / 8.0) / data.len() as f64) * 100.0);
-    println!("\nFirst 100 bits of encoded data:\n{}",
-             &encoded.chars().take(100).collect::<String>());
+/// Code lengths as rows of 16 symbols, skipping rows with no codes
+fn print_code_lengths(out: &mut impl Write, title: &str, lengths: &[u8]) -> io::Result<()> {
+    writeln!(out, "    {} code lengths:", title)?;
+    for (row, chunk) in lengths.chunks(16).enumerate() {
+        if chunk.iter().any(|&len| len > 0) {
+            let cells: Vec<String> = chunk.iter().map(|len| format!("{:2}", len)).collect();
+            writeln!(out, "      {:3}: {}", row * 16, cells.join(" "))?;
+        }
+    }
+    Ok(())
+}
+
+fn print_block(out: &mut impl Write, index: usize, block: &BlockReport, verbose: bool) -> io::Result<()> {
+    let costs = &block.costs;
+    writeln!(
+        out,
+        "  block {} at bit {}: {}{}, {:.1} bytes -> {} bytes",
+        index,
+        block.bit_offset,
+        block.kind.name(),
+        if block.is_final { " (final)" } else { "" },
+        costs.total() as f64 / 8.0,
+        block.output_len
+    )?;
+    writeln!(out, "    literals: {}, matches: {}", block.literals, block.matches)?;
+    writeln!(
+        out,
+        "    bits: header {}, literals {}, lengths {} + {} extra, distances {} + {} extra, end of block {}, stored {}",
+        costs.header,
+        costs.literals,
+        costs.lengths,
+        costs.length_extra,
+        costs.distances,
+        costs.distance_extra,
+        costs.end_of_block,
+        costs.stored
+    )?;
+    if !block.litlen_lengths.is_empty() {
+        print_code_lengths(out, "literal/length", &block.litlen_lengths)?;
+        print_code_lengths(out, "distance", &block.distance_lengths)?;
+    }
+    if block.matches > 0 {
+        let lengths: Vec<String> = (0..block.length_histogram.len())
+            .filter(|&length| block.length_histogram[length] > 0)
+            .map(|length| format!("{}:{}", length, block.length_histogram[length]))
+            .collect();
+        writeln!(out, "    match lengths: {}", lengths.join(" "))?;
+        let distances: Vec<String> = (0..block.distance_histogram.len())
+            .filter(|&code| block.distance_histogram[code] > 0)
+            .map(|code| format!("d{}:{}", code, block.distance_histogram[code]))
+            .collect();
+        writeln!(out, "    distance codes: {}", distances.join(" "))?;
+    }
+    if verbose {
+        writeln!(out, "    tokens:")?;
+        for (i, token) in block.tokens.iter().enumerate() {
+            match *token {
+                Token::Literal(byte) => writeln!(out, "      {}: literal {:?}", i, byte as char)?,
+                Token::Reference { length, distance } => {
+                    writeln!(out, "      {}: match length {}, distance {}", i, length, distance)?;
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+fn print_member(out: &mut impl Write, member: &MemberReport, verbose: bool) -> io::Result<()> {
+    let name = member
+        .header
+        .as_ref()
+        .and_then(|header| header.filename.as_ref())
+        .map_or(String::new(), |name| format!(" \"{}\"", String::from_utf8_lossy(name)));
+    let kind = if member.header.is_some() { "gzip member" } else { "raw DEFLATE stream" };
+    writeln!(
+        out,
+        "{} at byte {}{}: {} -> {} bytes ({:.1}%), {} blocks",
+        kind,
+        member.offset,
+        name,
+        member.compressed_len,
+        member.output_len(),
+        ratio(member.output_len() as u64, member.compressed_len as u64),
+        member.blocks.len()
+    )?;
+    for (i, block) in member.blocks.iter().enumerate() {
+        print_block(out, i, block, verbose)?;
+    }
+    Ok(())
+}
+
+/// `--analyze`: describe the blocks of a gzip or raw DEFLATE file
+fn analyze_file(options: &Options, name: &str) -> io::Result<Status> {
+    let mut data = Vec::new();
+    if name == "-" {
+        io::stdin().lock().read_to_end(&mut data)?;
+    } else {
+        File::open(name)?.read_to_end(&mut data)?;
+    }
+
+    let members = analyze(&data, options.verbose).map_err(io::Error::from)?;
+    let mut out = BufWriter::new(io::stdout().lock());
+    if options.json {
+        writeln!(out, "{}", gzip::analyze::to_json(name, &members))?;
+    } else {
+        writeln!(out, "{}:", name)?;
+        for member in &members {
+            print_member(&mut out, member, options.verbose)?;
+        }
+    }
+    out.flush()?;
+    Ok(Status::Ok)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    fn parse(args: &[&str]) -> Result<Command, String> {
+        parse_args(args.iter().map(|arg| arg.to_string()))
+    }
+
     #[test]
-    fn test_lz77_compression() {
-        let data = b"abcabc";
-        let tokens = lz77_compress(data, 100, 100);
+    fn test_parse_defaults_to_stdin() {
+        assert_eq!(parse(&[]), Ok(Command::Run(Options::default(), vec!["-".to_string()])));
+    }
 
-        // Should find the repeated "abc"
-        assert!(tokens.iter().any(|t| matches!(t, Token::Reference { .. })));
+    #[test]
+    fn test_parse_combined_flags() {
+        let Ok(Command::Run(options, files)) = parse(&["-dck", "a.gz", "--", "-v"]) else {
+            panic!("expected options");
+        };
+        assert_eq!(options.mode, Mode::Decompress);
+        assert!(options.stdout && options.keep && !options.verbose);
+        assert_eq!(files, ["a.gz", "-v"]);
+
+        let Ok(Command::Run(options, _)) = parse(&["-9", "--fast", "-v3"]) else {
+            panic!("expected options");
+        };
+        assert_eq!(options.level.level(), 3);
+        assert!(options.verbose && !options.ultra);
+
+        let Ok(Command::Run(options, files)) = parse(&["-kp4", "a", "-p", "2", "--processes=8", "b"]) else {
+            panic!("expected options");
+        };
+        assert!(options.keep);
+        assert_eq!(options.threads, 8);
+        assert_eq!(files, ["a", "b"]);
+
+        let Ok(Command::Run(options, _)) = parse(&["--ultra", "-c"]) else {
+            panic!("expected options");
+        };
+        assert!(options.ultra && options.stdout);
+
+        let Ok(Command::Run(options, files)) = parse(&["--analyze", "--json", "a.gz"]) else {
+            panic!("expected options");
+        };
+        assert_eq!(options.mode, Mode::Analyze);
+        assert!(options.json);
+        assert_eq!(files, ["a.gz"]);
     }
 
     #[test]
-    fn test_huffman_tree() {
-        let mut freq = HashMap::new();
-        freq.insert(65, 3); // 'A' appears 3 times
-        freq.insert(66, 1); // 'B' appears 1 time
+    fn test_parse_rejects_unknown_options() {
+        assert!(parse(&["-x"]).is_err());
+        assert!(parse(&["--frobnicate"]).is_err());
+        assert!(parse(&["-p"]).is_err());
+        assert!(parse(&["-p0"]).is_err());
+        assert_eq!(parse(&["-th"]), Ok(Command::Help));
+    }
+
+    #[test]
+    fn test_parse_tar() {
+        let Ok(Command::Run(options, files)) = parse(&["--tar", "out.tar.gz", "-9", "src", "docs"]) else {
+            panic!("expected options");
+        };
+        assert_eq!(options.tar.as_deref(), Some("out.tar.gz"));
+        assert_eq!(options.level.level(), 9);
+        assert_eq!(files, ["src", "docs"]);
+
+        let Ok(Command::Run(options, files)) = parse(&["-d", "--tar=-"]) else {
+            panic!("expected options");
+        };
+        assert_eq!(options.tar.as_deref(), Some("-"));
+        assert!(files.is_empty());
+
+        assert!(parse(&["--tar"]).is_err());
+        assert!(parse(&["--tar", "out.tar.gz"]).is_err());
+        assert!(parse(&["-d", "--tar", "in.tar.gz", "a", "b"]).is_err());
+        assert!(parse(&["-l", "--tar", "in.tar.gz"]).is_err());
+    }
 
-        let tree = build_huffman_tree(&freq);
-        assert!(tree.is_some());
+    #[test]
+    fn test_extract_checks_trailer() {
+        let dir = env::temp_dir().join(format!("gzip-main-{}-trailer", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let header = TarHeader {
+            path: b"file.txt".to_vec(),
+            entry_type: EntryType::File,
+            link: Vec::new(),
+            mode: 0o644,
+            mtime: 0,
+            size: 6,
+        };
+        let mut tar = TarWriter::new(GzEncoder::new(Vec::new(), CompressionLevel::DEFAULT));
+        tar.append(&header, &b"hello\n"[..]).unwrap();
+        let mut archive = tar.finish().unwrap().finish().unwrap();
+        let good = dir.join("good.tar.gz");
+        fs::write(&good, &archive).unwrap();
+        let trailer = archive.len() - 8;
+        archive[trailer] ^= 1;
+        let bad = dir.join("bad.tar.gz");
+        fs::write(&bad, &archive).unwrap();
+
+        let options = Options { mode: Mode::Decompress, ..Options::default() };
+        let out = dir.join("out");
+        let status = extract_archive(&options, good.to_str().unwrap(), out.to_str().unwrap()).unwrap();
+        assert_eq!(status, Status::Ok);
+        assert_eq!(fs::read(out.join("file.txt")).unwrap(), b"hello\n");
+        let err = extract_archive(&options, bad.to_str().unwrap(), out.to_str().unwrap()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_archive_name() {
+        assert_eq!(archive_name(Path::new("/srv/www/")).as_deref(), Some(&b"srv/www"[..]));
+        assert_eq!(archive_name(Path::new("./a//b/.")).as_deref(), Some(&b"a/b"[..]));
+        assert_eq!(archive_name(Path::new(".")).as_deref(), Some(&b"."[..]));
+        assert_eq!(archive_name(Path::new("a/../b")), None);
+    }
+
+    #[test]
+    fn test_decompressed_name() {
+        assert_eq!(decompressed_name("notes.txt.gz").as_deref(), Some("notes.txt"));
+        assert_eq!(decompressed_name("dir/release.tgz").as_deref(), Some("dir/release.tar"));
+        assert_eq!(decompressed_name("notes.txt"), None);
+        assert_eq!(decompressed_name(".gz"), None);
     }
 }
@@ -0,0 +1,288 @@
+//! Zopfli-style optimal parsing: the slow, small end of the trade-off.
+//!
+//! Every position of the input is a node in a graph, with an edge to the
+//! next position for a literal and an edge for each match length that can
+//! start there. Given the cost in bits of every symbol, the cheapest parse
+//! is a shortest path, found in one pass since all edges point forward. The
+//! symbol costs depend on the parse, though, so the search is repeated:
+//! each parse's statistics become the next cost model, and the parse with
+//! the smallest exact block size is kept. The output is plain DEFLATE.
+
+use crate::deflate::{block_cost, distance_symbol, length_symbol, BlockStats, CompressionLevel, DISTANCE_EXTRA};
+use crate::lz77::{lz77_compress_from, MatchConfig, MatchFinder, Token, LOOKAHEAD_SIZE, MIN_MATCH, WINDOW_SIZE};
+
+/// Cost-model refinements run when the caller has no preference
+pub const DEFAULT_ITERATIONS: usize = 15;
+
+/// Input parsed as one graph. The match cache costs about 16 bytes per
+/// input byte, so larger inputs are parsed a chunk at a time, each chunk
+/// able to match back into the one before it.
+const CHUNK_SIZE: usize = 1024 * 1024;
+
+/// Chain links followed when collecting matches; more finds slightly
+/// closer matches on highly repetitive input at a large cost in time
+const MAX_CHAIN: usize = 8192;
+
+/// Matches available at every position of a chunk, collected once and
+/// shared by all iterations
+struct MatchCache {
+    /// (length, distance) pairs as returned by `MatchFinder::all_matches`
+    matches: Vec<(u16, u16)>,
+    /// Matches of position `i` are `matches[offsets[i]..offsets[i + 1]]`
+    offsets: Vec<u32>,
+}
+
+impl MatchCache {
+    /// Matches for every position in `start..data.len()`, looking back
+    /// into `data[..start]` as well
+    fn new(data: &[u8], start: usize) -> Self {
+        let config = MatchConfig { max_chain: MAX_CHAIN, ..MatchConfig::default() };
+        let mut finder = MatchFinder::new(config);
+        for pos in start.saturating_sub(WINDOW_SIZE)..start {
+            finder.insert(data, pos);
+        }
+
+        let mut matches = Vec::new();
+        let mut offsets = vec![0];
+        let mut found = Vec::new();
+        for pos in start..data.len() {
+            found.clear();
+            finder.all_matches(data, pos, &mut found);
+            matches.extend(found.iter().map(|&(length, distance)| (length as u16, distance as u16)));
+            offsets.push(matches.len() as u32);
+            finder.insert(data, pos);
+        }
+        MatchCache { matches, offsets }
+    }
+
+    fn at(&self, i: usize) -> &[(u16, u16)] {
+        &self.matches[self.offsets[i] as usize..self.offsets[i + 1] as usize]
+    }
+}
+
+/// Estimated bits for each literal, match length and distance, extra bits
+/// included, from the symbol frequencies of an earlier parse
+struct CostModel {
+    literal: [f64; 256],
+    length: [f64; LOOKAHEAD_SIZE + 1],
+    distance: [f64; 30],
+}
+
+impl CostModel {
+    fn new(stats: &BlockStats) -> Self {
+        let litlen = symbol_bits(&stats.litlen);
+        let distance = symbol_bits(&stats.distance);
+
+        let mut model = CostModel { literal: [0.0; 256], length: [0.0; LOOKAHEAD_SIZE + 1], distance: [0.0; 30] };
+        model.literal.copy_from_slice(&litlen[..256]);
+        for length in MIN_MATCH..=LOOKAHEAD_SIZE {
+            let symbol = length_symbol(length);
+            model.length[length] = litlen[symbol.symbol as usize] + symbol.extra_bits as f64;
+        }
+        for (symbol, cost) in model.distance.iter_mut().enumerate() {
+            *cost = distance[symbol] + DISTANCE_EXTRA[symbol] as f64;
+        }
+        model
+    }
+
+    fn distance(&self, distance: usize) -> f64 {
+        self.distance[distance_symbol(distance).symbol as usize]
+    }
+}
+
+/// -log2 of each symbol's probability. Unused symbols cost as much as one
+/// seen once, so the next parse may still pick them up.
+fn symbol_bits(frequencies: &[usize]) -> Vec<f64> {
+    let total = frequencies.iter().sum::<usize>().max(1) as f64;
+    frequencies.iter().map(|&freq| total.log2() - (freq.max(1) as f64).log2()).collect()
+}
+
+/// Length of the run of bytes equal to `data[i]` starting at `i`, for each
+/// `i`, capped well above what the shortest path needs
+fn runs(data: &[u8]) -> Vec<u16> {
+    let mut same = vec![0u16; data.len()];
+    for i in (0..data.len()).rev() {
+        same[i] = if i + 1 < data.len() && data[i + 1] == data[i] {
+            same[i + 1].saturating_add(1)
+        } else {
+            1
+        };
+    }
+    same
+}
+
+/// Cheapest parse of `data[start..]` under `model`
+fn shortest_path(data: &[u8], start: usize, cache: &MatchCache, same: &[u16], model: &CostModel) -> Vec<Token> {
+    let n = data.len() - start;
+    let mut cost = vec![f64::INFINITY; n + 1];
+    // Length and distance of the edge that reaches each position cheapest;
+    // length 1 is a literal
+    let mut edge = vec![(0u16, 0u16); n + 1];
+    cost[0] = 0.0;
+
+    let mut i = 0;
+    while i < n {
+        // Inside a long run of one byte, the best parse is a string of
+        // maximum-length matches at distance 1; take them without trying
+        // every length at every position, as Zopfli does
+        let run = same[i] as usize;
+        if run > 2 * LOOKAHEAD_SIZE
+            && i > LOOKAHEAD_SIZE
+            && i + 2 * LOOKAHEAD_SIZE < n
+            && same[i - LOOKAHEAD_SIZE] as usize > LOOKAHEAD_SIZE
+        {
+            let step = model.length[LOOKAHEAD_SIZE] + model.distance(1);
+            for _ in 0..LOOKAHEAD_SIZE {
+                cost[i + LOOKAHEAD_SIZE] = cost[i] + step;
+                edge[i + LOOKAHEAD_SIZE] = (LOOKAHEAD_SIZE as u16, 1);
+                i += 1;
+            }
+            continue;
+        }
+
+        let here = cost[i];
+        let literal = here + model.literal[data[start + i] as usize];
+        if literal < cost[i + 1] {
+            cost[i + 1] = literal;
+            edge[i + 1] = (1, 0);
+        }
+
+        let mut shorter = MIN_MATCH - 1;
+        for &(longest, distance) in cache.at(i) {
+            let distance_cost = here + model.distance(distance as usize);
+            for length in shorter + 1..=longest as usize {
+                let total = distance_cost + model.length[length];
+                if total < cost[i + length] {
+                    cost[i + length] = total;
+                    edge[i + length] = (length as u16, distance);
+                }
+            }
+            shorter = longest as usize;
+        }
+        i += 1;
+    }
+
+    let mut tokens = Vec::new();
+    let mut j = n;
+    while j > 0 {
+        let (length, distance) = edge[j];
+        let length = length as usize;
+        if length == 1 {
+            tokens.push(Token::Literal(data[start + j - 1]));
+        } else {
+            tokens.push(Token::Reference { length, distance: distance as usize });
+        }
+        j -= length;
+    }
+    tokens.reverse();
+    tokens
+}
+
+/// Exact size of `tokens` as one block
+fn parse_cost(tokens: &[Token], len: usize) -> usize {
+    block_cost(&BlockStats::from_tokens(tokens), len)
+}
+
+/// Optimal parse of `data[start..]`, matches reaching into `data[..start]`
+fn parse_chunk(data: &[u8], start: usize, iterations: usize) -> Vec<Token> {
+    let len = data.len() - start;
+    let mut best = lz77_compress_from(data, start, CompressionLevel::BEST.match_config());
+    if len == 0 || iterations == 0 {
+        return best;
+    }
+    let mut best_cost = parse_cost(&best, len);
+
+    let cache = MatchCache::new(data, start);
+    let same = runs(&data[start..]);
+    let mut stats = BlockStats::from_tokens(&best);
+    let mut last = Vec::new();
+    for _ in 0..iterations {
+        let tokens = shortest_path(data, start, &cache, &same, &CostModel::new(&stats));
+        if tokens == last {
+            // The model reproduces itself; further iterations change nothing
+            break;
+        }
+        stats = BlockStats::from_tokens(&tokens);
+        let cost = parse_cost(&tokens, len);
+        if cost < best_cost {
+            best_cost = cost;
+            best = tokens.clone();
+        }
+        last = tokens;
+    }
+    best
+}
+
+/// Parse `data[start..]` for the smallest output, refining the cost model
+/// `iterations` times. `data[..start]` is a dictionary, as in
+/// `lz77_compress_from`. Far slower than any compression level.
+pub fn optimal_parse(data: &[u8], start: usize, iterations: usize) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    let mut chunk_start = start;
+    while chunk_start < data.len() {
+        let chunk_end = (chunk_start + CHUNK_SIZE).min(data.len());
+        let history = chunk_start.saturating_sub(WINDOW_SIZE);
+        tokens.extend(parse_chunk(&data[history..chunk_end], chunk_start - history, iterations));
+        chunk_start = chunk_end;
+    }
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::{deflate_ultra, deflate_with_level};
+    use crate::inflate::inflate;
+    use crate::test_data::text;
+
+    /// Expand tokens back into bytes, starting from `history`
+    fn expand(history: &[u8], tokens: &[Token]) -> Vec<u8> {
+        let mut out = history.to_vec();
+        for token in tokens {
+            match *token {
+                Token::Literal(byte) => out.push(byte),
+                Token::Reference { length, distance } => {
+                    let start = out.len() - distance;
+                    for i in 0..length {
+                        out.push(out[start + i]);
+                    }
+                }
+            }
+        }
+        out.split_off(history.len())
+    }
+
+    #[test]
+    fn test_tokens_reproduce_input() {
+        let data = text(50_000, 1951);
+        assert_eq!(expand(&[], &optimal_parse(&data, 0, 3)), data);
+        // With a dictionary, only the new bytes are parsed
+        assert_eq!(expand(&data[..1000], &optimal_parse(&data, 1000, 3)), &data[1000..]);
+    }
+
+    #[test]
+    fn test_long_runs() {
+        let mut data = vec![0u8; 5000];
+        data.extend(b"tail".repeat(100));
+        data.extend(vec![7u8; 3000]);
+        let tokens = optimal_parse(&data, 0, 2);
+        assert_eq!(expand(&[], &tokens), data);
+        assert!(tokens.len() < 60, "{} tokens", tokens.len());
+    }
+
+    #[test]
+    fn test_smaller_than_best_level() {
+        let data = text(50_000, 1951);
+        let ultra = deflate_ultra(&data, DEFAULT_ITERATIONS);
+        let best = deflate_with_level(&data, CompressionLevel::BEST);
+        assert_eq!(inflate(&ultra).unwrap(), data);
+        assert!(ultra.len() < best.len(), "{} vs {}", ultra.len(), best.len());
+    }
+
+    #[test]
+    fn test_edge_cases() {
+        for data in [&b""[..], b"a", b"ab", b"abc", b"abcabc"] {
+            assert_eq!(inflate(&deflate_ultra(data, DEFAULT_ITERATIONS)).unwrap(), data);
+        }
+    }
+}
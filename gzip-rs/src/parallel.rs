@@ -0,0 +1,289 @@
+//! pigz-style parallel compression. The input is cut into fixed-size chunks
+//! that are compressed independently, each primed with the 32 KiB before it
+//! as a dictionary so little ratio is lost. Every chunk but the last ends in
+//! a sync flush, which leaves the output byte-aligned, so the pieces can be
+//! concatenated into one DEFLATE stream. Chunk boundaries do not depend on
+//! the number of threads, so neither does the output.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+
+use crate::crc32::{crc32, crc32_combine};
+use crate::deflate::{CompressionLevel, Deflater};
+use crate::error::GzipError;
+use crate::gz::{extra_flags_for, GzHeader};
+use crate::lz77::WINDOW_SIZE;
+
+/// Input compressed as one unit of work
+pub const CHUNK_SIZE: usize = 128 * 1024;
+
+/// Number of threads to use when the caller has no preference
+pub fn default_threads() -> usize {
+    thread::available_parallelism().map_or(1, |n| n.get())
+}
+
+/// Compressed chunk plus what is needed to stitch it into the member
+struct Chunk {
+    deflated: Vec<u8>,
+    crc: u32,
+    len: usize,
+}
+
+/// Compress one chunk, letting it refer back into `history`
+fn compress_chunk(history: &[u8], input: &[u8], level: CompressionLevel, is_final: bool) -> Chunk {
+    let mut deflater = Deflater::with_dictionary(level, history);
+    deflater.write(input);
+    if is_final {
+        deflater.finish();
+    } else {
+        deflater.flush();
+    }
+    Chunk { deflated: deflater.take_output(), crc: crc32(input), len: input.len() }
+}
+
+/// Compress `data[start..]` chunk by chunk on up to `threads` threads;
+/// `data[..start]` is history the first chunk may refer to. Returns the
+/// DEFLATE bytes and the CRC-32 of the compressed input.
+fn deflate_chunks(
+    data: &[u8],
+    start: usize,
+    level: CompressionLevel,
+    threads: usize,
+    is_final: bool,
+) -> (Vec<u8>, u32) {
+    let mut bounds: Vec<usize> = (start..data.len()).step_by(CHUNK_SIZE).collect();
+    if bounds.is_empty() && is_final {
+        // Nothing left, but the stream still needs its final block
+        bounds.push(data.len());
+    }
+    let count = bounds.len();
+    let chunk = |i: usize| {
+        let begin = bounds[i];
+        let end = (begin + CHUNK_SIZE).min(data.len());
+        let history = &data[begin.saturating_sub(WINDOW_SIZE)..begin];
+        compress_chunk(history, &data[begin..end], level, is_final && i + 1 == count)
+    };
+
+    let mut chunks: Vec<(usize, Chunk)> = if threads <= 1 || count <= 1 {
+        (0..count).map(|i| (i, chunk(i))).collect()
+    } else {
+        let next = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads.min(count))
+                .map(|_| {
+                    scope.spawn(|| {
+                        let mut done = Vec::new();
+                        loop {
+                            let i = next.fetch_add(1, Ordering::Relaxed);
+                            if i >= count {
+                                return done;
+                            }
+                            done.push((i, chunk(i)));
+                        }
+                    })
+                })
+                .collect();
+            workers.into_iter().flat_map(|worker| worker.join().unwrap()).collect()
+        })
+    };
+    chunks.sort_by_key(|(i, _)| *i);
+
+    let mut deflated = Vec::new();
+    let mut crc = 0;
+    for (_, chunk) in chunks {
+        deflated.extend_from_slice(&chunk.deflated);
+        crc = crc32_combine(crc, chunk.crc, chunk.len as u64);
+    }
+    (deflated, crc)
+}
+
+/// Compress `data` into a raw DEFLATE stream using `threads` threads
+pub fn deflate_parallel(data: &[u8], level: CompressionLevel, threads: usize) -> Vec<u8> {
+    deflate_chunks(data, 0, level, threads, true).0
+}
+
+/// Compress `data` into a gzip file using `threads` threads
+pub fn gzip_parallel(data: &[u8], level: CompressionLevel, threads: usize) -> Vec<u8> {
+    let header = GzHeader { extra_flags: extra_flags_for(level), ..GzHeader::default() };
+    let mut out = header.encode();
+    let (deflated, crc) = deflate_chunks(data, 0, level, threads, true);
+    out.extend_from_slice(&deflated);
+    out.extend_from_slice(&crc.to_le_bytes());
+    // ISIZE is the input length modulo 2^32
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out
+}
+
+/// Streaming counterpart of `gzip_parallel`: input is collected until every
+/// thread has a chunk to work on, so memory stays at `threads` chunks plus
+/// the window. Call `finish` to write the trailer; dropping the encoder does
+/// the same but has to ignore errors.
+pub struct ParallelGzEncoder<W: Write> {
+    inner: Option<W>,
+    level: CompressionLevel,
+    threads: usize,
+    /// Header bytes not yet handed to `inner`
+    header: Vec<u8>,
+    /// History (at most `WINDOW_SIZE` bytes) followed by input not yet compressed
+    buffer: Vec<u8>,
+    /// Start of the uncompressed input in `buffer`
+    pending: usize,
+    crc: u32,
+    size: u32,
+}
+
+impl<W: Write> ParallelGzEncoder<W> {
+    pub fn new(inner: W, level: CompressionLevel, threads: usize) -> Self {
+        let header = GzHeader { extra_flags: extra_flags_for(level), ..GzHeader::default() };
+        Self::with_header_bytes(inner, header.encode(), level, threads)
+    }
+
+    /// An encoder writing `header`; fails if it cannot be written, see
+    /// `GzHeader::to_bytes`
+    pub fn with_header(
+        inner: W,
+        header: &GzHeader,
+        level: CompressionLevel,
+        threads: usize,
+    ) -> Result<Self, GzipError> {
+        Ok(Self::with_header_bytes(inner, header.to_bytes()?, level, threads))
+    }
+
+    fn with_header_bytes(inner: W, header: Vec<u8>, level: CompressionLevel, threads: usize) -> Self {
+        ParallelGzEncoder {
+            inner: Some(inner),
+            level,
+            threads: threads.max(1),
+            header,
+            buffer: Vec::new(),
+            pending: 0,
+            crc: 0,
+            size: 0,
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    fn batch_size(&self) -> usize {
+        self.threads * CHUNK_SIZE
+    }
+
+    /// Compress the pending input and hand the result to the inner writer
+    fn compress_pending(&mut self, is_final: bool) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        if !self.header.is_empty() {
+            inner.write_all(&self.header)?;
+            self.header.clear();
+        }
+        if self.pending == self.buffer.len() && !is_final {
+            return Ok(());
+        }
+
+        let len = self.buffer.len() - self.pending;
+        let (deflated, crc) = deflate_chunks(&self.buffer, self.pending, self.level, self.threads, is_final);
+        inner.write_all(&deflated)?;
+        self.crc = crc32_combine(self.crc, crc, len as u64);
+
+        // Slide: keep only the window later chunks may refer back to
+        let keep_from = self.buffer.len().saturating_sub(WINDOW_SIZE);
+        self.buffer.drain(..keep_from);
+        self.pending = self.buffer.len();
+        Ok(())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        self.compress_pending(true)?;
+
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&self.crc.to_le_bytes())?;
+        inner.write_all(&self.size.to_le_bytes())?;
+        inner.flush()
+    }
+
+    /// Compress any remaining input, write the trailer and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+}
+
+impl<W: Write> Write for ParallelGzEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let room = self.batch_size() - (self.buffer.len() - self.pending);
+        let n = room.min(buf.len());
+        self.buffer.extend_from_slice(&buf[..n]);
+        // ISIZE is the input length modulo 2^32
+        self.size = self.size.wrapping_add(n as u32);
+
+        if self.buffer.len() - self.pending == self.batch_size() {
+            self.compress_pending(false)?;
+        }
+        Ok(n)
+    }
+
+    /// Sync flush: every chunk already ends in one, so this just compresses
+    /// what is pending
+    fn flush(&mut self) -> io::Result<()> {
+        self.compress_pending(false)?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for ParallelGzEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gz::gunzip;
+    use crate::inflate::inflate;
+    use crate::test_data::text;
+
+    #[test]
+    fn test_round_trip() {
+        for len in [0, 1, CHUNK_SIZE, 2 * CHUNK_SIZE + 17] {
+            let data = text(len, 1951);
+            for level in [0, 1, 6] {
+                let level = CompressionLevel::new(level).unwrap();
+                assert_eq!(gunzip(&gzip_parallel(&data, level, 4)).unwrap(), data);
+                assert_eq!(inflate(&deflate_parallel(&data, level, 3)).unwrap(), data);
+            }
+        }
+    }
+
+    #[test]
+    fn test_output_independent_of_thread_count() {
+        let data = text(3 * CHUNK_SIZE + 1000, 1951);
+        let single = gzip_parallel(&data, CompressionLevel::DEFAULT, 1);
+        assert_eq!(gzip_parallel(&data, CompressionLevel::DEFAULT, 2), single);
+        assert_eq!(gzip_parallel(&data, CompressionLevel::DEFAULT, 8), single);
+    }
+
+    #[test]
+    fn test_dictionary_keeps_ratio() {
+        let data = text(3 * CHUNK_SIZE, 1951);
+        let serial = crate::gz::gzip(&data);
+        let parallel = gzip_parallel(&data, CompressionLevel::DEFAULT, 4);
+        // Only the sync-flush markers and block restarts are extra
+        assert!(parallel.len() < serial.len() + serial.len() / 50, "{} vs {}", parallel.len(), serial.len());
+    }
+
+    #[test]
+    fn test_streaming_matches_one_shot() {
+        let data = text(4 * CHUNK_SIZE + 5, 1951);
+        let mut encoder = ParallelGzEncoder::new(Vec::new(), CompressionLevel::DEFAULT, 3);
+        for piece in data.chunks(10_000) {
+            encoder.write_all(piece).unwrap();
+        }
+        let streamed = encoder.finish().unwrap();
+        assert_eq!(streamed, gzip_parallel(&data, CompressionLevel::DEFAULT, 3));
+    }
+}
@@ -0,0 +1,383 @@
+//! An adaptive binary range coder for LZ77 tokens, in the style of LZMA.
+//!
+//! Every decision is coded as one bit against a probability that adapts as
+//! bits are seen: literal or match, then the literal byte, the match length
+//! or the distance slot, one bit at a time down a binary tree. Unlike a
+//! Huffman code nothing is sent up front and a symbol may cost a fraction
+//! of a bit, so the coder follows local statistics without block headers.
+//! The low extra bits of a distance are modelled too; the rest go out raw.
+
+use crate::deflate::{distance_symbol, DISTANCE_BASE, DISTANCE_EXTRA, NUM_DISTANCE_SYMBOLS};
+use crate::entropy::EntropyCoder;
+use crate::error::GzipError;
+use crate::lz77::{Token, LOOKAHEAD_SIZE, MIN_MATCH, WINDOW_SIZE};
+
+/// Probabilities are fractions of 2^PROB_BITS
+const PROB_BITS: u32 = 11;
+
+/// Every probability starts at one half
+const PROB_INIT: u16 = 1 << (PROB_BITS - 1);
+
+/// How far a probability moves towards each bit seen; smaller adapts faster
+const MOVE_BITS: u32 = 5;
+
+/// The range is renormalised a byte at a time once it drops below this
+const TOP: u32 = 1 << 24;
+
+/// Low bits of a distance coded with a model rather than raw
+const MODELLED_EXTRA_BITS: u8 = 4;
+
+/// Literal contexts, chosen by the top bits of the previous literal
+const LITERAL_CONTEXTS: usize = 8;
+
+/// Distance slot contexts, chosen by the match length: short matches tend
+/// to be close
+const DISTANCE_CONTEXTS: usize = 4;
+
+/// More tokens than a byte of coded input can hold. A probability never
+/// passes 2017/2048, so each of a token's nine or more decisions costs at
+/// least 0.02 bits and a token at least 0.2.
+const MAX_TOKENS_PER_BYTE: u64 = 64;
+
+/// The adaptive probabilities, identical on both sides
+struct Model {
+    /// Literal or match, by whether the previous token was a match
+    is_match: [u16; 2],
+    /// 8-bit trees over the literal byte
+    literal: [[u16; 256]; LITERAL_CONTEXTS],
+    /// 8-bit tree over `length - MIN_MATCH`, which covers 3..=258 exactly
+    length: [u16; 256],
+    /// 5-bit trees over the DEFLATE distance code
+    slot: [[u16; 32]; DISTANCE_CONTEXTS],
+    /// Trees over the low extra bits, one per distance code
+    extra: [[u16; 1 << MODELLED_EXTRA_BITS]; NUM_DISTANCE_SYMBOLS],
+    /// Whether the previous token was a match
+    after_match: bool,
+    /// Context from the most recent literal
+    literal_context: usize,
+}
+
+impl Model {
+    fn new() -> Self {
+        Model {
+            is_match: [PROB_INIT; 2],
+            literal: [[PROB_INIT; 256]; LITERAL_CONTEXTS],
+            length: [PROB_INIT; 256],
+            slot: [[PROB_INIT; 32]; DISTANCE_CONTEXTS],
+            extra: [[PROB_INIT; 1 << MODELLED_EXTRA_BITS]; NUM_DISTANCE_SYMBOLS],
+            after_match: false,
+            literal_context: 0,
+        }
+    }
+
+    fn distance_context(length: usize) -> usize {
+        (length - MIN_MATCH).min(DISTANCE_CONTEXTS - 1)
+    }
+}
+
+struct Encoder {
+    low: u64,
+    range: u32,
+    /// The byte held back in case a carry still has to reach it, followed
+    /// by `cache_size - 1` bytes of 0xff that the carry would also flip
+    cache: u8,
+    cache_size: u64,
+    out: Vec<u8>,
+}
+
+impl Encoder {
+    fn new(out: Vec<u8>) -> Self {
+        Encoder { low: 0, range: u32::MAX, cache: 0, cache_size: 1, out }
+    }
+
+    fn bit(&mut self, prob: &mut u16, bit: u32) {
+        let bound = (self.range >> PROB_BITS) * *prob as u32;
+        if bit == 0 {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+        } else {
+            self.low += bound as u64;
+            self.range -= bound;
+            *prob -= *prob >> MOVE_BITS;
+        }
+        self.normalize();
+    }
+
+    /// The low `count` bits of `value` at probability one half each
+    fn direct(&mut self, value: u32, count: u8) {
+        for i in (0..count).rev() {
+            self.range >>= 1;
+            if (value >> i) & 1 == 1 {
+                self.low += self.range as u64;
+            }
+            self.normalize();
+        }
+    }
+
+    /// The low `count` bits of `value`, most significant first, down a
+    /// tree of `probs` with the root at index 1
+    fn tree(&mut self, probs: &mut [u16], count: u8, value: u32) {
+        let mut node = 1;
+        for i in (0..count).rev() {
+            let bit = (value >> i) & 1;
+            self.bit(&mut probs[node], bit);
+            node = (node << 1) | bit as usize;
+        }
+    }
+
+    fn normalize(&mut self) {
+        while self.range < TOP {
+            self.range <<= 8;
+            self.shift_low();
+        }
+    }
+
+    fn shift_low(&mut self) {
+        if self.low < 0xff00_0000 || self.low >= 1 << 32 {
+            let carry = (self.low >> 32) as u8;
+            let mut byte = self.cache;
+            loop {
+                self.out.push(byte.wrapping_add(carry));
+                byte = 0xff;
+                self.cache_size -= 1;
+                if self.cache_size == 0 {
+                    break;
+                }
+            }
+            self.cache = (self.low >> 24) as u8;
+        }
+        self.cache_size += 1;
+        self.low = (self.low & 0x00ff_ffff) << 8;
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        for _ in 0..5 {
+            self.shift_low();
+        }
+        self.out
+    }
+
+    fn token(&mut self, model: &mut Model, token: &Token) {
+        match *token {
+            Token::Literal(byte) => {
+                self.bit(&mut model.is_match[model.after_match as usize], 0);
+                self.tree(&mut model.literal[model.literal_context], 8, byte as u32);
+                model.literal_context = byte as usize >> 5;
+                model.after_match = false;
+            }
+            Token::Reference { length, distance } => {
+                debug_assert!((MIN_MATCH..=LOOKAHEAD_SIZE).contains(&length), "length {} out of range", length);
+                debug_assert!((1..=WINDOW_SIZE).contains(&distance), "distance {} out of range", distance);
+                self.bit(&mut model.is_match[model.after_match as usize], 1);
+                self.tree(&mut model.length, 8, (length - MIN_MATCH) as u32);
+
+                let symbol = distance_symbol(distance);
+                let slot = symbol.symbol as usize;
+                self.tree(&mut model.slot[Model::distance_context(length)], 5, slot as u32);
+                let modelled = symbol.extra_bits.min(MODELLED_EXTRA_BITS);
+                self.direct((symbol.extra >> modelled) as u32, symbol.extra_bits - modelled);
+                self.tree(&mut model.extra[slot], modelled, symbol.extra as u32 & ((1 << modelled) - 1));
+                model.after_match = true;
+            }
+        }
+    }
+}
+
+struct Decoder<'a> {
+    data: &'a [u8],
+    pos: usize,
+    range: u32,
+    code: u32,
+}
+
+impl<'a> Decoder<'a> {
+    fn new(data: &'a [u8], pos: usize) -> Result<Self, GzipError> {
+        let mut decoder = Decoder { data, pos, range: u32::MAX, code: 0 };
+        // The encoder's first byte is always zero; anything else is not ours
+        if decoder.next_byte()? != 0 {
+            return Err(GzipError::InvalidSymbol { offset: pos });
+        }
+        for _ in 0..4 {
+            decoder.code = (decoder.code << 8) | decoder.next_byte()?;
+        }
+        Ok(decoder)
+    }
+
+    fn next_byte(&mut self) -> Result<u32, GzipError> {
+        let byte = *self.data.get(self.pos).ok_or(GzipError::TruncatedStream { offset: self.pos })?;
+        self.pos += 1;
+        Ok(byte as u32)
+    }
+
+    fn bit(&mut self, prob: &mut u16) -> Result<u32, GzipError> {
+        let bound = (self.range >> PROB_BITS) * *prob as u32;
+        let bit = if self.code < bound {
+            self.range = bound;
+            *prob += ((1 << PROB_BITS) - *prob) >> MOVE_BITS;
+            0
+        } else {
+            self.code -= bound;
+            self.range -= bound;
+            *prob -= *prob >> MOVE_BITS;
+            1
+        };
+        self.normalize()?;
+        Ok(bit)
+    }
+
+    fn direct(&mut self, count: u8) -> Result<u32, GzipError> {
+        let mut value = 0;
+        for _ in 0..count {
+            self.range >>= 1;
+            let bit = (self.code >= self.range) as u32;
+            if bit == 1 {
+                self.code -= self.range;
+            }
+            value = (value << 1) | bit;
+            self.normalize()?;
+        }
+        Ok(value)
+    }
+
+    fn tree(&mut self, probs: &mut [u16], count: u8) -> Result<u32, GzipError> {
+        let mut node = 1;
+        for _ in 0..count {
+            node = (node << 1) | self.bit(&mut probs[node])? as usize;
+        }
+        Ok((node - (1 << count)) as u32)
+    }
+
+    fn normalize(&mut self) -> Result<(), GzipError> {
+        if self.range < TOP {
+            self.range <<= 8;
+            self.code = (self.code << 8) | self.next_byte()?;
+        }
+        Ok(())
+    }
+
+    fn token(&mut self, model: &mut Model) -> Result<Token, GzipError> {
+        if self.bit(&mut model.is_match[model.after_match as usize])? == 0 {
+            let byte = self.tree(&mut model.literal[model.literal_context], 8)? as u8;
+            model.literal_context = byte as usize >> 5;
+            model.after_match = false;
+            return Ok(Token::Literal(byte));
+        }
+
+        let length = self.tree(&mut model.length, 8)? as usize + MIN_MATCH;
+        let offset = self.pos;
+        let slot = self.tree(&mut model.slot[Model::distance_context(length)], 5)? as usize;
+        if slot >= NUM_DISTANCE_SYMBOLS {
+            return Err(GzipError::InvalidSymbol { offset });
+        }
+        let extra_bits = DISTANCE_EXTRA[slot];
+        let modelled = extra_bits.min(MODELLED_EXTRA_BITS);
+        let high = self.direct(extra_bits - modelled)?;
+        let low = self.tree(&mut model.extra[slot], modelled)?;
+        model.after_match = true;
+
+        let distance = DISTANCE_BASE[slot] as usize + ((high << modelled) | low) as usize;
+        Ok(Token::Reference { length, distance })
+    }
+}
+
+/// Adaptive range coding of each token. The stream is the token count as a
+/// little-endian u64 followed by the coded bits.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct RangeCoder;
+
+impl EntropyCoder for RangeCoder {
+    fn name(&self) -> &'static str {
+        "range"
+    }
+
+    fn encode(&self, tokens: &[Token]) -> Vec<u8> {
+        let mut encoder = Encoder::new((tokens.len() as u64).to_le_bytes().to_vec());
+        let mut model = Model::new();
+        for token in tokens {
+            encoder.token(&mut model, token);
+        }
+        encoder.finish()
+    }
+
+    fn decode(&self, data: &[u8]) -> Result<Vec<Token>, GzipError> {
+        let count = data
+            .get(..8)
+            .ok_or(GzipError::TruncatedStream { offset: data.len() })?;
+        let count = u64::from_le_bytes(count.try_into().unwrap());
+        // A well-predicted token costs well under a byte, so the count alone
+        // could ask for a long decode before the input runs out; refuse one
+        // the input cannot possibly hold
+        if count > (data.len() as u64 - 8 + 1) * MAX_TOKENS_PER_BYTE {
+            return Err(GzipError::TruncatedStream { offset: data.len() });
+        }
+
+        let mut decoder = Decoder::new(data, 8)?;
+        let mut model = Model::new();
+        let mut tokens = Vec::new();
+        for _ in 0..count {
+            tokens.push(decoder.token(&mut model)?);
+        }
+        Ok(tokens)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lz77::lz77_compress;
+
+    #[test]
+    fn test_round_trip() {
+        let data = include_bytes!("../tests/fixtures/sample.txt").repeat(4);
+        let tokens = lz77_compress(&data, WINDOW_SIZE, LOOKAHEAD_SIZE);
+        let encoded = RangeCoder.encode(&tokens);
+        assert_eq!(RangeCoder.decode(&encoded).unwrap(), tokens);
+        assert!(encoded.len() < data.len() / 2);
+    }
+
+    #[test]
+    fn test_extreme_tokens() {
+        let tokens = vec![
+            Token::Literal(0),
+            Token::Literal(255),
+            Token::Reference { length: 3, distance: 1 },
+            Token::Reference { length: 258, distance: 2 },
+            Token::Reference { length: 100, distance: WINDOW_SIZE },
+        ];
+        assert_eq!(RangeCoder.decode(&RangeCoder.encode(&tokens)).unwrap(), tokens);
+        assert_eq!(RangeCoder.decode(&RangeCoder.encode(&[])).unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_carries() {
+        // Highly skewed bits push `low` against the top of the range, so
+        // carries ripple through runs of 0xff bytes
+        let tokens: Vec<Token> = (0..20_000).map(|i| Token::Literal(if i % 97 == 0 { 1 } else { 255 })).collect();
+        assert_eq!(RangeCoder.decode(&RangeCoder.encode(&tokens)).unwrap(), tokens);
+    }
+
+    #[test]
+    fn test_predictable_tokens() {
+        // As cheap as tokens get, and still within the count check
+        let tokens = vec![Token::Literal(0); 200_000];
+        let encoded = RangeCoder.encode(&tokens);
+        assert!(encoded.len() * 32 < tokens.len(), "{} bytes", encoded.len());
+        assert_eq!(RangeCoder.decode(&encoded).unwrap(), tokens);
+    }
+
+    #[test]
+    fn test_truncated() {
+        let tokens = lz77_compress(b"the quick brown fox jumps over the lazy dog", WINDOW_SIZE, LOOKAHEAD_SIZE);
+        let encoded = RangeCoder.encode(&tokens);
+        assert!(matches!(
+            RangeCoder.decode(&encoded[..encoded.len() - 5]),
+            Err(GzipError::TruncatedStream { .. })
+        ));
+        assert_eq!(RangeCoder.decode(&encoded[..3]), Err(GzipError::TruncatedStream { offset: 3 }));
+
+        // A count no input this short could hold fails before decoding
+        let mut inflated = encoded.clone();
+        inflated[..8].copy_from_slice(&u64::MAX.to_le_bytes());
+        assert_eq!(RangeCoder.decode(&inflated), Err(GzipError::TruncatedStream { offset: encoded.len() }));
+    }
+}
@@ -0,0 +1,266 @@
+//! Block splitting: where one DEFLATE block should end and the next begin.
+//!
+//! Each block carries its own Huffman tables, so starting a new block pays
+//! off when the symbol statistics change, e.g. between the text and binary
+//! members of a tarball. Like Zopfli, the token stream is split recursively:
+//! the split point that minimises the estimated size of the two halves is
+//! tried, kept if the exact block costs agree it helps, and each half is
+//! split again.
+
+use crate::deflate::{block_cost, token_len, BlockStats, END_OF_BLOCK, MAX_STORED_BLOCK};
+use crate::lz77::Token;
+
+/// Tokens between candidate split points
+const GRANULE: usize = 512;
+
+/// Tokens searched for split points at once. Longer streams are cut into
+/// spans this long first, which bounds both memory and search time.
+const MAX_SPAN_TOKENS: usize = 64 * 1024;
+
+/// Candidate split points sampled per round of the search
+const SEARCH_POINTS: usize = 9;
+
+/// Rough size of a dynamic block header: a fixed part plus a few bits per
+/// code length sent. Only used to rank split points; the decision itself is
+/// checked with exact costs.
+const HEADER_BASE_BITS: f64 = 70.0;
+const HEADER_BITS_PER_SYMBOL: f64 = 4.0;
+
+/// Statistics of every prefix of a span that ends on a granule boundary
+struct Prefixes {
+    stats: Vec<BlockStats>,
+    /// Input bytes covered by each prefix
+    bytes: Vec<usize>,
+    /// Literal/length symbols that occur anywhere in the span; the rest
+    /// count zero in every block and can be skipped when estimating
+    used_litlen: Vec<usize>,
+    used_distance: Vec<usize>,
+}
+
+impl Prefixes {
+    fn new(tokens: &[Token]) -> Self {
+        let mut stats = vec![BlockStats::new()];
+        let mut bytes = vec![0];
+        for granule in tokens.chunks(GRANULE) {
+            let mut next = stats.last().unwrap().clone();
+            for token in granule {
+                next.add(token);
+            }
+            bytes.push(bytes.last().unwrap() + granule.iter().map(token_len).sum::<usize>());
+            stats.push(next);
+        }
+        let total = stats.last().unwrap();
+        let used = |counts: &[usize]| (0..counts.len()).filter(|&symbol| counts[symbol] > 0).collect();
+        let used_litlen = used(&total.litlen);
+        let used_distance = used(&total.distance);
+        Prefixes { stats, bytes, used_litlen, used_distance }
+    }
+
+    fn granules(&self) -> usize {
+        self.stats.len() - 1
+    }
+
+    /// Statistics and input length of a block holding granules `start..end`
+    fn block(&self, start: usize, end: usize) -> (BlockStats, usize) {
+        let (from, to) = (&self.stats[start], &self.stats[end]);
+        let mut stats = to.clone();
+        for (count, &before) in stats.litlen.iter_mut().zip(&from.litlen) {
+            *count -= before;
+        }
+        for (count, &before) in stats.distance.iter_mut().zip(&from.distance) {
+            *count -= before;
+        }
+        stats.extra_bits -= from.extra_bits;
+        stats.litlen[END_OF_BLOCK] = 1;
+        (stats, self.bytes[end] - self.bytes[start])
+    }
+
+    /// Cheap approximation of the size of the best block for granules
+    /// `start..end`, computed straight from the prefix counts
+    fn estimate(&self, start: usize, end: usize) -> f64 {
+        let (from, to) = (&self.stats[start], &self.stats[end]);
+        let len = self.bytes[end] - self.bytes[start];
+
+        let mut litlen = SymbolCosts::default();
+        for &symbol in &self.used_litlen {
+            litlen.add(to.litlen[symbol] - from.litlen[symbol], fixed_litlen_bits(symbol));
+        }
+        // The end-of-block symbol, not counted in the prefixes
+        litlen.add(1, 7);
+        let mut distance = SymbolCosts::default();
+        for &symbol in &self.used_distance {
+            distance.add(to.distance[symbol] - from.distance[symbol], 5);
+        }
+
+        let dynamic = HEADER_BASE_BITS
+            + HEADER_BITS_PER_SYMBOL * (litlen.used + distance.used) as f64
+            + litlen.entropy_bits()
+            + distance.entropy_bits();
+        let fixed = (litlen.fixed_bits + distance.fixed_bits) as f64;
+        let extra_bits = (to.extra_bits - from.extra_bits) as f64;
+        let stored = (len * 8 + 40 * len.div_ceil(MAX_STORED_BLOCK).max(1)) as f64;
+        (dynamic.min(fixed) + extra_bits).min(stored)
+    }
+
+    fn exact(&self, start: usize, end: usize) -> usize {
+        let (stats, len) = self.block(start, end);
+        block_cost(&stats, len)
+    }
+}
+
+/// Running totals for estimating the cost of one alphabet
+#[derive(Default)]
+struct SymbolCosts {
+    total: usize,
+    used: usize,
+    /// Sum of freq * log2(freq) over the symbols
+    freq_log_freq: f64,
+    /// Bits under the fixed code
+    fixed_bits: usize,
+}
+
+impl SymbolCosts {
+    fn add(&mut self, freq: usize, fixed_length: usize) {
+        if freq > 0 {
+            let freq_f = freq as f64;
+            self.total += freq;
+            self.used += 1;
+            self.freq_log_freq += freq_f * freq_f.log2();
+            self.fixed_bits += freq * fixed_length;
+        }
+    }
+
+    /// Bits an ideal code would spend on these symbols
+    fn entropy_bits(&self) -> f64 {
+        if self.total == 0 {
+            return 0.0;
+        }
+        let total = self.total as f64;
+        total * total.log2() - self.freq_log_freq
+    }
+}
+
+/// Length of a symbol in the fixed literal/length code
+fn fixed_litlen_bits(symbol: usize) -> usize {
+    match symbol {
+        0..=143 => 8,
+        144..=255 => 9,
+        256..=279 => 7,
+        _ => 8,
+    }
+}
+
+/// Split point in granules `start + 1..end` with the smallest estimated
+/// cost for the two halves. Long ranges are searched coarse to fine, like
+/// Zopfli does: evenly spaced points first, then around the best of them.
+fn best_split(prefixes: &Prefixes, start: usize, end: usize) -> (usize, f64) {
+    let cost = |mid: usize| prefixes.estimate(start, mid) + prefixes.estimate(mid, end);
+
+    let (mut low, mut high) = (start + 1, end);
+    while high - low > SEARCH_POINTS {
+        let points: Vec<usize> =
+            (1..=SEARCH_POINTS).map(|i| low + i * (high - low) / (SEARCH_POINTS + 1)).collect();
+        let costs: Vec<f64> = points.iter().map(|&mid| cost(mid)).collect();
+        let i = (0..SEARCH_POINTS).min_by(|&a, &b| costs[a].total_cmp(&costs[b])).unwrap();
+        if i > 0 {
+            low = points[i - 1];
+        }
+        if i + 1 < SEARCH_POINTS {
+            high = points[i + 1];
+        }
+    }
+    (low..high).map(|mid| (mid, cost(mid))).min_by(|a, b| a.1.total_cmp(&b.1)).unwrap()
+}
+
+/// Recursively split granules `start..end`, pushing split points in order
+fn split_range(prefixes: &Prefixes, start: usize, end: usize, splits: &mut Vec<usize>) {
+    if end - start < 2 {
+        return;
+    }
+
+    let (best, best_estimate) = best_split(prefixes, start, end);
+    if best_estimate >= prefixes.estimate(start, end) {
+        return;
+    }
+    if prefixes.exact(start, best) + prefixes.exact(best, end) >= prefixes.exact(start, end) {
+        return;
+    }
+
+    split_range(prefixes, start, best, splits);
+    splits.push(best);
+    split_range(prefixes, best, end, splits);
+}
+
+/// Where each block should end: increasing token indices, the last being
+/// `tokens.len()`
+pub(crate) fn block_boundaries(tokens: &[Token]) -> Vec<usize> {
+    let mut ends = Vec::new();
+
+    for span_start in (0..tokens.len()).step_by(MAX_SPAN_TOKENS) {
+        let span_end = (span_start + MAX_SPAN_TOKENS).min(tokens.len());
+        let prefixes = Prefixes::new(&tokens[span_start..span_end]);
+
+        let mut splits = Vec::new();
+        split_range(&prefixes, 0, prefixes.granules(), &mut splits);
+        ends.extend(splits.into_iter().map(|granule| span_start + granule * GRANULE));
+        ends.push(span_end);
+    }
+
+    ends
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::deflate::deflate;
+    use crate::inflate::inflate;
+    use crate::test_data::values;
+
+    /// Lowercase text, then bytes with no structure, then text again
+    fn heterogeneous() -> Vec<u8> {
+        let values = values(120_000, 12_345);
+        let text = |values: &[u32]| values.iter().map(|&value| b"etaoin shrdlu"[value as u8 as usize % 13]).collect();
+        let mut data: Vec<u8> = text(&values[..40_000]);
+        data.extend(values[40_000..80_000].iter().map(|&value| value as u8));
+        data.extend(text(&values[80_000..]));
+        data
+    }
+
+    #[test]
+    fn test_splits_where_statistics_change() {
+        let tokens: Vec<Token> = heterogeneous().into_iter().map(Token::Literal).collect();
+        let ends = block_boundaries(&tokens);
+
+        assert_eq!(*ends.last().unwrap(), tokens.len());
+        for change in [40_000, 80_000] {
+            assert!(
+                ends.iter().any(|&end| end.abs_diff(change) <= GRANULE),
+                "no split near {} in {:?}",
+                change,
+                ends
+            );
+        }
+    }
+
+    #[test]
+    fn test_keeps_uniform_data_together() {
+        let tokens: Vec<Token> = b"abcd".repeat(10_000).into_iter().map(Token::Literal).collect();
+        assert_eq!(block_boundaries(&tokens), [tokens.len()]);
+    }
+
+    #[test]
+    fn test_long_streams_split_into_spans() {
+        let tokens = vec![Token::Literal(b'x'); MAX_SPAN_TOKENS + 10];
+        assert_eq!(block_boundaries(&tokens), [MAX_SPAN_TOKENS, MAX_SPAN_TOKENS + 10]);
+    }
+
+    #[test]
+    fn test_heterogeneous_round_trip() {
+        let data = heterogeneous();
+        let compressed = deflate(&data);
+        assert_eq!(inflate(&compressed).unwrap(), data);
+        // The random middle is stored; each text run gets its own code of
+        // a little over 4 bits per byte
+        assert!(compressed.len() < 40_000 + 2 * 22_000, "{}", compressed.len());
+    }
+}
@@ -0,0 +1,871 @@
+//! ustar archives (POSIX.1-1988) with pax extended headers (POSIX.1-2001)
+//! for what ustar cannot hold: paths over 255 bytes, link targets over 100,
+//! sizes of 8 GiB or more. Both directions stream, so a `.tar.gz` is a
+//! `TarWriter` over a `GzEncoder` or a `TarReader` over a `GzDecoder` and
+//! never sits in memory whole.
+//!
+//! Regular files, directories and symbolic links are supported, with their
+//! permission bits and modification times; the reader also understands GNU
+//! long-name entries, and passes over other entry types.
+
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Component, Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
+
+const BLOCK_SIZE: usize = 512;
+
+/// Largest value of an 11-digit octal field
+const MAX_OCTAL: u64 = (1 << 33) - 1;
+
+/// Largest pax or GNU long-name entry accepted, so a damaged header cannot
+/// make the reader allocate without bound
+const MAX_METADATA: u64 = 1 << 20;
+
+/// Reasons an archive cannot be read or unpacked. Offsets are byte
+/// positions in the uncompressed archive.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum TarError {
+    /// A header has a bad number field or pax record
+    InvalidHeader { offset: u64 },
+    /// A header's checksum does not match its contents
+    ChecksumMismatch { offset: u64 },
+    /// The archive ended inside an entry or before its end marker
+    UnexpectedEof { offset: u64 },
+    /// An entry would be unpacked outside the destination directory: its
+    /// path is absolute, goes through `..`, or goes through a symlink
+    UnsafePath { path: Vec<u8> },
+    /// Reading the archive or writing the files failed
+    Io(io::ErrorKind, String),
+}
+
+impl fmt::Display for TarError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TarError::InvalidHeader { offset } => write!(f, "invalid tar header at byte {}", offset),
+            TarError::ChecksumMismatch { offset } => write!(f, "tar header checksum mismatch at byte {}", offset),
+            TarError::UnexpectedEof { offset } => write!(f, "unexpected end of archive at byte {}", offset),
+            TarError::UnsafePath { path } => {
+                write!(f, "refusing to unpack {} outside the destination", String::from_utf8_lossy(path))
+            }
+            TarError::Io(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for TarError {}
+
+impl From<io::Error> for TarError {
+    fn from(err: io::Error) -> Self {
+        // Reading entry data reports archive errors wrapped in io::Error
+        if let Some(err) = err.get_ref().and_then(|inner| inner.downcast_ref::<TarError>()) {
+            return err.clone();
+        }
+        TarError::Io(err.kind(), err.to_string())
+    }
+}
+
+impl From<TarError> for io::Error {
+    fn from(err: TarError) -> Self {
+        match err {
+            TarError::Io(kind, message) => io::Error::new(kind, message),
+            TarError::UnexpectedEof { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EntryType {
+    File,
+    Directory,
+    Symlink,
+    /// Anything else (hard links, devices, FIFOs...), by its typeflag byte
+    Other(u8),
+}
+
+impl EntryType {
+    fn typeflag(self) -> u8 {
+        match self {
+            EntryType::File => b'0',
+            EntryType::Directory => b'5',
+            EntryType::Symlink => b'2',
+            EntryType::Other(flag) => flag,
+        }
+    }
+}
+
+/// One archive entry, without its data
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TarHeader {
+    /// '/'-separated path, without a trailing '/' for directories
+    pub path: Vec<u8>,
+    pub entry_type: EntryType,
+    /// Target of a symlink, empty otherwise
+    pub link: Vec<u8>,
+    /// Permission bits
+    pub mode: u32,
+    /// Modification time in seconds since the Unix epoch
+    pub mtime: u64,
+    /// Length of the data that follows; always 0 except for files
+    pub size: u64,
+}
+
+/// Writes an archive entry by entry
+pub struct TarWriter<W: Write> {
+    inner: W,
+}
+
+impl<W: Write> TarWriter<W> {
+    pub fn new(inner: W) -> Self {
+        TarWriter { inner }
+    }
+
+    /// Add an entry, copying exactly `header.size` bytes of `data` after it
+    pub fn append<R: Read>(&mut self, header: &TarHeader, data: R) -> io::Result<()> {
+        let mut path = header.path.clone();
+        if header.entry_type == EntryType::Directory && !path.ends_with(b"/") {
+            path.push(b'/');
+        }
+
+        let mut pax = Vec::new();
+        let (name, prefix) = match split_path(&path) {
+            Some(fields) => fields,
+            None => {
+                pax_record(&mut pax, "path", &path);
+                (&path[..100], &[][..])
+            }
+        };
+        let link = if header.link.len() > 100 {
+            pax_record(&mut pax, "linkpath", &header.link);
+            &[][..]
+        } else {
+            &header.link[..]
+        };
+        if header.size > MAX_OCTAL {
+            pax_record(&mut pax, "size", header.size.to_string().as_bytes());
+        }
+        if header.mtime > MAX_OCTAL {
+            pax_record(&mut pax, "mtime", header.mtime.to_string().as_bytes());
+        }
+
+        if !pax.is_empty() {
+            let fields = Fields { mode: 0o644, size: pax.len() as u64, mtime: header.mtime.min(MAX_OCTAL), typeflag: b'x' };
+            self.inner.write_all(&header_block(b"././@PaxHeader", b"", b"", &fields))?;
+            self.inner.write_all(&pax)?;
+            self.inner.write_all(&[0; BLOCK_SIZE][..padding(pax.len() as u64)])?;
+        }
+        let fields = Fields {
+            mode: header.mode & 0o7777,
+            size: header.size.min(MAX_OCTAL),
+            mtime: header.mtime.min(MAX_OCTAL),
+            typeflag: header.entry_type.typeflag(),
+        };
+        self.inner.write_all(&header_block(name, prefix, link, &fields))?;
+
+        let copied = io::copy(&mut data.take(header.size), &mut self.inner)?;
+        if copied != header.size {
+            return Err(io::Error::new(io::ErrorKind::UnexpectedEof, "file shrank while being archived"));
+        }
+        self.inner.write_all(&[0; BLOCK_SIZE][..padding(header.size)])
+    }
+
+    /// Add the file, directory or symlink at `path` under the name `name`,
+    /// and everything in a directory after it, in name order. Symlinks are
+    /// stored, not followed; other special files are skipped. `each` sees
+    /// every entry as it is added.
+    pub fn append_path(&mut self, path: &Path, name: &[u8], each: &mut dyn FnMut(&TarHeader)) -> io::Result<()> {
+        let metadata = fs::symlink_metadata(path)?;
+        let file_type = metadata.file_type();
+        let mtime = metadata
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map_or(0, |since| since.as_secs());
+        let mut header =
+            TarHeader { path: name.to_vec(), entry_type: EntryType::File, link: Vec::new(), mode: mode(&metadata), mtime, size: 0 };
+
+        if file_type.is_symlink() {
+            header.entry_type = EntryType::Symlink;
+            header.link = path_to_bytes(&fs::read_link(path)?);
+            each(&header);
+            self.append(&header, io::empty())
+        } else if file_type.is_dir() {
+            header.entry_type = EntryType::Directory;
+            each(&header);
+            self.append(&header, io::empty())?;
+
+            let mut children: Vec<_> = fs::read_dir(path)?.map(|entry| entry.map(|entry| entry.file_name())).collect::<io::Result<_>>()?;
+            children.sort();
+            for child in children {
+                let mut child_name = name.to_vec();
+                if !child_name.ends_with(b"/") {
+                    child_name.push(b'/');
+                }
+                child_name.extend_from_slice(&path_to_bytes(Path::new(&child)));
+                self.append_path(&path.join(&child), &child_name, each)?;
+            }
+            Ok(())
+        } else if file_type.is_file() {
+            header.size = metadata.len();
+            each(&header);
+            self.append(&header, io::BufReader::new(File::open(path)?))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Write the end-of-archive marker and give back the writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.write_all(&[0; 2 * BLOCK_SIZE])?;
+        Ok(self.inner)
+    }
+}
+
+/// Numeric fields of a header block
+struct Fields {
+    mode: u32,
+    size: u64,
+    mtime: u64,
+    typeflag: u8,
+}
+
+fn header_block(name: &[u8], prefix: &[u8], link: &[u8], fields: &Fields) -> [u8; BLOCK_SIZE] {
+    let mut block = [0; BLOCK_SIZE];
+    block[..name.len()].copy_from_slice(name);
+    write_octal(&mut block[100..108], fields.mode as u64);
+    write_octal(&mut block[108..116], 0);
+    write_octal(&mut block[116..124], 0);
+    write_octal(&mut block[124..136], fields.size);
+    write_octal(&mut block[136..148], fields.mtime);
+    block[156] = fields.typeflag;
+    block[157..157 + link.len()].copy_from_slice(link);
+    block[257..263].copy_from_slice(b"ustar\0");
+    block[263..265].copy_from_slice(b"00");
+    block[345..345 + prefix.len()].copy_from_slice(prefix);
+
+    let checksum = checksum(&block);
+    block[148..156].copy_from_slice(format!("{:06o}\0 ", checksum).as_bytes());
+    block
+}
+
+/// Zero-padded octal, ending in a NUL; `value` must fit
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    field[..digits.len()].copy_from_slice(digits.as_bytes());
+}
+
+/// Sum of the header bytes, counting the checksum field as spaces
+fn checksum(block: &[u8; BLOCK_SIZE]) -> u32 {
+    let sum: u32 = block.iter().map(|&byte| byte as u32).sum();
+    sum - block[148..156].iter().map(|&byte| byte as u32).sum::<u32>() + 8 * b' ' as u32
+}
+
+/// ustar name and prefix fields for `path`, or `None` if it needs pax
+fn split_path(path: &[u8]) -> Option<(&[u8], &[u8])> {
+    if path.len() <= 100 {
+        return Some((path, b""));
+    }
+    // The name may end in '/', so look for a separator before the last byte
+    let split = (path.len() - 101..path.len() - 1).find(|&i| path[i] == b'/')?;
+    (split <= 155).then(|| (&path[split + 1..], &path[..split]))
+}
+
+/// Append a "<length> <key>=<value>\n" record, where the length counts
+/// the whole record including its own digits
+fn pax_record(out: &mut Vec<u8>, key: &str, value: &[u8]) {
+    let base = key.len() + value.len() + 3;
+    let mut len = base + 1;
+    while base + len.to_string().len() != len {
+        len = base + len.to_string().len();
+    }
+    out.extend_from_slice(format!("{} {}=", len, key).as_bytes());
+    out.extend_from_slice(value);
+    out.push(b'\n');
+}
+
+fn padding(size: u64) -> usize {
+    (BLOCK_SIZE - (size % BLOCK_SIZE as u64) as usize) % BLOCK_SIZE
+}
+
+/// Values from pax or GNU entries that override the next header's
+#[derive(Default)]
+struct Overrides {
+    path: Option<Vec<u8>>,
+    link: Option<Vec<u8>>,
+    size: Option<u64>,
+    mtime: Option<u64>,
+}
+
+/// Reads an archive entry by entry. After `next_entry` returns a header,
+/// reading yields that entry's data.
+pub struct TarReader<R: Read> {
+    inner: R,
+    /// Bytes read from `inner` so far
+    offset: u64,
+    /// Data of the current entry not yet read
+    remaining: u64,
+    /// Padding after the current entry's data
+    padding: usize,
+    done: bool,
+}
+
+impl<R: Read> TarReader<R> {
+    pub fn new(inner: R) -> Self {
+        TarReader { inner, offset: 0, remaining: 0, padding: 0, done: false }
+    }
+
+    /// Give back the reader, positioned wherever the archive stopped: after
+    /// its first zero block once `next_entry` has returned `None`
+    pub fn into_inner(self) -> R {
+        self.inner
+    }
+
+    /// Header of the next entry, skipping whatever is left of the current
+    /// one; `None` at the end of the archive
+    pub fn next_entry(&mut self) -> Result<Option<TarHeader>, TarError> {
+        if self.done {
+            return Ok(None);
+        }
+        io::copy(self, &mut io::sink())?;
+        self.skip_padding()?;
+
+        let mut overrides = Overrides::default();
+        loop {
+            let start = self.offset;
+            let mut block = [0; BLOCK_SIZE];
+            self.read_exact_at(&mut block)?;
+            if block.iter().all(|&byte| byte == 0) {
+                // One zero block is enough; a second, if present, is left unread
+                self.done = true;
+                return Ok(None);
+            }
+
+            let stored = parse_number(&block[148..156]).ok_or(TarError::InvalidHeader { offset: start })?;
+            let signed: i64 = block.iter().map(|&byte| byte as i8 as i64).sum::<i64>()
+                - block[148..156].iter().map(|&byte| byte as i8 as i64).sum::<i64>()
+                + 8 * b' ' as i64;
+            if stored != checksum(&block) as u64 && stored as i64 != signed {
+                return Err(TarError::ChecksumMismatch { offset: start });
+            }
+
+            let number = |range: std::ops::Range<usize>| {
+                parse_number(&block[range]).ok_or(TarError::InvalidHeader { offset: start })
+            };
+            let typeflag = block[156];
+
+            if matches!(typeflag, b'x' | b'g' | b'L' | b'K') {
+                // A pax size is for the entry these describe, not for them
+                let size = number(124..136)?;
+                if size > MAX_METADATA {
+                    return Err(TarError::InvalidHeader { offset: start });
+                }
+                let mut data = vec![0; size as usize];
+                self.read_exact_at(&mut data)?;
+                self.padding = padding(size);
+                self.skip_padding()?;
+                match typeflag {
+                    b'x' => parse_pax(&data, &mut overrides).ok_or(TarError::InvalidHeader { offset: start })?,
+                    b'L' => overrides.path = Some(until_nul(&data).to_vec()),
+                    b'K' => overrides.link = Some(until_nul(&data).to_vec()),
+                    // Global pax headers only carry defaults we have no use for
+                    _ => {}
+                }
+                continue;
+            }
+
+            let size = match overrides.size {
+                Some(size) => size,
+                None => number(124..136)?,
+            };
+            let mut path = match overrides.path.take() {
+                Some(path) => path,
+                None => {
+                    let name = until_nul(&block[..100]);
+                    let prefix = until_nul(&block[345..500]);
+                    // Only POSIX ustar has a prefix; GNU keeps other fields there
+                    if &block[257..263] == b"ustar\0" && !prefix.is_empty() {
+                        [prefix, b"/", name].concat()
+                    } else {
+                        name.to_vec()
+                    }
+                }
+            };
+            let entry_type = match typeflag {
+                b'0' | b'7' if path.ends_with(b"/") => EntryType::Directory,
+                b'0' | b'7' => EntryType::File,
+                0 if path.ends_with(b"/") => EntryType::Directory,
+                0 => EntryType::File,
+                b'5' => EntryType::Directory,
+                b'2' => EntryType::Symlink,
+                flag => EntryType::Other(flag),
+            };
+            while path.len() > 1 && path.ends_with(b"/") {
+                path.pop();
+            }
+            let header = TarHeader {
+                path,
+                entry_type,
+                link: overrides.link.take().unwrap_or_else(|| until_nul(&block[157..257]).to_vec()),
+                mode: number(100..108)? as u32 & 0o7777,
+                mtime: match overrides.mtime {
+                    Some(mtime) => mtime,
+                    None => number(136..148)?,
+                },
+                size,
+            };
+            self.remaining = size;
+            self.padding = padding(size);
+            return Ok(Some(header));
+        }
+    }
+
+    /// Extract the rest of the archive under `dest`, calling `each` with
+    /// every header first. Entries of other types are passed over. Paths
+    /// that would land outside `dest` are an error, including ones through
+    /// a symlink unpacked earlier. Directory permissions are set last, so
+    /// read-only directories can still be filled.
+    pub fn unpack(&mut self, dest: &Path, mut each: impl FnMut(&TarHeader)) -> Result<(), TarError> {
+        let mut directories = Vec::new();
+
+        while let Some(header) = self.next_entry()? {
+            each(&header);
+            if let EntryType::Other(_) = header.entry_type {
+                continue;
+            }
+            let relative = safe_path(&header.path)?;
+            check_parents(dest, &relative, &header.path)?;
+            let target = dest.join(&relative);
+
+            match header.entry_type {
+                EntryType::Directory => {
+                    if fs::symlink_metadata(&target).is_ok_and(|metadata| !metadata.is_dir()) {
+                        fs::remove_file(&target)?;
+                    }
+                    fs::create_dir_all(&target)?;
+                    directories.push((target, header));
+                }
+                EntryType::File => {
+                    let file = create_file(&target)?;
+                    let mut writer = io::BufWriter::new(&file);
+                    let copied = io::copy(&mut self.by_ref().take(header.size), &mut writer)?;
+                    if copied != header.size {
+                        return Err(TarError::UnexpectedEof { offset: self.offset });
+                    }
+                    writer.flush()?;
+                    drop(writer);
+                    set_metadata(&file, &header)?;
+                }
+                EntryType::Symlink => {
+                    if relative.as_os_str().is_empty() {
+                        return Err(TarError::UnsafePath { path: header.path });
+                    }
+                    remove_existing(&target)?;
+                    symlink(&bytes_to_path(&header.link), &target)?;
+                }
+                EntryType::Other(_) => unreachable!(),
+            }
+        }
+
+        for (path, header) in directories.iter().rev() {
+            set_metadata(&File::open(path)?, header)?;
+        }
+        Ok(())
+    }
+
+    fn read_exact_at(&mut self, buf: &mut [u8]) -> Result<(), TarError> {
+        match self.inner.read_exact(buf) {
+            Ok(()) => {
+                self.offset += buf.len() as u64;
+                Ok(())
+            }
+            Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => {
+                Err(TarError::UnexpectedEof { offset: self.offset })
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn skip_padding(&mut self) -> Result<(), TarError> {
+        let mut padding = [0; BLOCK_SIZE];
+        let len = std::mem::take(&mut self.padding);
+        self.read_exact_at(&mut padding[..len])
+    }
+}
+
+impl<R: Read> Read for TarReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.remaining == 0 || buf.is_empty() {
+            return Ok(0);
+        }
+        let len = buf.len().min(self.remaining.min(usize::MAX as u64) as usize);
+        let n = self.inner.read(&mut buf[..len])?;
+        if n == 0 {
+            return Err(TarError::UnexpectedEof { offset: self.offset }.into());
+        }
+        self.offset += n as u64;
+        self.remaining -= n as u64;
+        Ok(n)
+    }
+}
+
+/// An octal field, or GNU base-256 when the high bit of the first byte is
+/// set; `None` if it is neither
+fn parse_number(field: &[u8]) -> Option<u64> {
+    if field[0] & 0x80 != 0 {
+        if field[0] & 0x40 != 0 || field.len() > 9 && field[1..field.len() - 8].iter().any(|&byte| byte != 0) {
+            return None;
+        }
+        return Some(field[field.len().saturating_sub(8)..].iter().fold(0, |value, &byte| value << 8 | byte as u64));
+    }
+    let mut digits = field.iter().skip_while(|&&byte| byte == b' ').take_while(|&&byte| byte != 0 && byte != b' ');
+    digits.try_fold(0u64, |value, &byte| match byte {
+        b'0'..=b'7' => value.checked_mul(8).map(|value| value + (byte - b'0') as u64),
+        _ => None,
+    })
+}
+
+fn until_nul(field: &[u8]) -> &[u8] {
+    &field[..field.iter().position(|&byte| byte == 0).unwrap_or(field.len())]
+}
+
+/// Apply the records of a pax extended header
+fn parse_pax(mut data: &[u8], overrides: &mut Overrides) -> Option<()> {
+    while !data.is_empty() {
+        let space = data.iter().position(|&byte| byte == b' ')?;
+        let len: usize = std::str::from_utf8(&data[..space]).ok()?.parse().ok()?;
+        if len <= space + 1 || len > data.len() || data[len - 1] != b'\n' {
+            return None;
+        }
+        let record = &data[space + 1..len - 1];
+        let equals = record.iter().position(|&byte| byte == b'=')?;
+        let (key, value) = (&record[..equals], &record[equals + 1..]);
+        let number = || std::str::from_utf8(value).ok()?.split('.').next()?.parse::<u64>().ok();
+        match key {
+            b"path" => overrides.path = Some(value.to_vec()),
+            b"linkpath" => overrides.link = Some(value.to_vec()),
+            b"size" => overrides.size = Some(number()?),
+            // Fractional seconds are dropped, times before 1970 clamped
+            b"mtime" => overrides.mtime = Some(if value.starts_with(b"-") { 0 } else { number()? }),
+            _ => {}
+        }
+        data = &data[len..];
+    }
+    Some(())
+}
+
+/// `path` as a relative path with no `..`, `.` or empty components, or an
+/// error if it is absolute or climbs out. The archive root itself (`.` or
+/// `./`) comes back empty.
+pub fn safe_path(path: &[u8]) -> Result<PathBuf, TarError> {
+    let unsafe_path = || TarError::UnsafePath { path: path.to_vec() };
+    if path.starts_with(b"/") {
+        return Err(unsafe_path());
+    }
+    let mut out = PathBuf::new();
+    for part in path.split(|&byte| byte == b'/') {
+        match part {
+            b"" | b"." => {}
+            b".." => return Err(unsafe_path()),
+            _ => out.push(bytes_to_path(part)),
+        }
+    }
+    // Whatever the platform reads as a root or drive prefix is refused too
+    if !out.components().all(|component| matches!(component, Component::Normal(_))) {
+        return Err(unsafe_path());
+    }
+    Ok(out)
+}
+
+/// Refuse to write through a symlink in any directory between `dest` and
+/// the entry, where one could lead anywhere
+fn check_parents(dest: &Path, relative: &Path, name: &[u8]) -> Result<(), TarError> {
+    let mut path = dest.to_path_buf();
+    let mut components = relative.components();
+    components.next_back();
+    for component in components {
+        path.push(component);
+        if fs::symlink_metadata(&path).is_ok_and(|metadata| metadata.file_type().is_symlink()) {
+            return Err(TarError::UnsafePath { path: name.to_vec() });
+        }
+    }
+    Ok(())
+}
+
+/// Remove a file or symlink in the way of a new entry, so creating the
+/// entry never follows an old link
+fn remove_existing(path: &Path) -> io::Result<()> {
+    match fs::symlink_metadata(path) {
+        Ok(metadata) if !metadata.is_dir() => fs::remove_file(path),
+        _ => Ok(()),
+    }
+}
+
+fn create_file(path: &Path) -> io::Result<File> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    remove_existing(path)?;
+    File::create(path)
+}
+
+fn set_metadata(file: &File, header: &TarHeader) -> io::Result<()> {
+    set_mode(file, header.mode)?;
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(header.mtime))
+}
+
+#[cfg(unix)]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    metadata.permissions().mode() & 0o7777
+}
+
+#[cfg(not(unix))]
+fn mode(metadata: &fs::Metadata) -> u32 {
+    if metadata.is_dir() || metadata.permissions().readonly() {
+        0o555
+    } else {
+        0o644
+    }
+}
+
+/// Apply permission bits, without set-user-ID, set-group-ID or sticky
+#[cfg(unix)]
+fn set_mode(file: &File, mode: u32) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    file.set_permissions(fs::Permissions::from_mode(mode & 0o777))
+}
+
+#[cfg(not(unix))]
+fn set_mode(file: &File, mode: u32) -> io::Result<()> {
+    let mut permissions = file.metadata()?.permissions();
+    permissions.set_readonly(mode & 0o222 == 0);
+    file.set_permissions(permissions)
+}
+
+#[cfg(unix)]
+fn symlink(target: &Path, link: &Path) -> io::Result<()> {
+    if let Some(parent) = link.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    std::os::unix::fs::symlink(target, link)
+}
+
+#[cfg(not(unix))]
+fn symlink(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "symbolic links are not supported here"))
+}
+
+#[cfg(unix)]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn bytes_to_path(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/// `path` as archive bytes, with '/' separators
+pub fn path_to_bytes(path: &Path) -> Vec<u8> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::ffi::OsStrExt;
+        path.as_os_str().as_bytes().to_vec()
+    }
+    #[cfg(not(unix))]
+    {
+        path.to_string_lossy().replace('\\', "/").into_bytes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    fn header(path: &[u8], entry_type: EntryType, size: u64) -> TarHeader {
+        TarHeader { path: path.to_vec(), entry_type, link: Vec::new(), mode: 0o640, mtime: 1_700_000_000, size }
+    }
+
+    /// Every entry read back, with its data
+    fn read_all(archive: &[u8]) -> Result<Vec<(TarHeader, Vec<u8>)>, TarError> {
+        let mut reader = TarReader::new(archive);
+        let mut entries = Vec::new();
+        while let Some(header) = reader.next_entry()? {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            entries.push((header, data));
+        }
+        Ok(entries)
+    }
+
+    /// A fresh, empty directory under the system temp directory
+    fn scratch(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("gzip-tar-{}-{}", process::id(), name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let long_dir = "d".repeat(120).into_bytes();
+        let long_path = [&long_dir[..], b"/", &b"f".repeat(90)].concat();
+        let huge_path = b"x/".repeat(200);
+        let mut link = header(b"link", EntryType::Symlink, 0);
+        link.link = b"../t/".repeat(30);
+        let entries = vec![
+            (header(b"dir", EntryType::Directory, 0), Vec::new()),
+            (header(b"dir/file.txt", EntryType::File, 11), b"hello tar\n\n".to_vec()),
+            (header(b"empty", EntryType::File, 0), Vec::new()),
+            (header(&long_path, EntryType::File, 1000), vec![7; 1000]),
+            (header(&huge_path[..huge_path.len() - 1], EntryType::File, 3), b"abc".to_vec()),
+            (link, Vec::new()),
+            (TarHeader { mtime: 1 << 40, ..header(b"future", EntryType::File, 0) }, Vec::new()),
+        ];
+
+        let mut writer = TarWriter::new(Vec::new());
+        for (header, data) in &entries {
+            writer.append(header, &data[..]).unwrap();
+        }
+        let archive = writer.finish().unwrap();
+        assert_eq!(archive.len() % BLOCK_SIZE, 0);
+        assert_eq!(read_all(&archive).unwrap(), entries);
+
+        // ustar fields where they fit: the 210-byte path needs no pax header
+        assert_eq!(&archive[4 * BLOCK_SIZE + 345..][..120], &long_dir[..]);
+    }
+
+    #[test]
+    fn test_skips_unread_data() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append(&header(b"a", EntryType::File, 700), &[1; 700][..]).unwrap();
+        writer.append(&header(b"b", EntryType::File, 2), &b"hi"[..]).unwrap();
+        let archive = writer.finish().unwrap();
+
+        let mut reader = TarReader::new(&archive[..]);
+        reader.next_entry().unwrap();
+        let mut first = [0; 10];
+        reader.read_exact(&mut first).unwrap();
+        assert_eq!(reader.next_entry().unwrap().unwrap().path, b"b");
+        assert_eq!(reader.next_entry().unwrap(), None);
+    }
+
+    #[test]
+    fn test_pax_size_skips_metadata_entries() {
+        // A pax size, then a GNU long name with a size of its own, then the
+        // entry both describe
+        let mut pax = Vec::new();
+        pax_record(&mut pax, "size", b"3");
+        let name = b"long-name\0";
+        let mut archive = Vec::new();
+        let mut entry = |name: &[u8], typeflag, size, data: &[u8]| {
+            archive.extend_from_slice(&header_block(name, b"", b"", &Fields { mode: 0o644, size, mtime: 0, typeflag }));
+            archive.extend_from_slice(data);
+            archive.extend_from_slice(&[0; BLOCK_SIZE][..padding(data.len() as u64)]);
+        };
+        entry(b"././@PaxHeader", b'x', pax.len() as u64, &pax);
+        entry(b"././@LongLink", b'L', name.len() as u64, name);
+        entry(b"short", b'0', 0, b"abc");
+        archive.extend_from_slice(&[0; 2 * BLOCK_SIZE]);
+
+        let entries = read_all(&archive).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0.path, b"long-name");
+        assert_eq!(entries[0].1, b"abc");
+    }
+
+    #[test]
+    fn test_rejects_damage() {
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append(&header(b"a", EntryType::File, 700), &[1; 700][..]).unwrap();
+        let archive = writer.finish().unwrap();
+
+        let mut damaged = archive.clone();
+        damaged[3] = b'!';
+        assert_eq!(read_all(&damaged), Err(TarError::ChecksumMismatch { offset: 0 }));
+        assert_eq!(read_all(&archive[..1000]), Err(TarError::UnexpectedEof { offset: 1000 }));
+        assert_eq!(read_all(&archive[..1536]), Err(TarError::UnexpectedEof { offset: 1536 }));
+    }
+
+    #[test]
+    fn test_safe_path() {
+        assert_eq!(safe_path(b"a/./b//c/").unwrap(), Path::new("a/b/c"));
+        assert_eq!(safe_path(b"./").unwrap(), Path::new(""));
+        for path in [&b"/etc/passwd"[..], b"../up", b"a/../../up", b"a/.."] {
+            assert_eq!(safe_path(path), Err(TarError::UnsafePath { path: path.to_vec() }));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_unpack_refuses_to_escape() {
+        let dir = scratch("escape");
+        let dest = dir.join("dest");
+        fs::create_dir(&dest).unwrap();
+
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append(&header(b"../outside", EntryType::File, 1), &b"x"[..]).unwrap();
+        let archive = writer.finish().unwrap();
+        assert!(matches!(TarReader::new(&archive[..]).unpack(&dest, |_| {}), Err(TarError::UnsafePath { .. })));
+
+        // A symlink out of the destination, then a file through it
+        let mut writer = TarWriter::new(Vec::new());
+        let link = TarHeader { link: dir.as_os_str().to_str().unwrap().as_bytes().to_vec(), ..header(b"link", EntryType::Symlink, 0) };
+        writer.append(&link, io::empty()).unwrap();
+        writer.append(&header(b"link/outside", EntryType::File, 1), &b"x"[..]).unwrap();
+        let archive = writer.finish().unwrap();
+        assert!(matches!(TarReader::new(&archive[..]).unpack(&dest, |_| {}), Err(TarError::UnsafePath { .. })));
+
+        // A symlink already there is replaced, not written through
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append(&header(b"link", EntryType::File, 1), &b"x"[..]).unwrap();
+        TarReader::new(&writer.finish().unwrap()[..]).unpack(&dest, |_| {}).unwrap();
+        assert!(fs::symlink_metadata(dest.join("link")).unwrap().is_file());
+        assert!(!dir.join("outside").exists());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_directory_round_trip() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = scratch("tree");
+        let source = dir.join("source");
+        fs::create_dir_all(source.join("sub/deeper")).unwrap();
+        fs::write(source.join("sub/deeper/data.bin"), vec![3; 5000]).unwrap();
+        fs::write(source.join("run.sh"), "#!/bin/sh\n").unwrap();
+        fs::set_permissions(source.join("run.sh"), fs::Permissions::from_mode(0o751)).unwrap();
+        std::os::unix::fs::symlink("sub/deeper/data.bin", source.join("shortcut")).unwrap();
+        let mtime = UNIX_EPOCH + Duration::from_secs(1_600_000_000);
+        File::open(source.join("run.sh")).unwrap().set_modified(mtime).unwrap();
+        fs::set_permissions(source.join("sub"), fs::Permissions::from_mode(0o555)).unwrap();
+
+        let mut names = Vec::new();
+        let mut writer = TarWriter::new(Vec::new());
+        writer.append_path(&source, b"top", &mut |header| names.push(header.path.clone())).unwrap();
+        let archive = writer.finish().unwrap();
+        let expected: [&[u8]; 6] =
+            [b"top", b"top/run.sh", b"top/shortcut", b"top/sub", b"top/sub/deeper", b"top/sub/deeper/data.bin"];
+        assert_eq!(names, expected);
+
+        let dest = dir.join("dest");
+        TarReader::new(&archive[..]).unpack(&dest, |_| {}).unwrap();
+        let top = dest.join("top");
+        assert_eq!(fs::read(top.join("sub/deeper/data.bin")).unwrap(), vec![3; 5000]);
+        assert_eq!(fs::read_link(top.join("shortcut")).unwrap(), Path::new("sub/deeper/data.bin"));
+        let script = fs::metadata(top.join("run.sh")).unwrap();
+        assert_eq!(script.permissions().mode() & 0o777, 0o751);
+        assert_eq!(script.modified().unwrap(), mtime);
+        assert_eq!(fs::metadata(top.join("sub")).unwrap().permissions().mode() & 0o777, 0o555);
+
+        for path in [source.join("sub"), top.join("sub")] {
+            fs::set_permissions(path, fs::Permissions::from_mode(0o755)).unwrap();
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
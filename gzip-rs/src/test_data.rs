@@ -0,0 +1,35 @@
+//! Deterministic inputs shared by the unit tests
+
+/// zlib-style linear congruential generator: cheap, and the same sequence
+/// on every platform
+struct Lcg(u32);
+
+impl Lcg {
+    fn next(&mut self) -> u32 {
+        self.0 = self.0.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+        self.0
+    }
+}
+
+/// `len` pseudo-random 16-bit values, the same for the same seed
+pub(crate) fn values(len: usize, seed: u32) -> Vec<u32> {
+    let mut lcg = Lcg(seed);
+    (0..len).map(|_| lcg.next() >> 16).collect()
+}
+
+/// Text-like input: words from a small vocabulary in pseudo-random order,
+/// some followed by a few arbitrary bytes so not everything repeats.
+/// Different seeds give different text.
+pub(crate) fn text(len: usize, seed: u32) -> Vec<u8> {
+    let words: [&[u8]; 8] =
+        [b"deflate ", b"window ", b"match ", b"block ", b"gzip\n", b"huffman ", b"chunk ", b"index "];
+    let mut lcg = Lcg(seed);
+    let mut out = Vec::with_capacity(len + 16);
+    while out.len() < len {
+        let state = lcg.next();
+        out.extend_from_slice(words[(state >> 16) as usize % words.len()]);
+        out.extend_from_slice(&state.to_le_bytes()[..(state >> 30) as usize]);
+    }
+    out.truncate(len);
+    out
+}
@@ -0,0 +1,567 @@
+use std::fmt;
+use std::io::{self, Read, Write};
+
+use crate::adler32::{adler32, Adler32};
+use crate::bits::BitReader;
+use crate::deflate::{deflate_with_dictionary, deflate_with_level, CompressionLevel, Deflater};
+use crate::error::GzipError;
+use crate::inflate::{InflateError, Inflater};
+use crate::limits::{LimitError, Limits};
+
+/// CM = 8 is the only compression method defined: DEFLATE
+pub const METHOD_DEFLATE: u8 = 8;
+/// CINFO = 7, a 32 KiB window, is the largest RFC 1950 allows
+const MAX_CINFO: u8 = 7;
+/// FDICT: a preset dictionary identifier follows the header
+const FDICT: u8 = 0x20;
+
+/// Reasons a zlib stream can fail to decode. Offsets are byte positions in the input.
+/// Callers see these as `GzipError`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum ZlibError {
+    /// The input ended inside the header or trailer
+    UnexpectedEof { offset: usize },
+    /// CMF and FLG are not a multiple of 31, so this is not a zlib header
+    HeaderCheck { offset: usize },
+    /// CM is something other than DEFLATE
+    UnsupportedMethod { offset: usize, method: u8 },
+    /// CINFO asks for a window larger than 32 KiB
+    InvalidWindowSize { offset: usize, window_bits: u8 },
+    /// FDICT is set but no dictionary was supplied
+    DictionaryRequired { offset: usize, id: u32 },
+    /// The supplied dictionary's Adler-32 differs from DICTID
+    DictionaryMismatch { offset: usize, expected: u32, actual: u32 },
+    /// The Adler-32 in the trailer does not match the decompressed data
+    ChecksumMismatch { offset: usize, expected: u32, actual: u32 },
+    /// The compressed data itself is corrupt
+    Inflate(InflateError),
+    /// Decoding stopped at one of the caller's `Limits`
+    Limit(LimitError),
+    /// Reading the input failed (streaming decoders only)
+    Io(io::ErrorKind, String),
+}
+
+impl fmt::Display for ZlibError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ZlibError::UnexpectedEof { offset } => {
+                write!(f, "unexpected end of stream at byte {}", offset)
+            }
+            ZlibError::HeaderCheck { offset } => {
+                write!(f, "incorrect header check at byte {}", offset)
+            }
+            ZlibError::UnsupportedMethod { offset, method } => {
+                write!(f, "unknown compression method {} at byte {}", method, offset)
+            }
+            ZlibError::InvalidWindowSize { offset, window_bits } => {
+                write!(f, "invalid window size 2^{} at byte {}", window_bits, offset)
+            }
+            ZlibError::DictionaryRequired { offset, id } => {
+                write!(f, "preset dictionary {:08x} required at byte {}", id, offset)
+            }
+            ZlibError::DictionaryMismatch { offset, expected, actual } => write!(
+                f,
+                "wrong preset dictionary at byte {} (expected {:08x}, got {:08x})",
+                offset, expected, actual
+            ),
+            ZlibError::ChecksumMismatch { offset, expected, actual } => write!(
+                f,
+                "Adler-32 mismatch at byte {} (expected {:08x}, got {:08x})",
+                offset, expected, actual
+            ),
+            ZlibError::Inflate(err) => err.fmt(f),
+            ZlibError::Limit(err) => err.fmt(f),
+            ZlibError::Io(_, message) => f.write_str(message),
+        }
+    }
+}
+
+impl std::error::Error for ZlibError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ZlibError::Inflate(err) => Some(err),
+            ZlibError::Limit(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<ZlibError> for io::Error {
+    fn from(err: ZlibError) -> Self {
+        match err {
+            ZlibError::Io(kind, message) => io::Error::new(kind, message),
+            ZlibError::UnexpectedEof { .. } => io::Error::new(io::ErrorKind::UnexpectedEof, err),
+            err => io::Error::new(io::ErrorKind::InvalidData, err),
+        }
+    }
+}
+
+impl From<LimitError> for ZlibError {
+    fn from(err: LimitError) -> Self {
+        ZlibError::Limit(err)
+    }
+}
+
+impl From<InflateError> for ZlibError {
+    fn from(err: InflateError) -> Self {
+        ZlibError::Inflate(err)
+    }
+}
+
+/// FLEVEL zlib writes for a compression level; informational only
+fn level_flag(level: CompressionLevel) -> u8 {
+    match level.level() {
+        0 | 1 => 0,
+        2..=5 => 1,
+        6 => 2,
+        _ => 3,
+    }
+}
+
+/// The two-byte CMF/FLG header, followed by DICTID when a dictionary is used
+fn header_bytes(level: CompressionLevel, dictionary: Option<&[u8]>) -> Vec<u8> {
+    let cmf = (MAX_CINFO << 4) | METHOD_DEFLATE;
+    let mut flg = level_flag(level) << 6;
+    if dictionary.is_some() {
+        flg |= FDICT;
+    }
+    // FCHECK makes CMF * 256 + FLG a multiple of 31
+    let remainder = (cmf as u16 * 256 + flg as u16) % 31;
+    flg |= ((31 - remainder) % 31) as u8;
+
+    let mut out = vec![cmf, flg];
+    if let Some(dictionary) = dictionary {
+        out.extend_from_slice(&adler32(dictionary).to_be_bytes());
+    }
+    out
+}
+
+/// Read the header and set up an inflater, seeded with `dictionary` if the
+/// stream was compressed against one
+fn read_header<R: Read>(
+    reader: &mut BitReader<R>,
+    dictionary: Option<&[u8]>,
+) -> Result<Inflater, ZlibError> {
+    let start = reader.byte_offset();
+    let header = reader.read_bytes(2).ok_or_else(|| eof_error(reader))?;
+    let (cmf, flg) = (header[0], header[1]);
+
+    if !(cmf as u16 * 256 + flg as u16).is_multiple_of(31) {
+        return Err(ZlibError::HeaderCheck { offset: start });
+    }
+    if cmf & 0x0f != METHOD_DEFLATE {
+        return Err(ZlibError::UnsupportedMethod { offset: start, method: cmf & 0x0f });
+    }
+    if cmf >> 4 > MAX_CINFO {
+        return Err(ZlibError::InvalidWindowSize { offset: start, window_bits: (cmf >> 4) + 8 });
+    }
+    if flg & FDICT == 0 {
+        return Ok(Inflater::new());
+    }
+
+    let offset = reader.byte_offset();
+    let id = reader.read_bytes(4).ok_or_else(|| eof_error(reader))?;
+    let id = u32::from_be_bytes([id[0], id[1], id[2], id[3]]);
+    match dictionary {
+        None => Err(ZlibError::DictionaryRequired { offset, id }),
+        Some(dictionary) => {
+            let actual = adler32(dictionary);
+            if actual != id {
+                return Err(ZlibError::DictionaryMismatch { offset, expected: id, actual });
+            }
+            Ok(Inflater::with_dictionary(dictionary))
+        }
+    }
+}
+
+fn eof_error<R: Read>(reader: &mut BitReader<R>) -> ZlibError {
+    match reader.take_error() {
+        Some(err) => ZlibError::Io(err.kind(), err.to_string()),
+        None => ZlibError::UnexpectedEof { offset: reader.byte_offset() },
+    }
+}
+
+/// Check the big-endian Adler-32 that follows the DEFLATE stream
+fn read_trailer<R: Read>(reader: &mut BitReader<R>, adler: u32) -> Result<(), ZlibError> {
+    reader.align_to_byte();
+    let offset = reader.byte_offset();
+    let trailer = reader.read_bytes(4).ok_or_else(|| eof_error(reader))?;
+    let expected = u32::from_be_bytes([trailer[0], trailer[1], trailer[2], trailer[3]]);
+    if adler != expected {
+        return Err(ZlibError::ChecksumMismatch { offset, expected, actual: adler });
+    }
+    Ok(())
+}
+
+/// Turn an inflate failure into a zlib error, surfacing I/O errors that the
+/// bit reader reported as end of input
+fn inflate_error<R: Read>(reader: &mut BitReader<R>, err: InflateError) -> ZlibError {
+    match (err, reader.take_error()) {
+        (InflateError::UnexpectedEof { .. }, Some(io_err)) => {
+            ZlibError::Io(io_err.kind(), io_err.to_string())
+        }
+        (err, _) => ZlibError::Inflate(err),
+    }
+}
+
+/// Compress `data` into a zlib stream (RFC 1950) at the default level
+pub fn zlib_compress(data: &[u8]) -> Vec<u8> {
+    zlib_compress_with_level(data, CompressionLevel::DEFAULT)
+}
+
+/// Compress `data` into a zlib stream at the given level
+pub fn zlib_compress_with_level(data: &[u8], level: CompressionLevel) -> Vec<u8> {
+    let mut out = header_bytes(level, None);
+    out.extend_from_slice(&deflate_with_level(data, level));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Compress `data` against a preset dictionary. The stream records the
+/// dictionary's Adler-32, and decoding needs the same dictionary.
+pub fn zlib_compress_with_dictionary(data: &[u8], level: CompressionLevel, dictionary: &[u8]) -> Vec<u8> {
+    let mut out = header_bytes(level, Some(dictionary));
+    out.extend_from_slice(&deflate_with_dictionary(data, level, dictionary));
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+/// Decompress a zlib stream that does not use a preset dictionary
+pub fn zlib_decompress(data: &[u8]) -> Result<Vec<u8>, GzipError> {
+    decompress(data, None, &Limits::NONE)
+}
+
+/// `zlib_decompress` for untrusted input: fails with
+/// `GzipError::LimitExceeded` as soon as the output or expansion ratio goes
+/// over `limits`
+pub fn zlib_decompress_with_limits(data: &[u8], limits: &Limits) -> Result<Vec<u8>, GzipError> {
+    decompress(data, None, limits)
+}
+
+/// Decompress a zlib stream, supplying the dictionary it may have been
+/// compressed against. The dictionary is ignored if FDICT is not set.
+pub fn zlib_decompress_with_dictionary(data: &[u8], dictionary: &[u8]) -> Result<Vec<u8>, GzipError> {
+    decompress(data, Some(dictionary), &Limits::NONE)
+}
+
+fn decompress(data: &[u8], dictionary: Option<&[u8]>, limits: &Limits) -> Result<Vec<u8>, GzipError> {
+    let mut reader = BitReader::new(data);
+    let mut inflater = read_header(&mut reader, dictionary)?;
+    loop {
+        inflater
+            .decode_more(&mut reader)
+            .map_err(|err| inflate_error(&mut reader, err))?;
+        limits.check_output(reader.byte_offset() as u64, inflater.output().len() as u64)?;
+        if inflater.is_done() {
+            break;
+        }
+    }
+    let output = inflater.into_output();
+    read_trailer(&mut reader, adler32(&output))?;
+    Ok(output)
+}
+
+/// Streaming zlib compressor, the counterpart of `GzEncoder`. Call `finish`
+/// to write the trailer; dropping the encoder does the same but has to
+/// ignore errors.
+pub struct ZlibEncoder<W: Write> {
+    inner: Option<W>,
+    deflater: Deflater,
+    /// Header bytes not yet handed to `inner`
+    header: Vec<u8>,
+    adler: Adler32,
+}
+
+impl<W: Write> ZlibEncoder<W> {
+    pub fn new(inner: W, level: CompressionLevel) -> Self {
+        ZlibEncoder {
+            inner: Some(inner),
+            deflater: Deflater::new(level),
+            header: header_bytes(level, None),
+            adler: Adler32::new(),
+        }
+    }
+
+    /// Compress against a preset dictionary
+    pub fn with_dictionary(inner: W, level: CompressionLevel, dictionary: &[u8]) -> Self {
+        ZlibEncoder {
+            inner: Some(inner),
+            deflater: Deflater::with_dictionary(level, dictionary),
+            header: header_bytes(level, Some(dictionary)),
+            adler: Adler32::new(),
+        }
+    }
+
+    pub fn get_ref(&self) -> &W {
+        self.inner.as_ref().unwrap()
+    }
+
+    /// Hand all completed output to the inner writer
+    fn write_output(&mut self) -> io::Result<()> {
+        let inner = self.inner.as_mut().unwrap();
+        if !self.header.is_empty() {
+            inner.write_all(&self.header)?;
+            self.header.clear();
+        }
+        inner.write_all(&self.deflater.take_output())
+    }
+
+    fn try_finish(&mut self) -> io::Result<()> {
+        self.deflater.finish();
+        self.write_output()?;
+
+        let inner = self.inner.as_mut().unwrap();
+        inner.write_all(&self.adler.value().to_be_bytes())?;
+        inner.flush()
+    }
+
+    /// Compress any remaining input, write the trailer and return the inner writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.try_finish()?;
+        Ok(self.inner.take().unwrap())
+    }
+}
+
+impl<W: Write> Write for ZlibEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.deflater.write(buf);
+        self.adler.update(buf);
+        self.write_output()?;
+        Ok(buf.len())
+    }
+
+    /// Sync flush: everything written so far can be decompressed from the
+    /// bytes the inner writer has received
+    fn flush(&mut self) -> io::Result<()> {
+        self.deflater.flush();
+        self.write_output()?;
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl<W: Write> Drop for ZlibEncoder<W> {
+    fn drop(&mut self) {
+        if self.inner.is_some() {
+            let _ = self.try_finish();
+        }
+    }
+}
+
+/// Streaming zlib decompressor with bounded memory. Reading stops at the end
+/// of the zlib stream; anything after it is left unread.
+pub struct ZlibDecoder<R: Read> {
+    reader: BitReader<R>,
+    /// `None` until the header has been read
+    inflater: Option<Inflater>,
+    dictionary: Option<Vec<u8>>,
+    adler: Adler32,
+    done: bool,
+    limits: Limits,
+    /// Bytes handed out so far
+    total: u64,
+}
+
+impl<R: Read> ZlibDecoder<R> {
+    pub fn new(inner: R) -> Self {
+        Self::with_limits(inner, Limits::NONE)
+    }
+
+    /// A decoder for untrusted input: reads fail with an `InvalidData` error,
+    /// which `GzipError::from` turns into `GzipError::LimitExceeded`, before
+    /// any output beyond `limits` is returned. `max_members` does not apply
+    /// to zlib.
+    pub fn with_limits(inner: R, limits: Limits) -> Self {
+        ZlibDecoder {
+            reader: BitReader::new(inner),
+            inflater: None,
+            dictionary: None,
+            adler: Adler32::new(),
+            done: false,
+            limits,
+            total: 0,
+        }
+    }
+
+    /// Decode a stream that may have been compressed against `dictionary`
+    pub fn with_dictionary(inner: R, dictionary: &[u8]) -> Self {
+        ZlibDecoder { dictionary: Some(dictionary.to_vec()), ..Self::new(inner) }
+    }
+
+    fn read_decoded(&mut self, buf: &mut [u8]) -> Result<usize, ZlibError> {
+        if self.done {
+            return Ok(0);
+        }
+        let inflater = match &mut self.inflater {
+            Some(inflater) => inflater,
+            None => {
+                let inflater = read_header(&mut self.reader, self.dictionary.as_deref())?;
+                self.dictionary = None;
+                self.inflater.insert(inflater)
+            }
+        };
+
+        loop {
+            let output = inflater.output();
+            if !output.is_empty() {
+                let n = output.len().min(buf.len());
+                self.limits.check_output(self.reader.byte_offset() as u64, self.total + n as u64)?;
+                self.total += n as u64;
+                buf[..n].copy_from_slice(&output[..n]);
+                self.adler.update(&buf[..n]);
+                inflater.consume(n);
+                return Ok(n);
+            }
+            if inflater.is_done() {
+                read_trailer(&mut self.reader, self.adler.value())?;
+                self.done = true;
+                return Ok(0);
+            }
+            inflater
+                .fill(&mut self.reader, buf.len())
+                .map_err(|err| inflate_error(&mut self.reader, err))?;
+        }
+    }
+}
+
+impl<R: Read> Read for ZlibDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        self.read_decoded(buf).map_err(io::Error::from)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::HeaderError;
+
+    const SAMPLE: &[u8] = include_bytes!("../tests/fixtures/sample.txt");
+
+    #[test]
+    fn test_decodes_zlib_output() {
+        // zlib.compress(b"hello hello hello hello\n")
+        let stream = [
+            0x78, 0x9c, 0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0x57, 0xc8, 0x40, 0x27, 0xb9, 0x00,
+            0x70, 0xbe, 0x08, 0xbb,
+        ];
+        assert_eq!(zlib_decompress(&stream).unwrap(), b"hello hello hello hello\n");
+    }
+
+    #[test]
+    fn test_header_bytes_match_zlib() {
+        assert_eq!(zlib_compress_with_level(b"", CompressionLevel::FASTEST)[..2], [0x78, 0x01]);
+        assert_eq!(zlib_compress(b"")[..2], [0x78, 0x9c]);
+        assert_eq!(zlib_compress_with_level(b"", CompressionLevel::BEST)[..2], [0x78, 0xda]);
+        // Empty input: header, an empty final block, Adler-32 of nothing
+        assert_eq!(zlib_compress(b""), [0x78, 0x9c, 0x03, 0x00, 0x00, 0x00, 0x00, 0x01]);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for level in 0..=9 {
+            let level = CompressionLevel::new(level).unwrap();
+            assert_eq!(zlib_decompress(&zlib_compress_with_level(SAMPLE, level)).unwrap(), SAMPLE);
+        }
+    }
+
+    #[test]
+    fn test_preset_dictionary() {
+        let dictionary = &SAMPLE[..4096];
+        let data = &SAMPLE[..2048];
+
+        let plain = zlib_compress(data);
+        let primed = zlib_compress_with_dictionary(data, CompressionLevel::DEFAULT, dictionary);
+        assert_eq!(primed[1] & FDICT, FDICT);
+        assert_eq!(primed[2..6], adler32(dictionary).to_be_bytes());
+        assert!(primed.len() < plain.len() / 4, "{} vs {}", primed.len(), plain.len());
+
+        assert_eq!(zlib_decompress_with_dictionary(&primed, dictionary).unwrap(), data);
+        assert_eq!(
+            zlib_decompress(&primed),
+            Err(GzipError::InvalidHeader { offset: 2, reason: HeaderError::DictionaryRequired(adler32(dictionary)) })
+        );
+        assert!(matches!(
+            zlib_decompress_with_dictionary(&primed, b"wrong"),
+            Err(GzipError::InvalidHeader { offset: 2, reason: HeaderError::DictionaryMismatch { .. } })
+        ));
+        // A dictionary offered for a stream without FDICT is not needed
+        assert_eq!(zlib_decompress_with_dictionary(&plain, dictionary).unwrap(), data);
+    }
+
+    #[test]
+    fn test_rejects_bad_headers() {
+        assert_eq!(zlib_decompress(&[0x78]), Err(GzipError::TruncatedStream { offset: 1 }));
+        assert_eq!(
+            zlib_decompress(&[0x78, 0x9d, 0x03, 0x00]),
+            Err(GzipError::InvalidHeader { offset: 0, reason: HeaderError::HeaderCheck })
+        );
+        // CM = 15 with a valid FCHECK
+        assert_eq!(
+            zlib_decompress(&[0x7f, 0x07]),
+            Err(GzipError::InvalidHeader { offset: 0, reason: HeaderError::UnsupportedMethod(15) })
+        );
+        // CINFO = 8 asks for a 64 KiB window
+        assert_eq!(
+            zlib_decompress(&[0x88, 0x1c]),
+            Err(GzipError::InvalidHeader { offset: 0, reason: HeaderError::InvalidWindowSize(16) })
+        );
+    }
+
+    #[test]
+    fn test_detects_corrupt_checksum() {
+        let mut stream = zlib_compress(SAMPLE);
+        let last = stream.len() - 1;
+        stream[last] ^= 1;
+
+        let expected = u32::from_be_bytes(stream[last - 3..].try_into().unwrap());
+        assert_eq!(
+            zlib_decompress(&stream),
+            Err(GzipError::ChecksumMismatch { offset: last - 3, expected, actual: adler32(SAMPLE) })
+        );
+    }
+
+    #[test]
+    fn test_streaming_round_trip() {
+        let dictionary = &SAMPLE[SAMPLE.len() - 1000..];
+        let mut encoder = ZlibEncoder::with_dictionary(Vec::new(), CompressionLevel::DEFAULT, dictionary);
+        for piece in SAMPLE.chunks(1000) {
+            encoder.write_all(piece).unwrap();
+        }
+        let stream = encoder.finish().unwrap();
+        assert_eq!(zlib_decompress_with_dictionary(&stream, dictionary).unwrap(), SAMPLE);
+
+        let mut decoder = ZlibDecoder::with_dictionary(&stream[..], dictionary);
+        let mut restored = Vec::new();
+        decoder.read_to_end(&mut restored).unwrap();
+        assert_eq!(restored, SAMPLE);
+
+        let mut decoder = ZlibDecoder::new(&stream[..]);
+        let err = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn test_limits() {
+        let bomb = zlib_compress(&vec![0u8; 1_000_000]);
+        let limits = Limits { max_output: Some(100_000), ..Limits::default() };
+        assert_eq!(
+            zlib_decompress_with_limits(&bomb, &limits),
+            Err(GzipError::LimitExceeded(LimitError::OutputTooLarge { limit: 100_000 }))
+        );
+        let limits = Limits { max_ratio: Some(50), ..Limits::default() };
+        assert!(matches!(
+            zlib_decompress_with_limits(&bomb, &limits),
+            Err(GzipError::LimitExceeded(LimitError::RatioTooHigh { limit: 50, .. }))
+        ));
+        assert_eq!(zlib_decompress_with_limits(&zlib_compress(SAMPLE), &limits).unwrap(), SAMPLE);
+
+        let mut decoded = Vec::new();
+        let err = ZlibDecoder::with_limits(&bomb[..], Limits { max_output: Some(100_000), ..Limits::default() })
+            .read_to_end(&mut decoded)
+            .unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(decoded.len() <= 100_000);
+    }
+}
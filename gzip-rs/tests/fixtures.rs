@@ -0,0 +1,221 @@
+//! Differential tests against the system gzip. Every `.gz` file under
+//! `fixtures/corpus` was written by GNU gzip (see `generate.sh`) and must
+//! decode to exactly the input it was made from; in the other direction,
+//! whatever this crate writes must decode with the system gzip when one is
+//! installed. Archives are checked against the system tar the same way.
+
+use std::fs;
+use std::io::{Cursor, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+use gzip::analyze::analyze;
+use gzip::index::{GzIndex, IndexedReader};
+use gzip::tar::{TarReader, TarWriter};
+use gzip::{gunzip, gzip_ultra, gzip_with_level, CompressionLevel, GzDecoder, GzEncoder};
+
+fn corpus() -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/corpus")
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|err| panic!("{}: {}", path.display(), err))
+}
+
+/// The input a corpus file was made from: `words.txt.9.gz` holds
+/// `words.txt`, and `multi.members.gz` three inputs back to back
+fn expected(name: &str) -> Vec<u8> {
+    if name == "multi.members.gz" {
+        return ["one.bin", "random.bin", "words.txt"].iter().flat_map(|input| read(&corpus().join(input))).collect();
+    }
+    let stem = name.strip_suffix(".gz").unwrap();
+    let input = &stem[..stem.rfind('.').unwrap()];
+    if input == "sample.txt" {
+        return read(&corpus().join("../sample.txt"));
+    }
+    read(&corpus().join(input))
+}
+
+fn gz_fixtures() -> Vec<(String, Vec<u8>)> {
+    let mut files: Vec<(String, Vec<u8>)> = fs::read_dir(corpus())
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "gz"))
+        .map(|path| (path.file_name().unwrap().to_string_lossy().into_owned(), read(&path)))
+        .collect();
+    files.sort();
+    assert!(!files.is_empty(), "corpus is missing; run tests/fixtures/corpus/generate.sh");
+    files
+}
+
+#[test]
+fn test_decodes_system_gzip_corpus() {
+    for (name, compressed) in gz_fixtures() {
+        let expected = expected(&name);
+        assert!(gunzip(&compressed).unwrap() == expected, "{}", name);
+
+        // The streaming decoder, fed a few bytes at a time
+        let mut decoder = GzDecoder::new(Trickle(&compressed[..]));
+        let mut streamed = Vec::new();
+        decoder.read_to_end(&mut streamed).unwrap();
+        assert!(streamed == expected, "{} (streaming)", name);
+
+        let members = analyze(&compressed, false).unwrap();
+        assert_eq!(members.iter().map(|member| member.output_len()).sum::<usize>(), expected.len(), "{}", name);
+    }
+}
+
+#[test]
+fn test_rejects_damaged_corpus() {
+    for (name, compressed) in gz_fixtures() {
+        // Any change to the trailer is caught
+        let mut damaged = compressed.clone();
+        let len = damaged.len();
+        damaged[len - 5] ^= 0x40;
+        assert!(gunzip(&damaged).is_err(), "{}", name);
+
+        // and so is losing the end of the file
+        assert!(gunzip(&compressed[..len - 1]).is_err(), "{}", name);
+    }
+}
+
+#[test]
+fn test_indexes_system_gzip_corpus() {
+    for (name, compressed) in gz_fixtures() {
+        let expected = expected(&name);
+        let index = GzIndex::build(&compressed[..], 4096).unwrap();
+        let index = GzIndex::deserialize(&index.serialize()).unwrap();
+        let mut reader = IndexedReader::new(Cursor::new(&compressed), index).unwrap();
+
+        // Every 1000th byte and the 100 after it, back to front
+        for offset in (0..expected.len()).step_by(1000).rev() {
+            let mut out = Vec::new();
+            reader.seek(SeekFrom::Start(offset as u64)).unwrap();
+            (&mut reader).take(100).read_to_end(&mut out).unwrap();
+            assert!(out == expected[offset..(offset + 100).min(expected.len())], "{} at {}", name, offset);
+        }
+    }
+}
+
+/// A reader returning at most 5 bytes per call, to exercise every place a
+/// streaming decoder can run out of input
+struct Trickle<'a>(&'a [u8]);
+
+impl Read for Trickle<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = buf.len().min(5).min(self.0.len());
+        buf[..n].copy_from_slice(&self.0[..n]);
+        self.0 = &self.0[n..];
+        Ok(n)
+    }
+}
+
+/// Decompress with the system gzip, or `None` if it is not installed
+fn system_gunzip(compressed: &[u8]) -> Option<Vec<u8>> {
+    let mut child = Command::new("gzip")
+        .args(["-d", "-c"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .ok()?;
+    let mut stdin = child.stdin.take().unwrap();
+    let input = compressed.to_vec();
+    let writer = std::thread::spawn(move || stdin.write_all(&input));
+    let output = child.wait_with_output().unwrap();
+    writer.join().unwrap().unwrap();
+    assert!(output.status.success(), "gzip -d failed: {}", String::from_utf8_lossy(&output.stderr));
+    Some(output.stdout)
+}
+
+#[test]
+fn test_system_gzip_decodes_our_output() {
+    if system_gunzip(&gzip_with_level(b"probe", CompressionLevel::DEFAULT)).is_none() {
+        eprintln!("system gzip not found; skipping");
+        return;
+    }
+
+    for input in ["one.bin", "random.bin", "runs.bin", "words.txt"] {
+        let data = read(&corpus().join(input));
+        for level in 0..=9 {
+            let compressed = gzip_with_level(&data, CompressionLevel::new(level).unwrap());
+            assert!(system_gunzip(&compressed).unwrap() == data, "{} at level {}", input, level);
+        }
+        assert!(system_gunzip(&gzip_ultra(&data, 3)).unwrap() == data, "{} ultra", input);
+
+        // Streaming output with a sync flush in the middle
+        let mut encoder = GzEncoder::new(Vec::new(), CompressionLevel::DEFAULT);
+        encoder.write_all(&data[..data.len() / 2]).unwrap();
+        encoder.flush().unwrap();
+        encoder.write_all(&data[data.len() / 2..]).unwrap();
+        assert!(system_gunzip(&encoder.finish().unwrap()).unwrap() == data, "{} streamed", input);
+    }
+}
+
+/// A scratch directory holding a small tree: nested directories, a long
+/// path that needs the ustar prefix or a pax header, and a symlink
+fn tar_tree(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("gzip-fixtures-{}-{}", std::process::id(), name));
+    let _ = fs::remove_dir_all(&dir);
+    let deep = dir.join("tree/nested").join("d".repeat(90)).join("e".repeat(90));
+    fs::create_dir_all(&deep).unwrap();
+    fs::write(deep.join("f".repeat(80)), read(&corpus().join("words.txt"))).unwrap();
+    fs::write(dir.join("tree/random.bin"), read(&corpus().join("random.bin"))).unwrap();
+    #[cfg(unix)]
+    std::os::unix::fs::symlink("random.bin", dir.join("tree/link")).unwrap();
+    dir
+}
+
+/// Whether two trees hold the same files, directories and symlinks
+fn same_tree(a: &Path, b: &Path) -> bool {
+    let (meta_a, meta_b) = (fs::symlink_metadata(a).unwrap(), fs::symlink_metadata(b).unwrap());
+    if meta_a.file_type().is_symlink() {
+        return meta_b.file_type().is_symlink() && fs::read_link(a).unwrap() == fs::read_link(b).unwrap();
+    }
+    if meta_a.is_file() {
+        return meta_b.is_file() && read(a) == read(b);
+    }
+    let names = |dir: &Path| {
+        let mut names: Vec<_> = fs::read_dir(dir).unwrap().map(|entry| entry.unwrap().file_name()).collect();
+        names.sort();
+        names
+    };
+    meta_b.is_dir() && names(a) == names(b) && names(a).iter().all(|name| same_tree(&a.join(name), &b.join(name)))
+}
+
+#[test]
+fn test_tar_interoperates_with_system_tar() {
+    if Command::new("tar").arg("--version").output().is_err() {
+        eprintln!("system tar not found; skipping");
+        return;
+    }
+    let dir = tar_tree("tar");
+
+    // Archives written by tar in its pax and GNU formats unpack here
+    for format in ["pax", "gnu"] {
+        let archive = dir.join(format!("{}.tar.gz", format));
+        let status = Command::new("tar")
+            .args([&format!("--format={}", format), "-czf"])
+            .arg(&archive)
+            .args(["-C", dir.to_str().unwrap(), "tree"])
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let dest = dir.join(format!("from-{}", format));
+        TarReader::new(GzDecoder::new(&read(&archive)[..])).unpack(&dest, |_| {}).unwrap();
+        assert!(same_tree(&dir.join("tree"), &dest.join("tree")), "{}", format);
+    }
+
+    // and ours unpack with tar
+    let mut writer = TarWriter::new(GzEncoder::new(Vec::new(), CompressionLevel::DEFAULT));
+    writer.append_path(&dir.join("tree"), b"tree", &mut |_| {}).unwrap();
+    let archive = dir.join("ours.tar.gz");
+    fs::write(&archive, writer.finish().unwrap().finish().unwrap()).unwrap();
+    let dest = dir.join("from-ours");
+    fs::create_dir(&dest).unwrap();
+    let status = Command::new("tar").arg("-xzf").arg(&archive).arg("-C").arg(&dest).status().unwrap();
+    assert!(status.success());
+    assert!(same_tree(&dir.join("tree"), &dest.join("tree")));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,33 @@
+#!/bin/sh
+# Regenerate the differential-testing corpus with the system gzip.
+# The inputs are deterministic, so only the .gz files depend on the gzip
+# version; tests/fixtures.rs checks that each one decodes to its input.
+set -e
+cd "$(dirname "$0")"
+
+python3 - <<'PY'
+import random
+rng = random.Random(1952)
+# Incompressible: gzip falls back to stored blocks
+open("random.bin", "wb").write(bytes(rng.randrange(256) for _ in range(12000)))
+# Long runs and short repeats: maximum-length matches and distance 1
+runs = b"".join(bytes([rng.randrange(4)]) * rng.randrange(1, 2000) for _ in range(30))
+open("runs.bin", "wb").write(runs + b"abc" * 1000)
+# Skewed text over a small alphabet
+words = [b"deflate", b"inflate", b"huffman", b"window", b"match", b"literal", b"block"]
+text = b" ".join(rng.choice(words) for _ in range(3000))
+open("words.txt", "wb").write(text + b"\n")
+open("one.bin", "wb").write(b"x")
+PY
+
+for input in random.bin runs.bin words.txt one.bin; do
+    for level in 1 6 9; do
+        gzip -"$level" -n -c "$input" > "$input.$level.gz"
+    done
+done
+
+gzip -c words.txt > words.txt.named.gz
+gzip --rsyncable -n -c words.txt > words.txt.rsyncable.gz
+gzip -n -c ../sample.txt > sample.txt.6.gz
+gzip -9 -n -c ../sample.txt > sample.txt.9.gz
+cat one.bin.6.gz random.bin.1.gz words.txt.9.gz > multi.members.gz
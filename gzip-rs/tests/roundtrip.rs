@@ -0,0 +1,184 @@
+//! Property tests: every encoder round-trips arbitrary and adversarial
+//! input, and every decoder survives arbitrary and damaged input without
+//! panicking. Failing cases are shrunk and saved under
+//! `proptest-regressions/` so they are replayed on later runs.
+
+use std::io::{Read, Write};
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+
+use gzip::analyze::analyze;
+use gzip::entropy::{EntropyCoder, HuffmanCoder, Lz77Codec, RangeCoder, StaticHuffmanCoder};
+use gzip::zlib::{zlib_compress_with_dictionary, zlib_compress_with_level, zlib_decompress_with_dictionary};
+use gzip::{
+    deflate_ultra, deflate_with_level, gunzip, gunzip_with_limits, gzip_parallel, gzip_with_level, inflate,
+    zlib_decompress, zlib_decompress_with_limits, CompressionLevel, GzDecoder, GzEncoder, HuffmanCode, Limits,
+};
+
+/// Inputs that stress different parts of the compressor: random bytes
+/// (stored blocks), long runs (maximum-length matches at distance 1),
+/// a repeated pattern with a few edits (long matches that break off),
+/// and a tiny alphabet (short matches everywhere, skewed codes)
+fn data() -> impl Strategy<Value = Vec<u8>> {
+    prop_oneof![
+        vec(any::<u8>(), 0..4096),
+        vec((any::<u8>(), 1..700usize), 0..16)
+            .prop_map(|runs| runs.into_iter().flat_map(|(byte, len)| std::iter::repeat_n(byte, len)).collect()),
+        (vec(any::<u8>(), 1..40), 1..200usize, vec((any::<usize>(), any::<u8>()), 0..10)).prop_map(
+            |(pattern, times, edits)| {
+                let mut data = pattern.repeat(times);
+                let len = data.len();
+                for (at, byte) in edits {
+                    data[at % len] = byte;
+                }
+                data
+            }
+        ),
+        vec(0..4u8, 0..8192),
+    ]
+}
+
+fn level() -> impl Strategy<Value = CompressionLevel> {
+    (0..=9u8).prop_map(|level| CompressionLevel::new(level).unwrap())
+}
+
+/// Output cap for decoding untrusted input in these tests
+const LIMITS: Limits = Limits { max_output: Some(1 << 22), max_ratio: None, max_members: Some(16) };
+
+proptest! {
+    #![proptest_config(ProptestConfig::with_cases(128))]
+
+    #[test]
+    fn gzip_round_trips(data in data(), level in level()) {
+        prop_assert_eq!(gunzip(&gzip_with_level(&data, level)).unwrap(), data);
+    }
+
+    #[test]
+    fn deflate_and_zlib_round_trip(data in data(), level in level()) {
+        prop_assert_eq!(inflate(&deflate_with_level(&data, level)).unwrap(), &data[..]);
+        prop_assert_eq!(zlib_decompress(&zlib_compress_with_level(&data, level)).unwrap(), data);
+    }
+
+    #[test]
+    fn dictionary_round_trips(dictionary in data(), data in data(), level in level()) {
+        let compressed = zlib_compress_with_dictionary(&data, level, &dictionary);
+        prop_assert_eq!(zlib_decompress_with_dictionary(&compressed, &dictionary).unwrap(), data);
+    }
+
+    #[test]
+    fn streaming_round_trips(
+        data in data(),
+        level in level(),
+        cuts in vec((1..2000usize, any::<bool>()), 1..8),
+        read_size in 1..600usize,
+    ) {
+        // Write in uneven pieces, sometimes flushing between them
+        let mut encoder = GzEncoder::new(Vec::new(), level);
+        let mut rest = &data[..];
+        for (len, flush) in cuts.iter().cycle().take(64) {
+            let (piece, tail) = rest.split_at((*len).min(rest.len()));
+            encoder.write_all(piece).unwrap();
+            if *flush {
+                encoder.flush().unwrap();
+            }
+            rest = tail;
+        }
+        encoder.write_all(rest).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        // and read back in pieces of another size
+        let mut decoder = GzDecoder::new(&compressed[..]);
+        let mut out = Vec::new();
+        let mut buf = vec![0; read_size];
+        loop {
+            let n = decoder.read(&mut buf).unwrap();
+            if n == 0 {
+                break;
+            }
+            out.extend_from_slice(&buf[..n]);
+        }
+        prop_assert_eq!(out, data);
+    }
+
+    #[test]
+    fn parallel_round_trips(data in data(), level in level(), threads in 1..4usize) {
+        prop_assert_eq!(gunzip(&gzip_parallel(&data, level, threads)).unwrap(), data);
+    }
+
+    #[test]
+    fn entropy_coders_round_trip(data in data()) {
+        let coders: [&dyn EntropyCoder; 3] = [&HuffmanCoder, &StaticHuffmanCoder, &RangeCoder];
+        let tokens = Lz77Codec::new(HuffmanCoder).parse(&data);
+        for coder in coders {
+            prop_assert_eq!(&coder.decode(&coder.encode(&tokens)).unwrap(), &tokens, "{}", coder.name());
+        }
+    }
+
+    #[test]
+    fn huffman_code_round_trips(symbols in vec(0..3000u16, 0..2000), max_bits in 12..=15u8) {
+        let code = HuffmanCode::from_data(&symbols, max_bits).unwrap();
+        let mut packed = code.serialize();
+        let table_len = packed.len();
+        packed.extend(code.encode(&symbols).unwrap());
+
+        let (read, used) = HuffmanCode::<u16>::deserialize(&packed).unwrap();
+        prop_assert_eq!(used, table_len);
+        prop_assert_eq!(read.decode(&packed[used..], symbols.len()).unwrap(), symbols);
+        prop_assert!(code.lengths().all(|(_, len)| len <= max_bits));
+    }
+
+    #[test]
+    fn decoders_survive_arbitrary_input(bytes in vec(any::<u8>(), 0..2048)) {
+        let _ = inflate(&bytes);
+        let _ = gunzip_with_limits(&bytes, &LIMITS);
+        let _ = zlib_decompress_with_limits(&bytes, &LIMITS);
+        let _ = analyze(&bytes, true);
+        let _ = RangeCoder.decode(&bytes);
+        let _ = HuffmanCode::<u16>::deserialize(&bytes);
+
+        // The same bytes behind the gzip magic, so the decoders get past the header
+        let mut member = vec![0x1f, 0x8b, 8, 0, 0, 0, 0, 0, 0, 255];
+        member.extend(&bytes);
+        let _ = gunzip_with_limits(&member, &LIMITS);
+        let _ = GzDecoder::with_limits(&member[..], LIMITS).read_to_end(&mut Vec::new());
+    }
+
+    #[test]
+    fn decoders_catch_damage(
+        data in data(),
+        level in level(),
+        flips in vec((any::<usize>(), 1..=255u8), 1..4),
+        cut in any::<usize>(),
+    ) {
+        let original = gzip_with_level(&data, level);
+        let mut damaged = original.clone();
+        let len = damaged.len();
+        for (at, mask) in flips {
+            damaged[at % len] ^= mask;
+        }
+
+        // Damage either goes unnoticed (a header byte like MTIME) and the
+        // data comes out intact, or it is reported; it never corrupts output
+        if let Ok(out) = gunzip_with_limits(&damaged, &LIMITS) {
+            prop_assert_eq!(out, &data[..]);
+        }
+        let mut streamed = Vec::new();
+        if GzDecoder::with_limits(&damaged[..], LIMITS).read_to_end(&mut streamed).is_ok() {
+            prop_assert_eq!(streamed, &data[..]);
+        }
+
+        // Truncation anywhere is always an error
+        prop_assert!(gunzip(&original[..cut % len]).is_err());
+    }
+}
+
+proptest! {
+    // Optimal parsing is slow in debug builds; fewer, smaller cases
+    #![proptest_config(ProptestConfig::with_cases(24))]
+
+    #[test]
+    fn ultra_round_trips(data in data().prop_map(|mut data| { data.truncate(1500); data }), iterations in 1..3usize) {
+        prop_assert_eq!(inflate(&deflate_ultra(&data, iterations)).unwrap(), data);
+    }
+}
@@ -1,116 +1,14 @@
-#[derive(Debug, Clone, PartialEq)]
-enum Token {
-    Literal(char),
-    Dot,
-    Star(Box<Token>),
-    Plus(Box<Token>),
-    Question(Box<Token>),
-    StartAnchor,
-    EndAnchor,
-}
-
-fn parse_pattern(pattern: &str) -> Vec<Token> {
-    let chars = pattern.chars().collect::<Vec<char>>();
-    let mut tokens = Vec::new();
-    let mut i = 0;
-
-    while i < chars.len() {
-        match chars[i] {
-            '.' => tokens.push(Token::Dot),
-            '^' => tokens.push(Token::StartAnchor),
-            '$' => tokens.push(Token::EndAnchor),
-            '*' if !tokens.is_empty() => {
-                let prev = tokens.pop().unwrap();
-                tokens.push(Token::Star(Box::new(prev)));
-            },
-            '+' if !tokens.is_empty() => {
-                let prev = tokens.pop().unwrap();
-                tokens.push(Token::Plus(Box::new(prev)));
-            },
-            '?' if !tokens.is_empty() => {
-                let prev = tokens.pop().unwrap();
-                tokens.push(Token::Question(Box::new(prev)));
-            },
-            c => tokens.push(Token::Literal(c)),
-        }
-        i += 1;
-    }
-
-    tokens
-}
-
-fn match_here(tokens: &[Token], text: &[char], pos: usize) -> bool {
-    if tokens.is_empty() {
-        return true;
-    }
+mod nfa;
+mod parser;
 
-    if let Token::EndAnchor = tokens[0] {
-        return tokens.len() == 1 && pos == text.len();
-    }
-
-    match &tokens[0] {
-        Token::Star(inner) => {
-            if match_here(&tokens[1..], text, pos) {
-                return true;
-            }
+use std::time::Instant;
 
-            if pos < text.len() && match_token(inner.as_ref(), text[pos]) {
-                return match_here(tokens, text, pos + 1);
-            }
-            false
-        }
-        Token::Plus(inner) => {
-            if pos < text.len() && match_token(inner, text[pos]) {
-                let star_token = Token::Star(inner.clone());
-                return match_here(&[star_token], text, pos + 1) &&
-                    match_here(&tokens[1..], text, pos + 1);
-            }
-            false
-        }
-        Token::Question(inner) => {
-            if pos < text.len() && match_token(inner, text[pos]) {
-                if match_here(&tokens[1..], text, pos + 1) {
-                    return true;
-                }
-            }
-
-            match_here(&tokens[1..], text, pos)
-        }
-        token => {
-            if pos >= text.len() {
-                return false;
-            }
-            if match_token(token, text[pos]) {
-                return match_here(&tokens[1..], text, pos + 1);
-            }
-            false
-        }
-    }
-}
+use nfa::compile;
+use parser::{parse, ParseError};
 
-fn match_token(token: &Token, c: char) -> bool {
-    match token {
-        Token::Literal(ch) => *ch == c,
-        Token::Dot => true,
-        _ => false,
-    }
-}
-
-fn regex_match(pattern: &str, text: &str) -> bool {
-    let tokens = parse_pattern(pattern);
-    let text_chars = text.chars().collect::<Vec<char>>();
-
-    if let Some(Token::StartAnchor) = tokens.first() {
-        return match_here(&tokens[1..], &text_chars, 0);
-    }
-
-    for start_pos in 0..=text_chars.len() {
-        if match_here(&tokens, &text_chars, start_pos) {
-            return true
-        }
-    }
-
-    false
+/// Whether `pattern` matches anywhere in `text`
+fn regex_match(pattern: &str, text: &str) -> Result<bool, ParseError> {
+    Ok(compile(&parse(pattern)?).is_match(text))
 }
 
 fn main() {
@@ -140,16 +38,102 @@ fn main() {
         ("a?b", "ab", true),
         ("^h.*o$", "hello", true),
         ("^h.*o$", "hi there yo", true),
+        ("^a+$", "aaa", true),
+        ("[abc]", "xbz", true),
+        ("[abc]", "xyz", false),
+        ("^[a-z0-9]+$", "abc123", true),
+        ("^[a-z0-9]+$", "abc-123", false),
+        ("[^0-9]", "123", false),
+        ("[^0-9]", "12a", true),
+        ("[]x]", "]", true),
+        ("[a-]", "-", true),
+        ("a\\.c", "a.c", true),
+        ("a\\.c", "abc", false),
+        ("a\\*", "a*", true),
+        ("\\\\", "back\\slash", true),
+        ("^\\d+$", "2024", true),
+        ("^\\d+$", "20x4", false),
+        ("^\\w+$", "snake_case9", true),
+        ("\\s", "no-spaces", false),
+        ("\\s", "tab\there", true),
+        ("^\\D\\W\\S$", "a-b", true),
+        ("^[\\d_]+$", "1_2", true),
+        ("^[\\D]$", "7", false),
+        ("^[\\]\\-]+$", "]-]", true),
+        ("cat|dog", "hotdog", true),
+        ("cat|dog", "cow", false),
+        ("^(ab)+$", "ababab", true),
+        ("^(ab)+$", "ababa", false),
+        ("^(?:cat|dog)s?$", "dogs", true),
+        ("^(?:cat|dog)s?$", "cats!", false),
+        ("^a(b|c)*d$", "abcbcd", true),
+        ("^(a|ab)(c|bcd)$", "abcd", true),
+        ("^(a*)*b$", "aaab", true),
+        ("^(a*)*b$", "aaa", false),
+        ("^(|x)y$", "y", true),
+        ("^((a)|b)+$", "abba", true),
+        ("x(^a)", "xa", false),
     ];
 
     for (pattern, text, expected) in tests {
-        let result = regex_match(pattern, text);
-        let status = if result == expected {
-            "\x1B[32m\x1B[1mPASSED\x1B[0m"
-        } else {
-            "\x1B[31m\x1B[1mFAILED\x1B[0m"
-        };
+        let result = regex_match(pattern, text).unwrap();
+        println!("{} Pattern: {} Text: {} => {}", status(result == expected), pattern, text, result);
+    }
+
+    // Patterns that take a backtracking matcher exponential time or a
+    // stack frame per char; here they should finish in milliseconds
+    let long_a = "a".repeat(100_000);
+    let long_x = "x".repeat(100_000);
+    let stress = vec![
+        ("(a*)*b", &long_a, false),
+        ("^(a|aa)+$", &long_a, true),
+        ("(x+x+)+y", &long_x, false),
+        ("^(\\w|x)*$", &long_x, true),
+    ];
+
+    for (pattern, text, expected) in stress {
+        let started = Instant::now();
+        let result = regex_match(pattern, text).unwrap();
+        let elapsed = started.elapsed();
+        println!(
+            "{} Pattern: {} Text: {} chars => {} in {:.1?}",
+            status(result == expected && elapsed.as_secs() < 1),
+            pattern,
+            text.len(),
+            result,
+            elapsed
+        );
+    }
+
+    let errors = vec![
+        // (pattern, offset of the error)
+        ("(ab", 0),
+        ("a(b(c)", 1),
+        ("ab)", 2),
+        ("*a", 0),
+        ("a|+", 2),
+        ("(?:*)", 3),
+        ("^*", 1),
+        ("a**", 2),
+        ("[abc", 0),
+        ("a[z-a]", 2),
+        ("abc\\", 3),
+        ("\\q", 0),
+        ("(?x)", 1),
+    ];
+
+    for (pattern, offset) in errors {
+        let result = regex_match(pattern, "");
+        let passed = matches!(&result, Err(err) if err.offset == offset);
+        let result = result.map_or_else(|err| err.to_string(), |matched| matched.to_string());
+        println!("{} Pattern: {} => {}", status(passed), pattern, result);
+    }
+}
 
-        println!("{} Pattern: {} Text: {} => {}", status, pattern, text, result);
+fn status(passed: bool) -> &'static str {
+    if passed {
+        "\x1B[32m\x1B[1mPASSED\x1B[0m"
+    } else {
+        "\x1B[31m\x1B[1mFAILED\x1B[0m"
     }
 }
@@ -0,0 +1,190 @@
+use crate::parser::{Node, RepeatKind};
+
+/// One state of a Thompson NFA, laid out as a program: control falls
+/// through to the next instruction unless it says otherwise
+#[derive(Debug, Clone, PartialEq)]
+enum Inst {
+    Char(char),
+    Any,
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    /// Continue at both targets
+    Split(usize, usize),
+    Jump(usize),
+    AssertStart,
+    AssertEnd,
+    Match,
+}
+
+/// A compiled pattern. Matching runs every NFA thread in lockstep over the
+/// text (a Pike VM), so it takes O(pattern × text) time whatever the
+/// pattern, and reads the text once, front to back, without recursion.
+#[derive(Debug, Clone)]
+pub struct Program {
+    insts: Vec<Inst>,
+}
+
+pub fn compile(node: &Node) -> Program {
+    let mut insts = Vec::new();
+    emit(node, &mut insts);
+    insts.push(Inst::Match);
+    Program { insts }
+}
+
+/// Append the instructions for `node`; recursion follows the pattern's
+/// nesting, never the text
+fn emit(node: &Node, insts: &mut Vec<Inst>) {
+    match node {
+        Node::Empty => {}
+        Node::Literal(c) => insts.push(Inst::Char(*c)),
+        Node::Dot => insts.push(Inst::Any),
+        Node::Class { ranges, negated } => insts.push(Inst::Class { ranges: ranges.clone(), negated: *negated }),
+        Node::StartAnchor => insts.push(Inst::AssertStart),
+        Node::EndAnchor => insts.push(Inst::AssertEnd),
+        Node::Concat(nodes) => nodes.iter().for_each(|node| emit(node, insts)),
+        Node::Group { node, .. } => emit(node, insts),
+        Node::Alternate(branches) => {
+            // split L1, next; L1: branch; jump end; next: split L2, ...
+            let mut jumps = Vec::new();
+            for (i, branch) in branches.iter().enumerate() {
+                if i + 1 == branches.len() {
+                    emit(branch, insts);
+                    break;
+                }
+                let split = insts.len();
+                insts.push(Inst::Split(split + 1, 0));
+                emit(branch, insts);
+                jumps.push(insts.len());
+                insts.push(Inst::Jump(0));
+                insts[split] = Inst::Split(split + 1, insts.len());
+            }
+            let end = insts.len();
+            for jump in jumps {
+                insts[jump] = Inst::Jump(end);
+            }
+        }
+        Node::Repeat { node, kind } => match kind {
+            // split L1, end; L1: node; end:
+            RepeatKind::ZeroOrOne => {
+                let split = insts.len();
+                insts.push(Inst::Split(split + 1, 0));
+                emit(node, insts);
+                insts[split] = Inst::Split(split + 1, insts.len());
+            }
+            // L0: split L1, end; L1: node; jump L0; end:
+            RepeatKind::ZeroOrMore => {
+                let split = insts.len();
+                insts.push(Inst::Split(split + 1, 0));
+                emit(node, insts);
+                insts.push(Inst::Jump(split));
+                insts[split] = Inst::Split(split + 1, insts.len());
+            }
+            // L1: node; split L1, end; end:
+            RepeatKind::OneOrMore => {
+                let start = insts.len();
+                emit(node, insts);
+                insts.push(Inst::Split(start, insts.len() + 1));
+            }
+        },
+    }
+}
+
+/// The threads alive at one position: the instructions they wait on, each
+/// once. `seen` holds the step an instruction was last added in, so
+/// starting a new step clears the set in O(1).
+struct Threads {
+    pcs: Vec<usize>,
+    seen: Vec<usize>,
+    step: usize,
+}
+
+impl Threads {
+    fn new(len: usize) -> Self {
+        Threads { pcs: Vec::with_capacity(len), seen: vec![usize::MAX; len], step: 0 }
+    }
+
+    fn clear(&mut self, step: usize) {
+        self.pcs.clear();
+        self.step = step;
+    }
+
+    /// False if `pc` is already in the set
+    fn insert(&mut self, pc: usize) -> bool {
+        if self.seen[pc] == self.step {
+            return false;
+        }
+        self.seen[pc] = self.step;
+        true
+    }
+}
+
+impl Program {
+    /// Whether the pattern matches anywhere in `text`
+    pub fn is_match(&self, text: &str) -> bool {
+        let mut current = Threads::new(self.insts.len());
+        let mut next = Threads::new(self.insts.len());
+        let mut stack = Vec::new();
+        let mut chars = text.chars().peekable();
+        let mut step = 0;
+
+        current.clear(step);
+        loop {
+            let c = chars.next();
+            let at_end = c.is_none();
+            // A new thread at every position makes the search unanchored
+            self.add(&mut current, &mut stack, 0, step == 0, at_end);
+
+            step += 1;
+            next.clear(step);
+            for &pc in &current.pcs {
+                let advance = match (&self.insts[pc], c) {
+                    (Inst::Match, _) => return true,
+                    (_, None) => false,
+                    (Inst::Char(ch), Some(c)) => *ch == c,
+                    (Inst::Any, Some(_)) => true,
+                    (Inst::Class { ranges, negated }, Some(c)) => {
+                        ranges.iter().any(|&(lo, hi)| lo <= c && c <= hi) != *negated
+                    }
+                    _ => unreachable!("only waiting instructions are queued"),
+                };
+                if advance {
+                    self.add(&mut next, &mut stack, pc + 1, false, chars.peek().is_none());
+                }
+            }
+            if at_end {
+                return false;
+            }
+            std::mem::swap(&mut current, &mut next);
+        }
+    }
+
+    /// Add a thread at `pc`, following jumps, splits and assertions on an
+    /// explicit stack until it reaches instructions that wait for a char
+    /// (or `Match`). Splits push their second target first, so the first
+    /// is followed first.
+    fn add(&self, threads: &mut Threads, stack: &mut Vec<usize>, pc: usize, at_start: bool, at_end: bool) {
+        stack.push(pc);
+        while let Some(pc) = stack.pop() {
+            if !threads.insert(pc) {
+                continue;
+            }
+            match self.insts[pc] {
+                Inst::Jump(to) => stack.push(to),
+                Inst::Split(first, second) => {
+                    stack.push(second);
+                    stack.push(first);
+                }
+                Inst::AssertStart => {
+                    if at_start {
+                        stack.push(pc + 1);
+                    }
+                }
+                Inst::AssertEnd => {
+                    if at_end {
+                        stack.push(pc + 1);
+                    }
+                }
+                _ => threads.pcs.push(pc),
+            }
+        }
+    }
+}
@@ -0,0 +1,287 @@
+use std::fmt;
+
+/// A parsed pattern. Alternation binds loosest, then concatenation, then
+/// quantifiers, which apply to the single atom or group before them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    /// Matches without consuming anything, as in `()` or `a|`
+    Empty,
+    Literal(char),
+    Dot,
+    /// `[...]`, `\d` and the like: inclusive ranges, matching a char inside
+    /// one of them, or outside all of them when negated
+    Class { ranges: Vec<(char, char)>, negated: bool },
+    StartAnchor,
+    EndAnchor,
+    Concat(Vec<Node>),
+    Alternate(Vec<Node>),
+    /// `(...)`, numbered from 1 in order of the opening paren, or `(?:...)`
+    /// with no number
+    Group { index: Option<usize>, node: Box<Node> },
+    Repeat { node: Box<Node>, kind: RepeatKind },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepeatKind {
+    /// `*`
+    ZeroOrMore,
+    /// `+`
+    OneOrMore,
+    /// `?`
+    ZeroOrOne,
+}
+
+/// Why a pattern was rejected, and where: `offset` counts chars from the
+/// start of the pattern
+#[derive(Debug, Clone, PartialEq)]
+pub struct ParseError {
+    pub offset: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+    /// A `(` with no `)`; the offset is the `(`
+    UnclosedGroup,
+    /// A `)` with no `(`
+    UnmatchedParen,
+    /// A quantifier with nothing to repeat: at the start, after `(`, `|`,
+    /// an anchor or another quantifier
+    DanglingQuantifier(char),
+    /// A `[` with no `]`; the offset is the `[`
+    UnclosedClass,
+    /// A range like `z-a` whose ends are reversed
+    InvalidRange(char, char),
+    /// A `\` at the very end
+    TrailingBackslash,
+    /// A `\` before a letter or digit with no meaning
+    UnknownEscape(char),
+    /// `(?` followed by anything but `:`
+    UnknownGroupFlag,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.kind {
+            ErrorKind::UnclosedGroup => write!(f, "unclosed group")?,
+            ErrorKind::UnmatchedParen => write!(f, "unmatched ')'")?,
+            ErrorKind::DanglingQuantifier(c) => write!(f, "'{}' has nothing to repeat", c)?,
+            ErrorKind::UnclosedClass => write!(f, "unclosed character class")?,
+            ErrorKind::InvalidRange(lo, hi) => write!(f, "invalid range {}-{}", lo, hi)?,
+            ErrorKind::TrailingBackslash => write!(f, "trailing backslash")?,
+            ErrorKind::UnknownEscape(c) => write!(f, "unknown escape \\{}", c)?,
+            ErrorKind::UnknownGroupFlag => write!(f, "unknown group flag")?,
+        }
+        write!(f, " at offset {}", self.offset)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+pub fn parse(pattern: &str) -> Result<Node, ParseError> {
+    let mut parser = Parser { chars: pattern.chars().collect(), pos: 0, groups: 0 };
+    let node = parser.alternation()?;
+    match parser.peek() {
+        // Only a `)` stops a top-level alternation early
+        Some(_) => Err(parser.error(ErrorKind::UnmatchedParen)),
+        None => Ok(node),
+    }
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// Capturing groups opened so far
+    groups: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn error(&self, kind: ErrorKind) -> ParseError {
+        ParseError { offset: self.pos, kind }
+    }
+
+    /// concat ('|' concat)*
+    fn alternation(&mut self) -> Result<Node, ParseError> {
+        let mut branches = vec![self.concat()?];
+        while self.peek() == Some('|') {
+            self.pos += 1;
+            branches.push(self.concat()?);
+        }
+        Ok(if branches.len() == 1 { branches.pop().unwrap() } else { Node::Alternate(branches) })
+    }
+
+    /// (atom quantifier?)*, up to a `|`, a `)` or the end
+    fn concat(&mut self) -> Result<Node, ParseError> {
+        let mut nodes = Vec::new();
+        while let Some(c) = self.peek() {
+            match c {
+                '|' | ')' => break,
+                '*' | '+' | '?' => {
+                    let repeatable = nodes
+                        .last()
+                        .is_some_and(|node| !matches!(node, Node::StartAnchor | Node::EndAnchor | Node::Repeat { .. }));
+                    if !repeatable {
+                        return Err(self.error(ErrorKind::DanglingQuantifier(c)));
+                    }
+                    let kind = match c {
+                        '*' => RepeatKind::ZeroOrMore,
+                        '+' => RepeatKind::OneOrMore,
+                        _ => RepeatKind::ZeroOrOne,
+                    };
+                    let node = nodes.pop().unwrap();
+                    nodes.push(Node::Repeat { node: Box::new(node), kind });
+                    self.pos += 1;
+                }
+                _ => nodes.push(self.atom()?),
+            }
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn atom(&mut self) -> Result<Node, ParseError> {
+        let start = self.pos;
+        let c = self.peek().unwrap();
+        self.pos += 1;
+        match c {
+            '.' => Ok(Node::Dot),
+            '^' => Ok(Node::StartAnchor),
+            '$' => Ok(Node::EndAnchor),
+            '(' => self.group(start),
+            '[' => self.class(start),
+            '\\' => {
+                let c = self.peek().ok_or(ParseError { offset: start, kind: ErrorKind::TrailingBackslash })?;
+                self.pos += 1;
+                if let Some(class) = shorthand_class(c) {
+                    return Ok(class);
+                }
+                escaped(c).map(Node::Literal).ok_or(ParseError { offset: start, kind: ErrorKind::UnknownEscape(c) })
+            }
+            c => Ok(Node::Literal(c)),
+        }
+    }
+
+    /// The rest of a group whose `(` is at `start`
+    fn group(&mut self, start: usize) -> Result<Node, ParseError> {
+        let index = if self.peek() == Some('?') {
+            if self.chars.get(self.pos + 1) != Some(&':') {
+                return Err(self.error(ErrorKind::UnknownGroupFlag));
+            }
+            self.pos += 2;
+            None
+        } else {
+            self.groups += 1;
+            Some(self.groups)
+        };
+
+        let node = self.alternation()?;
+        if self.peek() != Some(')') {
+            return Err(ParseError { offset: start, kind: ErrorKind::UnclosedGroup });
+        }
+        self.pos += 1;
+        Ok(Node::Group { index, node: Box::new(node) })
+    }
+
+    /// The rest of a bracket class whose `[` is at `start`. A `]` first in
+    /// the body and a `-` first or last are literal.
+    fn class(&mut self, start: usize) -> Result<Node, ParseError> {
+        let unclosed = ParseError { offset: start, kind: ErrorKind::UnclosedClass };
+        let negated = self.peek() == Some('^');
+        if negated {
+            self.pos += 1;
+        }
+        let first = self.pos;
+        let mut ranges = Vec::new();
+
+        loop {
+            match self.peek().ok_or(unclosed.clone())? {
+                ']' if self.pos > first => break,
+                '\\' if let Some(Node::Class { ranges: inner, negated }) =
+                    self.chars.get(self.pos + 1).and_then(|&c| shorthand_class(c)) =>
+                {
+                    ranges.extend(if negated { complement(&inner) } else { inner });
+                    self.pos += 2;
+                }
+                _ => {
+                    let lo_at = self.pos;
+                    let lo = self.class_char(&unclosed)?;
+                    let is_range = self.peek() == Some('-') && self.chars.get(self.pos + 1).is_some_and(|&c| c != ']');
+                    if !is_range {
+                        ranges.push((lo, lo));
+                        continue;
+                    }
+                    self.pos += 1;
+                    let hi = self.class_char(&unclosed)?;
+                    if lo > hi {
+                        return Err(ParseError { offset: lo_at, kind: ErrorKind::InvalidRange(lo, hi) });
+                    }
+                    ranges.push((lo, hi));
+                }
+            }
+        }
+        self.pos += 1;
+        Ok(Node::Class { ranges, negated })
+    }
+
+    /// One char of a bracket class, escaped or not
+    fn class_char(&mut self, unclosed: &ParseError) -> Result<char, ParseError> {
+        let start = self.pos;
+        let c = self.peek().ok_or(unclosed.clone())?;
+        self.pos += 1;
+        if c != '\\' {
+            return Ok(c);
+        }
+        let c = self.peek().ok_or(unclosed.clone())?;
+        self.pos += 1;
+        escaped(c).ok_or(ParseError { offset: start, kind: ErrorKind::UnknownEscape(c) })
+    }
+}
+
+/// The char an escape like `\n` or `\.` stands for; `None` for letters and
+/// digits with no meaning, which are kept free for future escapes
+fn escaped(c: char) -> Option<char> {
+    match c {
+        'n' => Some('\n'),
+        't' => Some('\t'),
+        'r' => Some('\r'),
+        c if c.is_alphanumeric() => None,
+        c => Some(c),
+    }
+}
+
+/// `\d`, `\w` and `\s`, or their negations `\D`, `\W` and `\S`
+fn shorthand_class(c: char) -> Option<Node> {
+    let ranges = match c.to_ascii_lowercase() {
+        'd' => vec![('0', '9')],
+        'w' => vec![('0', '9'), ('A', 'Z'), ('_', '_'), ('a', 'z')],
+        's' => vec![('\t', '\r'), (' ', ' ')],
+        _ => return None,
+    };
+    Some(Node::Class { ranges, negated: c.is_ascii_uppercase() })
+}
+
+/// Every char outside `ranges`, which must be sorted and disjoint
+fn complement(ranges: &[(char, char)]) -> Vec<(char, char)> {
+    let mut out = Vec::new();
+    let mut next = Some('\0');
+    for &(lo, hi) in ranges {
+        if let Some(start) = next.filter(|&start| start < lo) {
+            out.push((start, char::from_u32(lo as u32 - 1).unwrap_or('\u{D7FF}')));
+        }
+        next = match hi {
+            '\u{D7FF}' => Some('\u{E000}'),
+            hi => char::from_u32(hi as u32 + 1),
+        };
+    }
+    if let Some(start) = next {
+        out.push((start, char::MAX));
+    }
+    out
+}